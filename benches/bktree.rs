@@ -0,0 +1,95 @@
+//! Compares brute force neighbor search against the BK-tree on synthetic PDQ hashes.
+//!
+//! Run with `cargo bench --bench bktree`.
+
+use std::time::Instant;
+
+use duplicate_image_finder::index::BkTree;
+
+const NUM_HASHES: usize = 50_000;
+const NUM_QUERIES: usize = 1_000;
+const MAX_DISTANCE: u64 = 3;
+
+/// Small xorshift generator, good enough for synthetic hashes and free of extra dependencies.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn hash(&mut self) -> [u8; 32] {
+        let mut hash = [0; 32];
+        for chunk in hash.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next().to_le_bytes());
+        }
+        hash
+    }
+
+    /// A copy of `hash` with a few random bits flipped, to simulate near-duplicates.
+    fn near(&mut self, hash: &[u8; 32]) -> [u8; 32] {
+        let mut hash = *hash;
+        for _ in 0..self.next() % (MAX_DISTANCE + 2) {
+            let bit = self.next() % 256;
+            hash[bit as usize / 8] ^= 1 << (bit % 8);
+        }
+        hash
+    }
+}
+
+fn main() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+
+    let mut hashes = Vec::with_capacity(NUM_HASHES);
+    while hashes.len() < NUM_HASHES {
+        let hash = rng.hash();
+        hashes.push(hash);
+        if rng.next().is_multiple_of(4) {
+            hashes.push(rng.near(&hash));
+        }
+    }
+    hashes.truncate(NUM_HASHES);
+
+    let queries: Vec<_> = (0..NUM_QUERIES)
+        .map(|_| hashes[rng.next() as usize % NUM_HASHES])
+        .collect();
+
+    let start = Instant::now();
+    let mut tree = BkTree::new();
+    for (i, hash) in hashes.iter().enumerate() {
+        tree.insert(*hash, i);
+    }
+    let build = start.elapsed();
+
+    let start = Instant::now();
+    let brute: usize = queries
+        .iter()
+        .map(|query| {
+            hashes
+                .iter()
+                .filter(|hash| hamming::distance(*hash, query) <= MAX_DISTANCE)
+                .count()
+        })
+        .sum();
+    let brute_time = start.elapsed();
+
+    let start = Instant::now();
+    let indexed: usize = queries
+        .iter()
+        .map(|query| tree.query_within(query, MAX_DISTANCE).len())
+        .sum();
+    let tree_time = start.elapsed();
+
+    assert_eq!(brute, indexed, "BK-tree must find the same neighbors as brute force");
+
+    println!("{NUM_HASHES} hashes, {NUM_QUERIES} queries within distance {MAX_DISTANCE}");
+    println!("brute force: {brute_time:?}");
+    println!("bk-tree:     {tree_time:?} (built in {build:?})");
+    println!(
+        "speedup:     {:.1}x",
+        brute_time.as_secs_f64() / tree_time.as_secs_f64()
+    );
+}
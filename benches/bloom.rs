@@ -0,0 +1,67 @@
+//! Measures building and probing the reference filter on synthetic 64 bit hashes, and checks
+//! that it contains every inserted hash, keeps its false positive rate near the 1% it is sized
+//! for and survives being saved and loaded again.
+//!
+//! Run with `cargo bench --bench bloom`. The filter is written to the system temp directory and
+//! removed afterwards.
+
+use std::{fs, path::PathBuf, time::Instant};
+
+use duplicate_image_finder::{bloom::BloomFilter, digest::Digest, FileData, HashOptions};
+
+const NUM_HASHES: usize = 1_000_000;
+const NUM_QUERIES: usize = 1_000_000;
+/// Highest false positive rate accepted, with some leeway over the 1% the filter is sized for.
+const MAX_FALSE_POSITIVE_RATE: f64 = 0.015;
+
+/// Small xorshift generator, good enough for synthetic hashes and free of extra dependencies.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+fn main() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let files: Vec<_> = (0..NUM_HASHES)
+        .map(|i| {
+            let mut file = FileData::from_file(PathBuf::from(format!("{i}")));
+            file.verified_hash = Some(Digest::from_u64(rng.next()));
+            file
+        })
+        .collect();
+    let options = HashOptions::default();
+
+    let start = Instant::now();
+    let filter = BloomFilter::new(&files, &options);
+    let build_time = start.elapsed();
+
+    let path = std::env::temp_dir().join(format!("duplicate-image-finder-bench-bloom-{}", std::process::id()));
+    filter.save(&path).unwrap();
+    let size = fs::metadata(&path).unwrap().len();
+    let loaded = BloomFilter::load(&path).unwrap().expect("the filter was just saved");
+    fs::remove_file(&path).unwrap();
+
+    let start = Instant::now();
+    let missing = files
+        .iter()
+        .filter(|file| !loaded.may_contain(&file.verified_hash.unwrap()))
+        .count();
+    let false_positives = (0..NUM_QUERIES)
+        .filter(|_| loaded.may_contain(&Digest::from_u64(rng.next())))
+        .count();
+    let probe_time = start.elapsed();
+    let rate = false_positives as f64 / NUM_QUERIES as f64;
+
+    assert_eq!(missing, 0, "the filter must contain every inserted hash");
+    assert!(rate <= MAX_FALSE_POSITIVE_RATE, "false positive rate {rate} is too high");
+
+    println!("{NUM_HASHES} hashes in {} KiB, {:.2}% false positives", size >> 10, rate * 100.0);
+    println!("build:  {build_time:?}");
+    println!("probe:  {probe_time:?} for {} lookups", NUM_HASHES + NUM_QUERIES);
+}
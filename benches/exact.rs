@@ -0,0 +1,94 @@
+//! Compares hashing every file against only hashing files whose size collides with another one,
+//! on a synthetic tree where most sizes are unique.
+//!
+//! Run with `cargo bench --bench exact`. The tree is written to the system temp directory and
+//! removed afterwards.
+
+use std::{fs, time::Instant};
+
+use duplicate_image_finder::{
+    collect, exact_groups, hash_files, progress::NoProgress, retain_size_collisions, CollectOptions,
+    HashOptions,
+};
+
+const NUM_FILES: usize = 5_000;
+/// Sizes are spread evenly on a log scale between these bounds, like on real photo trees.
+const MIN_SIZE: f64 = 512.0;
+const MAX_SIZE: f64 = 128.0 * 1024.0;
+/// One in this many files is a copy of an earlier one.
+const COPY_EVERY: u64 = 20;
+
+/// Small xorshift generator, good enough for synthetic files and free of extra dependencies.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn size(&mut self) -> usize {
+        let t = (self.next() % 1_000_000) as f64 / 1_000_000.0;
+        (MIN_SIZE * (MAX_SIZE / MIN_SIZE).powf(t)) as usize
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        let mut data = Vec::with_capacity(len + 8);
+        while data.len() < len {
+            data.extend_from_slice(&self.next().to_le_bytes());
+        }
+        data.truncate(len);
+        data
+    }
+}
+
+fn main() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let root = std::env::temp_dir().join(format!("duplicate-image-finder-bench-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+
+    let mut written: Vec<Vec<u8>> = Vec::with_capacity(NUM_FILES);
+    for i in 0..NUM_FILES {
+        let data = match written.len() {
+            len if len > 0 && rng.next().is_multiple_of(COPY_EVERY) => written[rng.next() as usize % len].clone(),
+            _ => {
+                let len = rng.size();
+                rng.bytes(len)
+            },
+        };
+        fs::write(root.join(format!("{i:05}.bin")), &data).unwrap();
+        written.push(data);
+    }
+    let total: usize = written.iter().map(Vec::len).sum();
+
+    let options = HashOptions::default();
+    let roots = [root.as_path()];
+
+    let start = Instant::now();
+    let files = collect(&roots, &CollectOptions::default(), &NoProgress);
+    let (data, _) = hash_files(files, &options, &NoProgress);
+    let all_hashed = data.len();
+    let all = exact_groups(&data, &options, &NoProgress).len();
+    let all_time = start.elapsed();
+
+    let start = Instant::now();
+    let files = retain_size_collisions(collect(&roots, &CollectOptions::default(), &NoProgress));
+    let (data, _) = hash_files(files, &options, &NoProgress);
+    let size_first_hashed = data.len();
+    let size_first = exact_groups(&data, &options, &NoProgress).len();
+    let size_first_time = start.elapsed();
+
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(all, size_first, "skipping unique sizes must find the same groups");
+
+    println!("{NUM_FILES} files, {} MiB, {all} groups of duplicates", total >> 20);
+    println!("hash all files:  {all_time:?} ({all_hashed} files hashed)");
+    println!("size collisions: {size_first_time:?} ({size_first_hashed} files hashed)");
+    println!(
+        "speedup:         {:.1}x",
+        all_time.as_secs_f64() / size_first_time.as_secs_f64()
+    );
+}
@@ -0,0 +1,101 @@
+//! Measures how many of the neighbors found by brute force LSH finds, and how much faster it
+//! is, for several band counts and distances on synthetic PDQ hashes.
+//!
+//! Run with `cargo bench --bench lsh`.
+
+use std::time::Instant;
+
+use duplicate_image_finder::index::{LshIndex, LshParams, NeighborIndex};
+
+const NUM_HASHES: usize = 50_000;
+const NUM_QUERIES: usize = 1_000;
+/// Near-duplicates have up to this many bits flipped.
+const MAX_FLIPPED: u64 = 32;
+
+/// Small xorshift generator, good enough for synthetic hashes and free of extra dependencies.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn hash(&mut self) -> [u8; 32] {
+        let mut hash = [0; 32];
+        for chunk in hash.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next().to_le_bytes());
+        }
+        hash
+    }
+
+    /// A copy of `hash` with a few random bits flipped, to simulate near-duplicates.
+    fn near(&mut self, hash: &[u8; 32]) -> [u8; 32] {
+        let mut hash = *hash;
+        for _ in 0..self.next() % (MAX_FLIPPED + 1) {
+            let bit = self.next() % 256;
+            hash[bit as usize / 8] ^= 1 << (bit % 8);
+        }
+        hash
+    }
+}
+
+fn main() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+
+    let mut hashes = Vec::with_capacity(NUM_HASHES);
+    while hashes.len() < NUM_HASHES {
+        let hash = rng.hash();
+        hashes.push(hash);
+        if rng.next().is_multiple_of(4) {
+            hashes.push(rng.near(&hash));
+        }
+    }
+    hashes.truncate(NUM_HASHES);
+
+    // Every query is a hash with near-duplicates, otherwise there is nothing to find.
+    let queries: Vec<_> = (0..NUM_QUERIES)
+        .map(|_| {
+            let i = rng.next() as usize % (NUM_HASHES - 1);
+            if rng.next().is_multiple_of(2) { hashes[i] } else { rng.near(&hashes[i]) }
+        })
+        .collect();
+
+    println!("{NUM_HASHES} hashes, {NUM_QUERIES} queries");
+    println!("distance  bands  recall   brute force  lsh          speedup");
+    for distance in [3, 8, 16, 31] {
+        let start = Instant::now();
+        let brute: Vec<usize> = queries
+            .iter()
+            .map(|query| hashes.iter().filter(|hash| hamming::distance(*hash, query) <= distance).count())
+            .collect();
+        let brute_time = start.elapsed();
+
+        for bands in [4, 8, 16, 32] {
+            let mut index = LshIndex::new(LshParams { bands, hash_len: 32 });
+            for (i, hash) in hashes.iter().enumerate() {
+                index.insert(*hash, i);
+            }
+
+            let start = Instant::now();
+            let found: Vec<usize> = queries.iter().map(|query| index.query_within(query, distance).len()).collect();
+            let lsh_time = start.elapsed();
+
+            assert!(found.iter().zip(&brute).all(|(found, brute)| found <= brute));
+            if bands as u64 > distance {
+                assert_eq!(found, brute, "LSH must be exact with more bands than the distance");
+            }
+
+            let recall = found.iter().sum::<usize>() as f64 / brute.iter().sum::<usize>() as f64;
+            println!(
+                "{distance:>8}  {bands:>5}  {:>5.1}%   {:<11}  {:<11}  {:.1}x",
+                recall * 100.0,
+                format!("{brute_time:.1?}"),
+                format!("{lsh_time:.1?}"),
+                brute_time.as_secs_f64() / lsh_time.as_secs_f64()
+            );
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Compares verifying candidates with all threads against verifying them with a bounded number
+//! of open files, and checks both against a serial reference that reads and compares the files
+//! one after the other. Most candidates share their prefix but differ further in.
+//!
+//! Run with `cargo bench --bench verify`. The tree is written to the system temp directory and
+//! removed afterwards.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use duplicate_image_finder::{collect, exact_groups, hash_files, progress::NoProgress, CollectOptions, HashOptions};
+
+const NUM_BUCKETS: usize = 200;
+const FILES_PER_BUCKET: usize = 20;
+/// Size of every file, the first `HashOptions::prefix_len` bytes are shared within a bucket.
+const FILE_SIZE: usize = 256 * 1024;
+/// One in this many files is a copy of an earlier one of its bucket.
+const COPY_EVERY: u64 = 4;
+const MAX_OPEN_FILES: usize = 2;
+
+/// Small xorshift generator, good enough for synthetic files and free of extra dependencies.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        let mut data = Vec::with_capacity(len + 8);
+        while data.len() < len {
+            data.extend_from_slice(&self.next().to_le_bytes());
+        }
+        data.truncate(len);
+        data
+    }
+}
+
+/// The groups as sets of paths, which don't depend on the hash algorithm or the order of files.
+type Groups = BTreeSet<BTreeSet<PathBuf>>;
+
+/// Group the files by their full contents, reading them one after the other.
+fn serial_groups(paths: &[PathBuf]) -> Groups {
+    let mut by_contents: BTreeMap<Vec<u8>, BTreeSet<PathBuf>> = BTreeMap::new();
+    for path in paths {
+        by_contents.entry(fs::read(path).unwrap()).or_default().insert(path.clone());
+    }
+    by_contents.into_values().filter(|group| group.len() > 1).collect()
+}
+
+/// Group the files below `root` like `exact` does.
+fn parallel_groups(root: &Path, options: &HashOptions) -> Groups {
+    let files = collect(&[root], &CollectOptions::default(), &NoProgress);
+    let (data, _) = hash_files(files, options, &NoProgress);
+    exact_groups(&data, options, &NoProgress)
+        .into_values()
+        .map(|group| group.into_iter().map(|file| file.path.clone()).collect())
+        .collect()
+}
+
+fn main() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let root = std::env::temp_dir().join(format!("duplicate-image-finder-bench-verify-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+
+    let prefix_len = HashOptions::default().prefix_len;
+    let mut paths = Vec::with_capacity(NUM_BUCKETS * FILES_PER_BUCKET);
+    for bucket in 0..NUM_BUCKETS {
+        let prefix = rng.bytes(prefix_len);
+        let mut written: Vec<Vec<u8>> = Vec::with_capacity(FILES_PER_BUCKET);
+        for i in 0..FILES_PER_BUCKET {
+            let data = match written.len() {
+                len if len > 0 && rng.next().is_multiple_of(COPY_EVERY) => written[rng.next() as usize % len].clone(),
+                _ => [prefix.as_slice(), &rng.bytes(FILE_SIZE - prefix_len)].concat(),
+            };
+            let path = root.join(format!("{bucket:04}-{i:03}.bin"));
+            fs::write(&path, &data).unwrap();
+            paths.push(path);
+            written.push(data);
+        }
+    }
+
+    let start = Instant::now();
+    let serial = serial_groups(&paths);
+    let serial_time = start.elapsed();
+
+    let start = Instant::now();
+    let all_threads = parallel_groups(&root, &HashOptions::default());
+    let all_threads_time = start.elapsed();
+
+    let bounded_options = HashOptions { max_open_files: MAX_OPEN_FILES, ..Default::default() };
+    let start = Instant::now();
+    let bounded = parallel_groups(&root, &bounded_options);
+    let bounded_time = start.elapsed();
+
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(all_threads, serial, "verifying with all threads must find the serial groups");
+    assert_eq!(bounded, serial, "verifying with bounded open files must find the serial groups");
+
+    println!(
+        "{} files, {} MiB, {} groups of duplicates",
+        paths.len(),
+        (paths.len() * FILE_SIZE) >> 20,
+        serial.len()
+    );
+    println!("serial reference:      {serial_time:?}");
+    println!("all threads:           {all_threads_time:?}");
+    println!("{MAX_OPEN_FILES} files at once:       {bounded_time:?}");
+}
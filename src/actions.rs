@@ -0,0 +1,281 @@
+//! Actions that reclaim space taken up by duplicates.
+
+use std::{
+    cmp::Reverse, fs, io, path::{Component, Path, PathBuf}
+};
+
+use clap::ValueEnum;
+use color_eyre::eyre::{bail, eyre, Context, Result};
+
+use crate::{inode, FileData};
+
+pub mod trash;
+
+/// Which member of a duplicate group survives an action.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum KeepPolicy {
+    /// The first file in the group
+    First,
+    /// The file with the oldest modification time
+    Oldest,
+    /// The file with the newest modification time
+    Newest,
+    /// The file with the shortest path
+    ShortestPath,
+    /// The image with the most pixels, then the largest file, then the shortest path. Meant
+    /// for groups of similar images
+    BestResolution,
+}
+
+impl KeepPolicy {
+    /// Return the index of the file in `group` that should be kept. Files without a known
+    /// modification time never win the `oldest`/`newest` policies over ones with it.
+    pub fn survivor(self, group: &[&FileData]) -> usize {
+        let candidates = group.iter().enumerate();
+
+        let chosen = match self {
+            KeepPolicy::First => Some(0),
+            KeepPolicy::Oldest => candidates
+                .min_by_key(|(_, f)| (f.modified.is_none(), f.modified))
+                .map(|(i, _)| i),
+            KeepPolicy::Newest => candidates
+                .min_by_key(|(_, f)| Reverse(f.modified))
+                .map(|(i, _)| i),
+            KeepPolicy::ShortestPath => candidates
+                .min_by_key(|(_, f)| f.path.as_os_str().len())
+                .map(|(i, _)| i),
+            KeepPolicy::BestResolution => candidates
+                .min_by_key(|(_, f)| {
+                    let pixels = f.dimensions.map(|(w, h)| w as u64 * h as u64);
+                    (Reverse(pixels), Reverse(f.size), f.path.as_os_str().len())
+                })
+                .map(|(i, _)| i),
+        };
+
+        chosen.unwrap_or(0)
+    }
+}
+
+pub fn delete(file: &FileData) -> Result<()> {
+    fs::remove_file(&file.path).wrap_err_with(|| format!("Failed to delete {}", file.path.display()))
+}
+
+/// Replace `duplicate` with a hardlink to `survivor`.
+///
+/// The link is first created under a temporary name next to `duplicate` and then renamed over
+/// it, so an interrupted run leaves either the original file or the link in place.
+pub fn hardlink(survivor: &FileData, duplicate: &FileData) -> Result<()> {
+    let tmp = temp_path(&duplicate.path);
+
+    fs::hard_link(&survivor.path, &tmp).wrap_err_with(|| {
+        format!("Failed to link {} to {}", tmp.display(), survivor.path.display())
+    })?;
+
+    fs::rename(&tmp, &duplicate.path)
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })
+        .wrap_err_with(|| format!("Failed to replace {}", duplicate.path.display()))
+}
+
+/// Replace `duplicate` with a symlink to `survivor`, see `symlink_target` for where it points.
+///
+/// Like `hardlink`, the link is created under a temporary name and then renamed over `duplicate`.
+pub fn symlink(survivor: &FileData, duplicate: &FileData, relative: bool) -> Result<()> {
+    let target = symlink_target(survivor, duplicate, relative)?;
+    let tmp = temp_path(&duplicate.path);
+    create_symlink(&target, &tmp).wrap_err_with(|| {
+        format!("Failed to link {} to {}", tmp.display(), target.display())
+    })?;
+
+    fs::rename(&tmp, &duplicate.path)
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })
+        .wrap_err_with(|| format!("Failed to replace {}", duplicate.path.display()))
+}
+
+/// The target of a symlink to `survivor` replacing `duplicate`: absolute or, if `relative`,
+/// relative to the directory of `duplicate`. Both are resolved first, so the link points at the
+/// actual file. Fails if there is no such target, e.g. because `duplicate` already is the
+/// survivor or a link to it, or the two are on different drives.
+pub fn symlink_target(survivor: &FileData, duplicate: &FileData, relative: bool) -> Result<PathBuf> {
+    let resolve = |path: &Path| path.canonicalize().wrap_err_with(|| format!("Failed to resolve {}", path.display()));
+    let target = resolve(&survivor.path)?;
+    if target == resolve(&duplicate.path)? {
+        bail!("Can't link {} to itself, it is the same file as {}", duplicate.path.display(), survivor.path.display());
+    }
+
+    if !relative {
+        return Ok(target);
+    }
+
+    let parent = duplicate.path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    relative_path(&resolve(parent)?, &target).ok_or_else(|| {
+        eyre!("Can't link {} to {} with a relative path", duplicate.path.display(), survivor.path.display())
+    })
+}
+
+/// The path leading from the directory `from` to `to`, both absolute and without `..`. `None`
+/// if they don't share their root, like paths on different Windows drives.
+fn relative_path(from: &Path, to: &Path) -> Option<PathBuf> {
+    let mut from = from.components().peekable();
+    let mut to = to.components().peekable();
+    if from.peek() != to.peek() {
+        return None;
+    }
+
+    while from.peek().is_some() && from.peek() == to.peek() {
+        from.next();
+        to.next();
+    }
+
+    Some(from.map(|_| Component::ParentDir).chain(to).collect())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn create_symlink(_target: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Where `path` ends up when moved into `target`: below `target` at its path relative to the
+/// first of `roots` that contains it, or at its full path if none does.
+pub fn quarantine_path(target: &Path, path: &Path, roots: &[PathBuf]) -> PathBuf {
+    let relative = roots
+        .iter()
+        .find_map(|root| path.strip_prefix(root).ok())
+        .unwrap_or(path);
+
+    // Only plain names, so the result can't escape `target`.
+    let names = relative.components().filter_map(|c| match c {
+        Component::Normal(name) => Some(name),
+        _ => None,
+    });
+    target.join(names.collect::<PathBuf>())
+}
+
+/// Move `file` to `destination`, creating missing directories. If `destination` already exists
+/// a counter is appended to the file name. Files are renamed if possible, and copied and then
+/// removed across devices. Returns the path the file was moved to.
+pub fn move_file(file: &FileData, destination: &Path) -> Result<PathBuf> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).wrap_err_with(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    let destination = free_path(destination);
+    move_path(&file.path, &destination)?;
+    Ok(destination)
+}
+
+/// Rename `from` to `to`, or copy it and remove the original if they are on different devices.
+pub fn move_path(from: &Path, to: &Path) -> Result<()> {
+    match fs::rename(from, to) {
+        Ok(()) => return Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {},
+        Err(err) => {
+            return Err(err).wrap_err_with(|| format!("Failed to move {} to {}", from.display(), to.display()))
+        },
+    }
+
+    fs::copy(from, to).wrap_err_with(|| format!("Failed to copy {} to {}", from.display(), to.display()))?;
+    fs::remove_file(from).wrap_err_with(|| format!("Failed to remove {}", from.display()))
+}
+
+/// Replace the hardlink or symlink `path` with an independent copy of its contents, undoing
+/// `hardlink` and `symlink`.
+pub fn break_link(path: &Path) -> Result<()> {
+    let tmp = temp_path(path);
+
+    fs::copy(path, &tmp).wrap_err_with(|| format!("Failed to copy {}", path.display()))?;
+
+    fs::rename(&tmp, path)
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })
+        .wrap_err_with(|| format!("Failed to replace {}", path.display()))
+}
+
+/// Whether `a` and `b` are links to the same file. Without inode numbers only identical paths
+/// count as the same file.
+pub fn same_file(a: &Path, b: &Path) -> Result<bool> {
+    let stat = |path: &Path| fs::metadata(path).wrap_err_with(|| format!("Failed to stat {}", path.display()));
+
+    match (inode(&stat(a)?), inode(&stat(b)?)) {
+        (Some(inode_a), Some(inode_b)) => Ok(inode_a == inode_b),
+        _ => Ok(a == b),
+    }
+}
+
+/// Whether `a` and `b` are the same file on disk, as hardlinks or through a symlink. Changing
+/// one of them then changes or removes the other as well.
+pub fn same_data(a: &FileData, b: &FileData) -> bool {
+    if a.inode.is_some() && a.inode == b.inode {
+        return true;
+    }
+
+    match (fs::canonicalize(&a.path), fs::canonicalize(&b.path)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// `path` if nothing exists there yet, otherwise the first free `name-N.ext` next to it.
+fn free_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_owned();
+    }
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+
+    (1..)
+        .map(|i| path.with_file_name(format!("{stem}-{i}{extension}")))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+/// Check whether all `files` live on the same device, which is required for hardlinking.
+#[cfg(unix)]
+pub fn same_device(files: &[&FileData]) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let mut devices = files.iter().map(|file| {
+        fs::metadata(&file.path)
+            .map(|m| m.dev())
+            .wrap_err_with(|| format!("Failed to stat {}", file.path.display()))
+    });
+
+    let Some(first) = devices.next().transpose()? else {
+        return Ok(true);
+    };
+
+    for device in devices {
+        if device? != first {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Without device ids we let `hard_link` itself fail for cross-device groups.
+#[cfg(not(unix))]
+pub fn same_device(_files: &[&FileData]) -> Result<bool> {
+    Ok(true)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".dif-tmp-{}", std::process::id()));
+    path.with_file_name(name)
+}
@@ -0,0 +1,113 @@
+//! Moving files to the trash following the FreeDesktop.org trash specification, so that file
+//! managers on Linux and other Unix desktops can restore them.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::Result;
+
+/// The home trash directory, or an error explaining why there is none.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn trash_dir() -> Result<PathBuf> {
+    use std::env;
+
+    use color_eyre::eyre::bail;
+
+    let data_home = match (env::var_os("XDG_DATA_HOME"), env::var_os("HOME")) {
+        (Some(data_home), _) if !data_home.is_empty() => PathBuf::from(data_home),
+        (_, Some(home)) if !home.is_empty() => Path::new(&home).join(".local/share"),
+        _ => bail!("No trash directory available, neither XDG_DATA_HOME nor HOME is set"),
+    };
+
+    Ok(data_home.join("Trash"))
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub fn trash_dir() -> Result<PathBuf> {
+    color_eyre::eyre::bail!("Moving files to the trash is not supported on this platform")
+}
+
+/// Move `path` to the trash, returns where it ended up.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn trash(path: &Path) -> Result<PathBuf> {
+    use std::{fs, io::{ErrorKind, Write}, os::unix::ffi::OsStrExt, time::SystemTime};
+
+    use color_eyre::eyre::Context;
+
+    let trash = trash_dir()?;
+    let (files, info) = (trash.join("files"), trash.join("info"));
+    for dir in [&files, &info] {
+        fs::create_dir_all(dir).wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+    }
+
+    let original = std::path::absolute(path).wrap_err_with(|| format!("Failed to resolve {}", path.display()))?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+        _ => (&*name, String::new()),
+    };
+
+    // Creating the info file reserves the name, as the specification requires.
+    let (trashed, info_path, mut info_file) = (0..)
+        .map(|i| match i {
+            0 => name.to_string(),
+            i => format!("{stem}-{i}{extension}"),
+        })
+        .find_map(|name| {
+            let info_path = info.join(format!("{name}.trashinfo"));
+            match fs::OpenOptions::new().write(true).create_new(true).open(&info_path) {
+                Ok(file) if !files.join(&name).exists() => Some(Ok((files.join(&name), info_path, file))),
+                Ok(_) => {
+                    let _ = fs::remove_file(&info_path);
+                    None
+                },
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => None,
+                Err(err) => Some(Err(err).wrap_err_with(|| format!("Failed to create {}", info_path.display()))),
+            }
+        })
+        .unwrap()?;
+
+    // The specification asks for local time, but getting the time zone right isn't worth a
+    // dependency for a date only shown to users.
+    let deleted = crate::format_date(SystemTime::now());
+    let contents = format!("[Trash Info]\nPath={}\nDeletionDate={deleted}\n", encode(original.as_os_str().as_bytes()));
+
+    let result = info_file
+        .write_all(contents.as_bytes())
+        .wrap_err_with(|| format!("Failed to write {}", info_path.display()))
+        .and_then(|()| super::move_path(path, &trashed));
+    if result.is_err() {
+        let _ = fs::remove_file(&info_path);
+    }
+
+    result.map(|()| trashed)
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub fn trash(_path: &Path) -> Result<PathBuf> {
+    trash_dir()
+}
+
+/// Undo `trash`, moving the file at `trashed` back to `original` and removing its info file.
+pub fn restore(trashed: &Path, original: &Path) -> Result<()> {
+    super::move_path(trashed, original)?;
+
+    if let (Some(files), Some(name)) = (trashed.parent(), trashed.file_name()) {
+        let mut info = name.to_owned();
+        info.push(".trashinfo");
+        let _ = std::fs::remove_file(files.with_file_name("info").join(info));
+    }
+
+    Ok(())
+}
+
+/// Percent-encode a path for the `Path` key of an info file.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn encode(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
@@ -0,0 +1,275 @@
+//! Minimal reader for the members of zip and tar archives, see `CollectOptions::inspect_archives`.
+//!
+//! Only what is needed to hash the members is supported: zip members that are stored or
+//! deflated, without ZIP64 or encryption, and uncompressed tar archives.
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use miniz_oxide::inflate::{self, TINFLStatus};
+
+/// A file inside an archive, located well enough to read it without listing the archive again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveMember {
+    pub archive: PathBuf,
+    /// Path of the member inside the archive.
+    pub name: String,
+    /// Uncompressed size in bytes.
+    pub size: u64,
+    location: Location,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Location {
+    /// Offset of the local file header, which is followed by the data.
+    Zip { header: u64, compressed_size: u64, deflated: bool },
+    /// Offset of the data.
+    Tar { data: u64 },
+}
+
+const ZIP_LOCAL_HEADER: u32 = 0x0403_4b50;
+const ZIP_CENTRAL_HEADER: u32 = 0x0201_4b50;
+const ZIP_END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+
+const TAR_BLOCK: u64 = 512;
+
+impl ArchiveMember {
+    /// The path reported for the member, the path of the archive and the name of the member
+    /// joined with `!`, e.g. `photos.zip!2023/beach.jpg`.
+    pub fn path(&self) -> PathBuf {
+        let mut path = self.archive.clone().into_os_string();
+        path.push("!");
+        path.push(&self.name);
+        path.into()
+    }
+
+    /// Read and decompress the contents of the member.
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        let mut file = File::open(&self.archive)?;
+        match self.location {
+            Location::Zip { header, compressed_size, deflated } => {
+                file.seek(SeekFrom::Start(header))?;
+                let mut header = [0; 30];
+                file.read_exact(&mut header)?;
+                if u32_at(&header, 0) != ZIP_LOCAL_HEADER {
+                    return Err(invalid("invalid local file header"));
+                }
+                let skip = u16_at(&header, 26) as i64 + u16_at(&header, 28) as i64;
+                file.seek(SeekFrom::Current(skip))?;
+
+                let mut data = Vec::new();
+                file.take(compressed_size).read_to_end(&mut data)?;
+                if deflated {
+                    // Never inflate past the recorded size, so that a corrupt or hostile archive
+                    // can't exhaust the memory.
+                    data = inflate::decompress_to_vec_with_limit(&data, self.size as usize).map_err(|err| {
+                        match err.status {
+                            TINFLStatus::HasMoreOutput => invalid(&format!("{} is larger than recorded", self.name)),
+                            status => invalid(&format!("failed to inflate {}: {status:?}", self.name)),
+                        }
+                    })?;
+                }
+                if data.len() as u64 != self.size {
+                    return Err(invalid(&format!("{} is truncated", self.name)));
+                }
+                Ok(data)
+            },
+            Location::Tar { data } => {
+                file.seek(SeekFrom::Start(data))?;
+                let mut contents = Vec::new();
+                file.take(self.size).read_to_end(&mut contents)?;
+                if contents.len() as u64 != self.size {
+                    return Err(invalid(&format!("{} is truncated", self.name)));
+                }
+                Ok(contents)
+            },
+        }
+    }
+}
+
+/// Whether `path` has the extension of an archive whose members can be listed.
+pub fn is_archive(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zip") || extension.eq_ignore_ascii_case("tar"))
+}
+
+/// The regular files in the archive at `path`. Directories, encrypted members and those with an
+/// unsupported compression method are left out.
+pub fn members(path: &Path) -> io::Result<Vec<ArchiveMember>> {
+    let file = File::open(path)?;
+    let is_zip = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("zip"));
+    if is_zip {
+        zip_members(path, file)
+    } else {
+        tar_members(path, file)
+    }
+}
+
+fn zip_members(path: &Path, mut file: File) -> io::Result<Vec<ArchiveMember>> {
+    // The end of central directory record is at most 22 bytes plus a 64 KiB comment from the end.
+    let len = file.metadata()?.len();
+    let tail_len = len.min(22 + u16::MAX as u64);
+    file.seek(SeekFrom::Start(len - tail_len))?;
+    let mut tail = Vec::new();
+    (&mut file).take(tail_len).read_to_end(&mut tail)?;
+
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| u32_at(&tail, i) == ZIP_END_OF_CENTRAL_DIRECTORY)
+        .ok_or_else(|| invalid("not a zip archive"))?;
+    let (count, size, offset) = (u16_at(&tail, end + 10), u32_at(&tail, end + 12), u32_at(&tail, end + 16));
+    if count == u16::MAX || offset == u32::MAX {
+        return Err(invalid("ZIP64 archives are not supported"));
+    }
+
+    file.seek(SeekFrom::Start(offset as u64))?;
+    let mut directory = Vec::new();
+    (&mut file).take(size as u64).read_to_end(&mut directory)?;
+
+    let mut members = Vec::new();
+    let mut pos = 0;
+    for _ in 0..count {
+        let header = directory.get(pos..pos + 46).ok_or_else(|| invalid("truncated central directory"))?;
+        if u32_at(header, 0) != ZIP_CENTRAL_HEADER {
+            return Err(invalid("invalid central directory"));
+        }
+        let (flags, method) = (u16_at(header, 8), u16_at(header, 10));
+        let (compressed_size, size) = (u32_at(header, 20), u32_at(header, 24));
+        let name_len = u16_at(header, 28) as usize;
+        let skip = name_len + u16_at(header, 30) as usize + u16_at(header, 32) as usize;
+        let header_offset = u32_at(header, 42);
+
+        let name = directory.get(pos + 46..pos + 46 + name_len).ok_or_else(|| invalid("truncated central directory"))?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        pos += 46 + skip;
+
+        let encrypted = flags & 1 != 0;
+        if name.ends_with('/') || encrypted || !matches!(method, 0 | 8) {
+            continue;
+        }
+
+        members.push(ArchiveMember {
+            archive: path.to_owned(),
+            name,
+            size: size as u64,
+            location: Location::Zip { header: header_offset as u64, compressed_size: compressed_size as u64, deflated: method == 8 },
+        });
+    }
+
+    Ok(members)
+}
+
+fn tar_members(path: &Path, mut file: File) -> io::Result<Vec<ArchiveMember>> {
+    let mut members = Vec::new();
+    let mut offset = 0;
+    // Set by GNU long name entries for the entry that follows them.
+    let mut long_name = None;
+    loop {
+        let mut header = [0; TAR_BLOCK as usize];
+        match file.read_exact(&mut header) {
+            Ok(()) => {},
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof && offset > 0 => break,
+            Err(err) => return Err(err),
+        }
+        // The archive ends with empty blocks.
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        if offset == 0 && &header[257..262] != b"ustar" {
+            return Err(invalid("not a tar archive"));
+        }
+
+        let size = octal(&header[124..136]).ok_or_else(|| invalid("invalid size in tar header"))?;
+        let data = offset + TAR_BLOCK;
+        let padded = size.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+
+        match header[156] {
+            b'L' => {
+                let mut name = Vec::new();
+                (&mut file).take(size).read_to_end(&mut name)?;
+                let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                long_name = Some(String::from_utf8_lossy(&name[..end]).into_owned());
+            },
+            kind => {
+                let name = long_name.take().unwrap_or_else(|| {
+                    let prefix = c_str(&header[345..500]);
+                    let name = c_str(&header[0..100]);
+                    if prefix.is_empty() { name } else { format!("{prefix}/{name}") }
+                });
+                if matches!(kind, b'0' | 0) {
+                    members.push(ArchiveMember { archive: path.to_owned(), name, size, location: Location::Tar { data } });
+                }
+            },
+        }
+
+        offset = data + padded;
+        file.seek(SeekFrom::Start(offset))?;
+    }
+
+    Ok(members)
+}
+
+/// A NUL terminated octal number from a tar header.
+fn octal(field: &[u8]) -> Option<u64> {
+    let digits = c_str(field);
+    let digits = digits.trim_matches(' ');
+    if digits.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+/// A NUL terminated string from a tar header.
+fn c_str(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A zip member whose deflated data inflates to `data`, recorded as `size` bytes long.
+    fn deflated_member(archive: &Path, data: &[u8], size: u64) -> ArchiveMember {
+        let compressed = miniz_oxide::deflate::compress_to_vec(data, 6);
+        let mut header = vec![0; 30];
+        header[..4].copy_from_slice(&ZIP_LOCAL_HEADER.to_le_bytes());
+        header.extend_from_slice(&compressed);
+        std::fs::write(archive, header).unwrap();
+
+        ArchiveMember {
+            archive: archive.to_owned(),
+            name: "member".to_owned(),
+            size,
+            location: Location::Zip { header: 0, compressed_size: compressed.len() as u64, deflated: true },
+        }
+    }
+
+    #[test]
+    fn members_are_not_inflated_past_their_recorded_size() {
+        let archive = std::env::temp_dir().join(format!("duplicate-image-finder-archive-{}.zip", std::process::id()));
+        let data = vec![0; 1 << 20];
+
+        assert_eq!(deflated_member(&archive, &data, data.len() as u64).read().unwrap(), data);
+
+        let err = deflated_member(&archive, &data, 1000).read().unwrap_err();
+        assert_eq!(err.to_string(), "member is larger than recorded");
+
+        std::fs::remove_file(&archive).unwrap();
+    }
+}
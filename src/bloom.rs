@@ -0,0 +1,161 @@
+//! Compact set of the full-file hashes of a reference, saved to disk so that later scans can
+//! tell which files are certainly not in the reference without hashing it again.
+
+use std::{fs, io::ErrorKind, path::Path};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+
+use crate::{
+    digest::{Digest, HashAlgorithm}, FileData, HashOptions
+};
+
+/// Identifies filter files, in case some other file is passed instead.
+const MAGIC: &[u8; 8] = b"DIFBLOOM";
+
+/// Bumped whenever the layout of the filter changes, older filters have to be rebuilt.
+const VERSION: u8 = 1;
+
+/// Share of the hashes that aren't in the filter that it claims to contain anyway.
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// A bloom filter over full-file hashes, see `FileData::full_hash`. It never misses a hash that
+/// was inserted, but about `FALSE_POSITIVE_RATE` of the others are reported as contained too,
+/// so files it matches still have to be compared with the reference.
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    /// Algorithm all inserted hashes were computed with.
+    pub algorithm: HashAlgorithm,
+    /// Whether the hashes of images are their pixel hashes, see `HashOptions::ignore_metadata`.
+    pub ignore_metadata: bool,
+    num_hashes: u32,
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    /// Insert the `verified_hash` of all `files`, which were hashed with `options`. Files
+    /// without one are left out.
+    pub fn new(files: &[FileData], options: &HashOptions) -> Self {
+        // The optimal size and number of hash functions for the expected number of entries.
+        let entries = files.len().max(1) as f64;
+        let num_bits = (-entries * FALSE_POSITIVE_RATE.ln() / 2f64.ln().powi(2)).ceil() as usize;
+        let num_hashes = (num_bits as f64 / entries * 2f64.ln()).round().max(1.0) as u32;
+
+        let mut filter = Self {
+            algorithm: options.algorithm,
+            ignore_metadata: options.ignore_metadata,
+            num_hashes,
+            bits: vec![0; num_bits.div_ceil(64).max(1)],
+        };
+        for hash in files.iter().filter_map(|file| file.verified_hash) {
+            filter.insert(&hash);
+        }
+        filter
+    }
+
+    pub fn insert(&mut self, hash: &Digest) {
+        for bit in self.positions(hash) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Whether `hash` might have been inserted. `false` means it certainly wasn't.
+    pub fn may_contain(&self, hash: &Digest) -> bool {
+        self.positions(hash).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Whether hashes computed with `options` can be looked up in the filter.
+    pub fn matches(&self, options: &HashOptions) -> bool {
+        self.algorithm == options.algorithm && self.ignore_metadata == options.ignore_metadata
+    }
+
+    /// The bits for `hash`, derived from two independent hashes of it, since the 64 bit
+    /// algorithms don't give enough bits for every hash function on their own.
+    fn positions(&self, hash: &Digest) -> impl Iterator<Item = usize> {
+        let bytes = hash.as_bytes();
+        let first = seahash::hash(bytes);
+        let second = seahash::hash_seeded(bytes, 1, 2, 3, 4) | 1;
+        let num_bits = self.bits.len() as u64 * 64;
+        (0..self.num_hashes as u64).map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % num_bits) as usize)
+    }
+
+    /// Load the filter from `path`. A missing file yields `None`.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).wrap_err_with(|| format!("Failed to read reference filter {}", path.display())),
+        };
+
+        Self::from_bytes(&bytes)
+            .map(Some)
+            .wrap_err_with(|| format!("Invalid reference filter {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_bytes())
+            .wrap_err_with(|| format!("Failed to write reference filter {}", path.display()))
+    }
+
+    /// The magic and version, the algorithm name prefixed by its length, whether metadata was
+    /// ignored, the number of hash functions and of bit words, then the words, little endian.
+    fn to_bytes(&self) -> Vec<u8> {
+        let name = self.algorithm.name().as_bytes();
+        let mut bytes = Vec::with_capacity(32 + name.len() + self.bits.len() * 8);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name);
+        bytes.push(self.ignore_metadata.into());
+        bytes.extend_from_slice(&self.num_hashes.to_le_bytes());
+        bytes.extend_from_slice(&(self.bits.len() as u64).to_le_bytes());
+        for word in &self.bits {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            bail!("Not a reference filter written by `--reference-bloom`");
+        }
+
+        match reader.take(1)?[0] {
+            VERSION => {},
+            version => bail!("Filter has version {version}, but only version {VERSION} is supported. Delete it to rebuild it"),
+        }
+
+        let len = reader.take(1)?[0] as usize;
+        let algorithm = std::str::from_utf8(reader.take(len)?)
+            .ok()
+            .and_then(HashAlgorithm::from_name)
+            .ok_or_else(|| eyre!("Unknown hash algorithm"))?;
+        let ignore_metadata = reader.take(1)?[0] != 0;
+        let num_hashes = u32::from_le_bytes(reader.array()?);
+        let num_words = u64::from_le_bytes(reader.array()?) as usize;
+        if num_hashes == 0 || num_words == 0 || num_words.checked_mul(8) != Some(reader.0.len()) {
+            bail!("Truncated or malformed filter");
+        }
+
+        let bits = reader.0.chunks_exact(8).map(|word| u64::from_le_bytes(word.try_into().unwrap())).collect();
+        Ok(Self { algorithm, ignore_metadata, num_hashes, bits })
+    }
+}
+
+/// Reads the fields of a filter file front to back.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            bail!("Truncated or malformed filter");
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+}
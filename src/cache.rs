@@ -0,0 +1,391 @@
+//! Persistent cache of file hashes, keyed by path and invalidated by size and mtime.
+
+use std::{
+    collections::HashMap, fs, path::{Path, PathBuf}, time::{Duration, SystemTime, UNIX_EPOCH}
+};
+
+use color_eyre::eyre::{eyre, Context, Result};
+
+use crate::{
+    digest::{Digest, HashAlgorithm}, hex, json::{self, Value}, perceptual::PerceptualAlgorithm, FileData,
+    HashOptions, PerceptualHash
+};
+
+const VERSION: u64 = 10;
+
+#[derive(Debug, Default)]
+pub struct HashCache {
+    entries: HashMap<PathBuf, Entry>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    size: usize,
+    modified: SystemTime,
+    algorithm: HashAlgorithm,
+    /// Bytes covered by `file_hash`, see `HashOptions::prefix_len`.
+    prefix_len: usize,
+    file_hash: Digest,
+    /// Whether perceptual hashing was attempted, `perception_hash` may still be `None`
+    /// for files that aren't images.
+    perceptual: bool,
+    perceptual_algorithm: PerceptualAlgorithm,
+    perception_hash: Option<PerceptualHash>,
+    dimensions: Option<(u32, u32)>,
+    decode_error: Option<String>,
+    /// Hashes of the rotated and mirrored image, `None` if they weren't computed.
+    transformed_hashes: Option<Vec<[u8; 32]>>,
+    /// Hashes of the crops of the image, `None` if they weren't computed.
+    crop_hashes: Option<Vec<[u8; 32]>>,
+    /// Hashes of the later frames of animations, `None` if they weren't computed.
+    frame_hashes: Option<Vec<[u8; 32]>>,
+    /// Whether pixel hashing was attempted, like `perceptual`.
+    pixels: bool,
+    pixel_hash: Option<Digest>,
+}
+
+impl HashCache {
+    /// Load the cache from `path`. A missing file yields an empty cache.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err).wrap_err_with(|| format!("Failed to read cache {}", path.display())),
+        };
+
+        Self::from_json(&json::parse(&text)?)
+            .wrap_err_with(|| format!("Invalid cache file {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_json().to_string())
+            .wrap_err_with(|| format!("Failed to write cache {}", path.display()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Fill in the hashes of `file` from the cache, if its size and mtime still match and the
+    /// entry was hashed with (at least) the requested features. Returns whether it was restored.
+    pub fn restore(&self, file: &mut FileData, options: &HashOptions) -> bool {
+        let Some(entry) = self.entries.get(&file.path) else {
+            return false;
+        };
+
+        let perceptual = options.perception_hash;
+        if (perceptual && !entry.perceptual)
+            || entry.algorithm != options.algorithm
+            || entry.prefix_len != options.prefix_len
+        {
+            return false;
+        }
+
+        if perceptual && options.perceptual_algorithm != entry.perceptual_algorithm {
+            return false;
+        }
+
+        if perceptual && options.dihedral && entry.transformed_hashes.is_none() {
+            return false;
+        }
+
+        if perceptual && options.match_crops && entry.crop_hashes.is_none() {
+            return false;
+        }
+
+        if perceptual && options.all_frames && entry.frame_hashes.is_none() {
+            return false;
+        }
+
+        if options.ignore_metadata && !entry.pixels {
+            return false;
+        }
+
+        let Ok(metadata) = fs::metadata(&file.path) else {
+            return false;
+        };
+
+        if metadata.len() as usize != entry.size || metadata.modified().ok() != Some(entry.modified) {
+            return false;
+        }
+
+        // Changed since it was collected, left to `FileData::hash` to notice.
+        if file.size.is_some_and(|size| size != entry.size) {
+            return false;
+        }
+
+        file.size = Some(entry.size);
+        file.modified = Some(entry.modified);
+        file.file_hash = Some(entry.file_hash);
+        if perceptual {
+            file.perception_hash = entry.perception_hash;
+            file.dimensions = entry.dimensions;
+            file.decode_error = entry.decode_error.clone();
+        }
+        if perceptual && options.dihedral {
+            file.transformed_hashes = entry.transformed_hashes.clone().unwrap_or_default();
+        }
+        if perceptual && options.match_crops {
+            file.crop_hashes = entry.crop_hashes.clone().unwrap_or_default();
+        }
+        if perceptual && options.all_frames {
+            file.frame_hashes = entry.frame_hashes.clone().unwrap_or_default();
+        }
+        if options.ignore_metadata {
+            file.pixel_hash = entry.pixel_hash;
+            file.dimensions = file.dimensions.or(entry.dimensions);
+        }
+
+        true
+    }
+
+    /// Record the hashes of all `files`, replacing outdated entries.
+    pub fn update<'a>(&mut self, files: impl IntoIterator<Item = &'a FileData>, options: &HashOptions) {
+        let algorithm = options.algorithm;
+
+        for file in files {
+            let (Some(size), Some(modified), Some(file_hash)) = (file.size, file.modified, file.file_hash) else {
+                continue;
+            };
+
+            // Don't throw away perceptual hashes from an earlier run that are still valid.
+            let previous = self
+                .entries
+                .get(&file.path)
+                .filter(|e| e.perceptual && e.size == size && e.modified == modified);
+            let (perceptual, perceptual_algorithm, perception_hash, dimensions, decode_error) = match previous {
+                Some(previous) if !options.perception_hash => (
+                    true,
+                    previous.perceptual_algorithm,
+                    previous.perception_hash,
+                    previous.dimensions,
+                    previous.decode_error.clone(),
+                ),
+                _ => (
+                    options.perception_hash,
+                    options.perceptual_algorithm,
+                    file.perception_hash,
+                    file.dimensions,
+                    file.decode_error.clone(),
+                ),
+            };
+            let transformed_hashes = match previous {
+                _ if options.perception_hash && options.dihedral => Some(file.transformed_hashes.clone()),
+                Some(previous)
+                    if !options.perception_hash
+                        || (previous.perceptual_algorithm == perceptual_algorithm
+                            && previous.perception_hash == perception_hash) =>
+                {
+                    previous.transformed_hashes.clone()
+                },
+                _ => None,
+            };
+            let crop_hashes = match previous {
+                _ if options.perception_hash && options.match_crops => Some(file.crop_hashes.clone()),
+                Some(previous)
+                    if !options.perception_hash
+                        || (previous.perceptual_algorithm == perceptual_algorithm
+                            && previous.perception_hash == perception_hash) =>
+                {
+                    previous.crop_hashes.clone()
+                },
+                _ => None,
+            };
+            let frame_hashes = match previous {
+                _ if options.perception_hash && options.all_frames => Some(file.frame_hashes.clone()),
+                Some(previous)
+                    if !options.perception_hash
+                        || (previous.perceptual_algorithm == perceptual_algorithm
+                            && previous.perception_hash == perception_hash) =>
+                {
+                    previous.frame_hashes.clone()
+                },
+                _ => None,
+            };
+            let (pixels, pixel_hash) = match self.entries.get(&file.path) {
+                Some(previous)
+                    if !options.ignore_metadata
+                        && previous.pixels
+                        && previous.algorithm == algorithm
+                        && previous.size == size
+                        && previous.modified == modified =>
+                {
+                    (true, previous.pixel_hash)
+                },
+                _ => (options.ignore_metadata, file.pixel_hash),
+            };
+
+            self.entries.insert(file.path.clone(), Entry {
+                size,
+                modified,
+                algorithm,
+                prefix_len: options.prefix_len,
+                file_hash,
+                perceptual,
+                perceptual_algorithm,
+                perception_hash,
+                dimensions,
+                decode_error,
+                transformed_hashes,
+                crop_hashes,
+                frame_hashes,
+                pixels,
+                pixel_hash,
+            });
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let entries = self
+            .entries
+            .iter()
+            .filter_map(|(path, entry)| {
+                let modified = entry.modified.duration_since(UNIX_EPOCH).ok()?;
+                Some(Value::object([
+                    ("path", path.to_str()?.into()),
+                    ("size", entry.size.into()),
+                    ("modified", vec![modified.as_secs(), modified.subsec_nanos() as u64].into()),
+                    ("algorithm", entry.algorithm.name().into()),
+                    ("prefix_len", entry.prefix_len.into()),
+                    ("file_hash", entry.file_hash.to_string().into()),
+                    ("perceptual", entry.perceptual.into()),
+                    ("perceptual_algorithm", entry.perceptual_algorithm.name().into()),
+                    ("perception_hash", entry.perception_hash.map(|h| hex(&h.0)).into()),
+                    ("quality", entry.perception_hash.map(|h| h.1).into()),
+                    ("dimensions", entry.dimensions.map(|(w, h)| vec![w, h]).into()),
+                    ("decode_error", entry.decode_error.clone().into()),
+                    (
+                        "transformed_hashes",
+                        entry
+                            .transformed_hashes
+                            .as_ref()
+                            .map(|hashes| hashes.iter().map(|h| hex(h)).collect::<Vec<_>>())
+                            .into(),
+                    ),
+                    (
+                        "crop_hashes",
+                        entry
+                            .crop_hashes
+                            .as_ref()
+                            .map(|hashes| hashes.iter().map(|h| hex(h)).collect::<Vec<_>>())
+                            .into(),
+                    ),
+                    (
+                        "frame_hashes",
+                        entry
+                            .frame_hashes
+                            .as_ref()
+                            .map(|hashes| hashes.iter().map(|h| hex(h)).collect::<Vec<_>>())
+                            .into(),
+                    ),
+                    ("pixels", entry.pixels.into()),
+                    ("pixel_hash", entry.pixel_hash.map(|h| h.to_string()).into()),
+                ]))
+            })
+            .collect();
+
+        Value::object([("version", VERSION.into()), ("entries", Value::Array(entries))])
+    }
+
+    fn from_json(value: &Value) -> Result<Self> {
+        // Caches written by other versions are simply rebuilt.
+        if value.get("version").and_then(Value::as_u64) != Some(VERSION) {
+            return Ok(Self::default());
+        }
+
+        let entries = value
+            .get("entries")
+            .and_then(Value::as_array)
+            .ok_or_else(|| eyre!("Missing cache entries"))?;
+
+        let mut cache = Self::default();
+        for entry in entries {
+            let (path, entry) = parse_entry(entry).ok_or_else(|| eyre!("Malformed cache entry"))?;
+            cache.entries.insert(path, entry);
+        }
+
+        Ok(cache)
+    }
+}
+
+fn parse_entry(value: &Value) -> Option<(PathBuf, Entry)> {
+    let path = PathBuf::from(value.get("path")?.as_str()?);
+
+    let modified = value.get("modified")?.as_array()?;
+    let modified = UNIX_EPOCH
+        + Duration::new(modified.first()?.as_u64()?, modified.get(1)?.as_u64()? as u32);
+
+    let perception_hash = match value.get("perception_hash")? {
+        Value::Null => None,
+        hash => Some((
+            parse_hex(hash.as_str()?)?.try_into().ok()?,
+            value.get("quality")?.as_f64()? as f32,
+        )),
+    };
+
+    let transformed_hashes = parse_hashes(value.get("transformed_hashes")?)?;
+    let crop_hashes = parse_hashes(value.get("crop_hashes")?)?;
+    let frame_hashes = parse_hashes(value.get("frame_hashes")?)?;
+
+    let dimensions = match value.get("dimensions")? {
+        Value::Null => None,
+        dimensions => {
+            let dimensions = dimensions.as_array()?;
+            Some((dimensions.first()?.as_u64()? as u32, dimensions.get(1)?.as_u64()? as u32))
+        },
+    };
+
+    let pixel_hash = match value.get("pixel_hash")? {
+        Value::Null => None,
+        hash => Some(Digest::from_hex(hash.as_str()?)?),
+    };
+
+    Some((path, Entry {
+        size: value.get("size")?.as_u64()? as usize,
+        modified,
+        algorithm: HashAlgorithm::from_name(value.get("algorithm")?.as_str()?)?,
+        prefix_len: value.get("prefix_len")?.as_u64()? as usize,
+        file_hash: Digest::from_hex(value.get("file_hash")?.as_str()?)?,
+        perceptual: value.get("perceptual")?.as_bool()?,
+        perceptual_algorithm: PerceptualAlgorithm::from_name(value.get("perceptual_algorithm")?.as_str()?)?,
+        perception_hash,
+        dimensions,
+        decode_error: match value.get("decode_error")? {
+            Value::Null => None,
+            error => Some(error.as_str()?.to_owned()),
+        },
+        transformed_hashes,
+        crop_hashes,
+        frame_hashes,
+        pixels: value.get("pixels")?.as_bool()?,
+        pixel_hash,
+    }))
+}
+
+/// Parse an optional list of perceptual hashes, the outer `None` means it is malformed.
+fn parse_hashes(value: &Value) -> Option<Option<Vec<[u8; 32]>>> {
+    match value {
+        Value::Null => Some(None),
+        hashes => hashes
+            .as_array()?
+            .iter()
+            .map(|hash| parse_hex(hash.as_str()?)?.try_into().ok())
+            .collect::<Option<_>>()
+            .map(Some),
+    }
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
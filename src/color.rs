@@ -0,0 +1,81 @@
+//! ANSI colors for the text reports on a terminal, see `--color`.
+
+use std::{
+    env,
+    fmt::{self, Display},
+    io::{self, IsTerminal},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Color output that goes to a terminal, unless `NO_COLOR` is set
+    #[default]
+    Auto,
+    /// Always color the output, e.g. for `less -R`
+    Always,
+    /// Never color the output
+    Never,
+}
+
+/// Whether stdout and stderr are colored, set once by `init`.
+static STDOUT: AtomicBool = AtomicBool::new(false);
+static STDERR: AtomicBool = AtomicBool::new(false);
+
+pub fn init(choice: ColorChoice) {
+    let auto = |is_terminal: bool| {
+        let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        is_terminal && !no_color && env::var_os("TERM").is_none_or(|term| term != "dumb")
+    };
+    let (stdout, stderr) = match choice {
+        ColorChoice::Auto => (auto(io::stdout().is_terminal()), auto(io::stderr().is_terminal())),
+        ColorChoice::Always => (true, true),
+        ColorChoice::Never => (false, false),
+    };
+    STDOUT.store(stdout, Ordering::Relaxed);
+    STDERR.store(stderr, Ordering::Relaxed);
+}
+
+/// `value` displayed with the SGR parameters `code` if `enabled`.
+pub struct Painted<T> {
+    value: T,
+    code: &'static str,
+    enabled: bool,
+}
+
+impl<T: Display> Display for Painted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.enabled {
+            true => write!(f, "\x1b[{}m{}\x1b[0m", self.code, self.value),
+            false => self.value.fmt(f),
+        }
+    }
+}
+
+fn stdout<T>(value: T, code: &'static str) -> Painted<T> {
+    Painted { value, code, enabled: STDOUT.load(Ordering::Relaxed) }
+}
+
+/// The header of a group, printed to stdout.
+pub fn header<T>(value: T) -> Painted<T> {
+    stdout(value, "1;34")
+}
+
+/// The file of a group that `clean --keep first` would keep, printed to stdout.
+pub fn survivor<T>(value: T) -> Painted<T> {
+    stdout(value, "32")
+}
+
+/// Reclaimable space, printed to stdout.
+pub fn reclaimable<T>(value: T) -> Painted<T> {
+    stdout(value, "1;33")
+}
+
+/// Reclaimable space in a status message, which `status!` prints to stderr if stdout is
+/// `machine_readable`.
+pub fn status_reclaimable<T>(value: T, machine_readable: bool) -> Painted<T> {
+    let stream = if machine_readable { &STDERR } else { &STDOUT };
+    Painted { value, code: "1;33", enabled: stream.load(Ordering::Relaxed) }
+}
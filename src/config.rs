@@ -0,0 +1,125 @@
+//! Minimal reader for config files holding default command line options.
+//!
+//! Config files are a subset of TOML: one `key = value` per line, where values are strings,
+//! numbers, booleans or arrays of those on a single line. Keys are the long option names, e.g.
+//! `max-distance = 8` or `exclude = ["*.tmp", ".git"]`, with `-` or `_` between words.
+
+use std::{ffi::OsString, fs, path::Path};
+
+use clap::{parser::ValueSource, ArgMatches, Command};
+use color_eyre::eyre::{bail, eyre, Context, Result};
+
+use crate::json::{self, Value};
+
+/// Read the options of the config file at `path`, in the order they are listed.
+pub fn load(path: &Path) -> Result<Vec<(String, Value)>> {
+    let input = fs::read_to_string(path).wrap_err_with(|| format!("Failed to read config {}", path.display()))?;
+    parse(&input).wrap_err_with(|| format!("Invalid config {}", path.display()))
+}
+
+/// Parse the options of a config file, see the module documentation for its format.
+pub fn parse(input: &str) -> Result<Vec<(String, Value)>> {
+    let mut options: Vec<(String, Value)> = Vec::new();
+    for (number, line) in input.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let at = || format!("line {}", number + 1);
+        if line.starts_with('[') {
+            bail!("Tables aren't supported, at {}", at());
+        }
+        let (key, value) = line.split_once('=').ok_or_else(|| eyre!("Expected `key = value` at {}", at()))?;
+
+        let key = key.trim().replace('-', "_");
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!("Invalid key at {}", at());
+        }
+        if options.iter().any(|(other, _)| *other == key) {
+            bail!("Duplicate key `{key}` at {}", at());
+        }
+
+        let value = value.trim();
+        let value = match value.strip_prefix('\'').and_then(|value| value.strip_suffix('\'')) {
+            // Literal strings have no escapes.
+            Some(literal) => Value::String(literal.to_owned()),
+            // The remaining values are written like in JSON.
+            None => json::parse(value).wrap_err_with(|| format!("Invalid value at {}", at()))?,
+        };
+        options.push((key, value));
+    }
+
+    Ok(options)
+}
+
+/// `line` without a trailing `#` comment, unless the `#` is part of a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            },
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {},
+        }
+        escaped = false;
+    }
+    line
+}
+
+/// Turn `options` into command line arguments for `command`, the (sub)command that was invoked
+/// below `root`, e.g. `jobs = 4` into `--jobs=4`. Options that `matches` got from the command
+/// line take precedence and are left out, as are those conflicting with them. Options that only
+/// other commands have are ignored, so that one file can serve all of them.
+pub fn to_args(root: &Command, command: &Command, matches: &ArgMatches, options: &[(String, Value)]) -> Result<Vec<OsString>> {
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+    let mut args = Vec::new();
+    for (key, value) in options {
+        let Some(arg) = command.get_arguments().find(|arg| arg.get_id() == key.as_str()) else {
+            if !has_arg(root, key) {
+                bail!("Unknown option `{key}` in config");
+            }
+            continue;
+        };
+        let Some(long) = arg.get_long() else {
+            bail!("`{key}` can only be given on the command line");
+        };
+
+        if given(key) || command.get_arg_conflicts_with(arg).iter().any(|other| given(other.get_id().as_str())) {
+            continue;
+        }
+
+        let values = match value {
+            Value::Array(items) => items.as_slice(),
+            value => std::slice::from_ref(value),
+        };
+        for value in values {
+            let value = match value {
+                Value::Bool(true) if !arg.get_action().takes_values() => {
+                    args.push(format!("--{long}").into());
+                    continue;
+                },
+                Value::Bool(false) if !arg.get_action().takes_values() => continue,
+                Value::String(s) => s.clone(),
+                Value::Number(n) => n.to_string(),
+                _ if !arg.get_action().takes_values() => bail!("`{key}` must be true or false"),
+                _ => bail!("`{key}` must be a string, a number or an array of those"),
+            };
+            args.push(format!("--{long}={value}").into());
+        }
+    }
+
+    Ok(args)
+}
+
+/// Whether `command` or any of its subcommands has an argument with the id `id`.
+fn has_arg(command: &Command, id: &str) -> bool {
+    command.get_arguments().any(|arg| arg.get_id() == id) || command.get_subcommands().any(|sub| has_arg(sub, id))
+}
@@ -0,0 +1,218 @@
+//! PNG contact sheets, one per group of similar images, showing the images as a grid of
+//! thumbnails captioned with their file names.
+
+use std::{fs, path::Path};
+
+use color_eyre::eyre::{Context, Result};
+use duplicate_image_finder::FileData;
+use pdqhash::image::{self, imageops, Rgb, RgbImage};
+use rayon::prelude::*;
+
+/// Space around and between the tiles, in pixels.
+const PADDING: u32 = 8;
+/// Captions longer than this many lines are shortened in the middle.
+const CAPTION_LINES: u32 = 2;
+/// Horizontal and vertical distance between the characters of a caption, the glyphs are 5x7.
+const ADVANCE: u32 = 6;
+const LINE_HEIGHT: u32 = 9;
+const CAPTION_HEIGHT: u32 = 4 + CAPTION_LINES * LINE_HEIGHT;
+
+const BACKGROUND: Rgb<u8> = Rgb([255, 255, 255]);
+const TILE: Rgb<u8> = Rgb([221, 221, 221]);
+const TEXT: Rgb<u8> = Rgb([51, 51, 51]);
+const PLACEHOLDER_TEXT: Rgb<u8> = Rgb([102, 102, 102]);
+
+/// Write one sheet per group to `dir`, named `cluster-0001.png` and so on in the order of
+/// `groups`. Sheets wrap after `columns` images, which are scaled down to fit into a square of
+/// `thumbnail_size` pixels. Images that can't be decoded again are shown as a placeholder.
+pub fn write(dir: &Path, groups: &[Vec<&FileData>], columns: u32, thumbnail_size: u32) -> Result<()> {
+    fs::create_dir_all(dir)
+        .wrap_err_with(|| format!("Failed to create contact sheet directory {}", dir.display()))?;
+
+    groups.par_iter().enumerate().try_for_each(|(i, group)| {
+        let path = dir.join(format!("cluster-{:04}.png", i + 1));
+        render(group, columns, thumbnail_size)
+            .save(&path)
+            .wrap_err_with(|| format!("Failed to write contact sheet {}", path.display()))
+    })
+}
+
+fn render(group: &[&FileData], columns: u32, size: u32) -> RgbImage {
+    let columns = columns.clamp(1, group.len().max(1) as u32);
+    let rows = (group.len() as u32).div_ceil(columns);
+    let (cell_width, cell_height) = (size + PADDING, size + CAPTION_HEIGHT + PADDING);
+
+    let mut sheet = RgbImage::from_pixel(
+        PADDING + columns * cell_width,
+        PADDING + rows * cell_height,
+        BACKGROUND,
+    );
+    let thumbnails: Vec<_> = group.par_iter().map(|file| thumbnail(&file.path, size)).collect();
+    let tile = RgbImage::from_pixel(size, size, TILE);
+
+    for (i, (file, thumbnail)) in group.iter().zip(thumbnails).enumerate() {
+        let x = PADDING + i as u32 % columns * cell_width;
+        let y = PADDING + i as u32 / columns * cell_height;
+
+        imageops::overlay(&mut sheet, &tile, x, y);
+        match thumbnail {
+            Some(thumbnail) => {
+                let (left, top) = ((size - thumbnail.width()) / 2, (size - thumbnail.height()) / 2);
+                imageops::overlay(&mut sheet, &thumbnail, x + left, y + top);
+            },
+            None => {
+                let text = "No preview";
+                let left = size.saturating_sub(text.len() as u32 * ADVANCE) / 2;
+                draw_text(&mut sheet, x + left, y + (size - LINE_HEIGHT) / 2, text, PLACEHOLDER_TEXT);
+            },
+        }
+
+        let name = file.path.file_name().unwrap_or(file.path.as_os_str()).to_string_lossy();
+        for (line, text) in caption(&name, (size / ADVANCE) as usize).iter().enumerate() {
+            draw_text(&mut sheet, x, y + size + 4 + line as u32 * LINE_HEIGHT, text, TEXT);
+        }
+    }
+
+    sheet
+}
+
+/// The image at `path` scaled down to fit into a square of `size` pixels.
+fn thumbnail(path: &Path, size: u32) -> Option<RgbImage> {
+    let img = image::load_from_memory(&fs::read(path).ok()?).ok()?;
+    Some(img.thumbnail(size, size).to_rgb8())
+}
+
+/// Split `name` into lines of `width` characters. Names that need more than `CAPTION_LINES`
+/// lines lose their middle, so that both the start and the extension are still visible.
+fn caption(name: &str, width: usize) -> Vec<String> {
+    let mut chars: Vec<char> = name.chars().collect();
+    let max = width * CAPTION_LINES as usize;
+    if chars.len() > max && max > 3 {
+        let tail = (max - 3) / 2;
+        let head = max - 3 - tail;
+        chars.splice(head..chars.len() - tail, "...".chars());
+    }
+
+    chars.chunks(width.max(1)).map(|line| line.iter().collect()).collect()
+}
+
+/// Draw `text` with its top left corner at `x`, `y`. Characters other than printable ASCII are
+/// drawn as `?`, pixels outside of `img` are left out.
+fn draw_text(img: &mut RgbImage, x: u32, y: u32, text: &str, color: Rgb<u8>) {
+    for (i, c) in text.chars().enumerate() {
+        let glyph = match c {
+            ' '..='~' => &FONT[c as usize - ' ' as usize],
+            _ => &FONT['?' as usize - ' ' as usize],
+        };
+
+        let left = x + i as u32 * ADVANCE;
+        for (column, bits) in glyph.iter().enumerate() {
+            for row in 0..7 {
+                let (px, py) = (left + column as u32, y + row);
+                if bits >> row & 1 == 1 && px < img.width() && py < img.height() {
+                    img.put_pixel(px, py, color);
+                }
+            }
+        }
+    }
+}
+
+/// 5x7 pixel glyphs of the printable ASCII characters, starting at the space. Every byte is a
+/// column from left to right, with the top row in the lowest bit.
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5f, 0x00, 0x00], // '!'
+    [0x00, 0x07, 0x00, 0x07, 0x00], // '"'
+    [0x14, 0x7f, 0x14, 0x7f, 0x14], // '#'
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], // '$'
+    [0x23, 0x13, 0x08, 0x64, 0x62], // '%'
+    [0x36, 0x49, 0x55, 0x22, 0x50], // '&'
+    [0x00, 0x05, 0x03, 0x00, 0x00], // "'"
+    [0x00, 0x1c, 0x22, 0x41, 0x00], // '('
+    [0x00, 0x41, 0x22, 0x1c, 0x00], // ')'
+    [0x08, 0x2a, 0x1c, 0x2a, 0x08], // '*'
+    [0x08, 0x08, 0x3e, 0x08, 0x08], // '+'
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ','
+    [0x08, 0x08, 0x08, 0x08, 0x08], // '-'
+    [0x00, 0x60, 0x60, 0x00, 0x00], // '.'
+    [0x20, 0x10, 0x08, 0x04, 0x02], // '/'
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], // '0'
+    [0x00, 0x42, 0x7f, 0x40, 0x00], // '1'
+    [0x42, 0x61, 0x51, 0x49, 0x46], // '2'
+    [0x21, 0x41, 0x45, 0x4b, 0x31], // '3'
+    [0x18, 0x14, 0x12, 0x7f, 0x10], // '4'
+    [0x27, 0x45, 0x45, 0x45, 0x39], // '5'
+    [0x3c, 0x4a, 0x49, 0x49, 0x30], // '6'
+    [0x01, 0x71, 0x09, 0x05, 0x03], // '7'
+    [0x36, 0x49, 0x49, 0x49, 0x36], // '8'
+    [0x06, 0x49, 0x49, 0x29, 0x1e], // '9'
+    [0x00, 0x36, 0x36, 0x00, 0x00], // ':'
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ';'
+    [0x08, 0x14, 0x22, 0x41, 0x00], // '<'
+    [0x14, 0x14, 0x14, 0x14, 0x14], // '='
+    [0x00, 0x41, 0x22, 0x14, 0x08], // '>'
+    [0x02, 0x01, 0x51, 0x09, 0x06], // '?'
+    [0x32, 0x49, 0x79, 0x41, 0x3e], // '@'
+    [0x7e, 0x11, 0x11, 0x11, 0x7e], // 'A'
+    [0x7f, 0x49, 0x49, 0x49, 0x36], // 'B'
+    [0x3e, 0x41, 0x41, 0x41, 0x22], // 'C'
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], // 'D'
+    [0x7f, 0x49, 0x49, 0x49, 0x41], // 'E'
+    [0x7f, 0x09, 0x09, 0x09, 0x01], // 'F'
+    [0x3e, 0x41, 0x49, 0x49, 0x7a], // 'G'
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], // 'H'
+    [0x00, 0x41, 0x7f, 0x41, 0x00], // 'I'
+    [0x20, 0x40, 0x41, 0x3f, 0x01], // 'J'
+    [0x7f, 0x08, 0x14, 0x22, 0x41], // 'K'
+    [0x7f, 0x40, 0x40, 0x40, 0x40], // 'L'
+    [0x7f, 0x02, 0x0c, 0x02, 0x7f], // 'M'
+    [0x7f, 0x04, 0x08, 0x10, 0x7f], // 'N'
+    [0x3e, 0x41, 0x41, 0x41, 0x3e], // 'O'
+    [0x7f, 0x09, 0x09, 0x09, 0x06], // 'P'
+    [0x3e, 0x41, 0x51, 0x21, 0x5e], // 'Q'
+    [0x7f, 0x09, 0x19, 0x29, 0x46], // 'R'
+    [0x46, 0x49, 0x49, 0x49, 0x31], // 'S'
+    [0x01, 0x01, 0x7f, 0x01, 0x01], // 'T'
+    [0x3f, 0x40, 0x40, 0x40, 0x3f], // 'U'
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], // 'V'
+    [0x3f, 0x40, 0x38, 0x40, 0x3f], // 'W'
+    [0x63, 0x14, 0x08, 0x14, 0x63], // 'X'
+    [0x07, 0x08, 0x70, 0x08, 0x07], // 'Y'
+    [0x61, 0x51, 0x49, 0x45, 0x43], // 'Z'
+    [0x00, 0x7f, 0x41, 0x41, 0x00], // '['
+    [0x02, 0x04, 0x08, 0x10, 0x20], // '\\'
+    [0x00, 0x41, 0x41, 0x7f, 0x00], // ']'
+    [0x04, 0x02, 0x01, 0x02, 0x04], // '^'
+    [0x40, 0x40, 0x40, 0x40, 0x40], // '_'
+    [0x00, 0x01, 0x02, 0x04, 0x00], // '`'
+    [0x20, 0x54, 0x54, 0x54, 0x78], // 'a'
+    [0x7f, 0x48, 0x44, 0x44, 0x38], // 'b'
+    [0x38, 0x44, 0x44, 0x44, 0x20], // 'c'
+    [0x38, 0x44, 0x44, 0x48, 0x7f], // 'd'
+    [0x38, 0x54, 0x54, 0x54, 0x18], // 'e'
+    [0x08, 0x7e, 0x09, 0x01, 0x02], // 'f'
+    [0x0c, 0x52, 0x52, 0x52, 0x3e], // 'g'
+    [0x7f, 0x08, 0x04, 0x04, 0x78], // 'h'
+    [0x00, 0x44, 0x7d, 0x40, 0x00], // 'i'
+    [0x20, 0x40, 0x44, 0x3d, 0x00], // 'j'
+    [0x7f, 0x10, 0x28, 0x44, 0x00], // 'k'
+    [0x00, 0x41, 0x7f, 0x40, 0x00], // 'l'
+    [0x7c, 0x04, 0x18, 0x04, 0x78], // 'm'
+    [0x7c, 0x08, 0x04, 0x04, 0x78], // 'n'
+    [0x38, 0x44, 0x44, 0x44, 0x38], // 'o'
+    [0x7c, 0x14, 0x14, 0x14, 0x08], // 'p'
+    [0x08, 0x14, 0x14, 0x18, 0x7c], // 'q'
+    [0x7c, 0x08, 0x04, 0x04, 0x08], // 'r'
+    [0x48, 0x54, 0x54, 0x54, 0x20], // 's'
+    [0x04, 0x3f, 0x44, 0x40, 0x20], // 't'
+    [0x3c, 0x40, 0x40, 0x20, 0x7c], // 'u'
+    [0x1c, 0x20, 0x40, 0x20, 0x1c], // 'v'
+    [0x3c, 0x40, 0x30, 0x40, 0x3c], // 'w'
+    [0x44, 0x28, 0x10, 0x28, 0x44], // 'x'
+    [0x0c, 0x50, 0x50, 0x50, 0x3c], // 'y'
+    [0x44, 0x64, 0x54, 0x4c, 0x44], // 'z'
+    [0x00, 0x08, 0x36, 0x41, 0x00], // '{'
+    [0x00, 0x00, 0x7f, 0x00, 0x00], // '|'
+    [0x00, 0x41, 0x36, 0x08, 0x00], // '}'
+    [0x08, 0x04, 0x08, 0x10, 0x08], // '~'
+];
@@ -0,0 +1,156 @@
+//! Content hash algorithms used for exact duplicate detection.
+
+use std::{fmt, hash::Hasher as _};
+
+use clap::ValueEnum;
+use seahash::SeaHasher;
+
+mod blake3;
+mod sha256;
+mod xxh3;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, ValueEnum)]
+pub enum HashAlgorithm {
+    /// Fast non-cryptographic 64 bit hash
+    #[default]
+    Seahash,
+    /// XXH3, fast non-cryptographic 64 bit hash
+    Xxh3,
+    /// BLAKE3, cryptographic 256 bit hash
+    Blake3,
+    /// SHA-256, cryptographic 256 bit hash that is slower than BLAKE3, but checked by `sha256sum`
+    Sha256,
+}
+
+impl HashAlgorithm {
+    pub fn digest(self, data: &[u8]) -> Digest {
+        match self {
+            HashAlgorithm::Seahash => Digest::from_u64(seahash::hash(data)),
+            HashAlgorithm::Xxh3 => Digest::from_u64(xxh3::hash(data)),
+            HashAlgorithm::Blake3 => Digest::new(&blake3::hash(data)),
+            HashAlgorithm::Sha256 => Digest::new(&sha256::hash(data)),
+        }
+    }
+
+    /// Create an incremental hasher, for input that is not available in one piece.
+    pub fn hasher(self) -> Hasher {
+        match self {
+            HashAlgorithm::Seahash => Hasher::Seahash(SeaHasher::new()),
+            HashAlgorithm::Xxh3 => Hasher::Xxh3(xxh3::Hasher::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Sha256 => Hasher::Sha256(sha256::Hasher::new()),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Seahash => "seahash",
+            HashAlgorithm::Xxh3 => "xxh3",
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::value_variants().iter().copied().find(|a| a.name() == name)
+    }
+}
+
+/// Incremental version of `HashAlgorithm::digest`, yielding the same digest for the same bytes.
+pub enum Hasher {
+    Seahash(SeaHasher),
+    Xxh3(xxh3::Hasher),
+    Blake3(Box<blake3::Hasher>),
+    Sha256(sha256::Hasher),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Seahash(hasher) => hasher.write(data),
+            Hasher::Xxh3(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finish(&self) -> Digest {
+        match self {
+            Hasher::Seahash(hasher) => Digest::from_u64(hasher.finish()),
+            Hasher::Xxh3(hasher) => Digest::from_u64(hasher.finish()),
+            Hasher::Blake3(hasher) => Digest::new(&hasher.finalize()),
+            Hasher::Sha256(hasher) => Digest::new(&hasher.finalize()),
+        }
+    }
+}
+
+/// The output of any `HashAlgorithm`, wide enough for the largest one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Digest {
+    bytes: [u8; 32],
+    len: u8,
+}
+
+impl Digest {
+    /// Create a digest from up to 32 bytes.
+    pub fn new(bytes: &[u8]) -> Self {
+        let mut digest = Self { bytes: [0; 32], len: bytes.len() as u8 };
+        digest.bytes[..bytes.len()].copy_from_slice(bytes);
+        digest
+    }
+
+    /// 64 bit hashes are stored big endian, so they display like the plain integer in hex.
+    pub fn from_u64(hash: u64) -> Self {
+        Self::new(&hash.to_be_bytes())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    /// Parse the hex representation produced by `Display`.
+    pub fn from_hex(s: &str) -> Option<Self> {
+        if s.len() > 64 || !s.len().is_multiple_of(2) {
+            return None;
+        }
+
+        let bytes: Option<Vec<u8>> = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+            .collect();
+
+        Some(Self::new(&bytes?))
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.as_bytes() {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streaming_matches_one_shot() {
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        for &algorithm in HashAlgorithm::value_variants() {
+            for len in [0, 1, 3, 16, 17, 128, 129, 240, 241, 1023, 1024, 1025, 3000, 10_000] {
+                let expected = algorithm.digest(&data[..len]);
+                // Odd sizes, so that chunks straddle every block and stripe boundary.
+                for chunk in [1, 7, 63, 65, 1000, 1031] {
+                    let mut hasher = algorithm.hasher();
+                    for piece in data[..len].chunks(chunk) {
+                        hasher.update(piece);
+                    }
+                    assert_eq!(hasher.finish(), expected, "{} of {len} bytes in chunks of {chunk}", algorithm.name());
+                }
+            }
+        }
+    }
+}
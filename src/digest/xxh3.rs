@@ -0,0 +1,330 @@
+//! XXH3 64 bit with the default secret and seed 0, following the reference implementation.
+
+const PRIME32_1: u64 = 0x9E3779B1;
+const PRIME32_2: u64 = 0x85EBCA77;
+const PRIME32_3: u64 = 0xC2B2AE3D;
+const PRIME64_1: u64 = 0x9E3779B185EBCA87;
+const PRIME64_2: u64 = 0xC2B2AE3D27D4EB4F;
+const PRIME64_3: u64 = 0x165667B19E3779F9;
+const PRIME64_4: u64 = 0x85EBCA77C2B2AE63;
+const PRIME64_5: u64 = 0x27D4EB2F165667C5;
+const PRIME_MX1: u64 = 0x165667919E3779F9;
+const PRIME_MX2: u64 = 0x9FB21C651E98DF25;
+
+const SECRET: [u8; 192] = [
+    0xb8, 0xfe, 0x6c, 0x39, 0x23, 0xa4, 0x4b, 0xbe, 0x7c, 0x01, 0x81, 0x2c, 0xf7, 0x21, 0xad, 0x1c,
+    0xde, 0xd4, 0x6d, 0xe9, 0x83, 0x90, 0x97, 0xdb, 0x72, 0x40, 0xa4, 0xa4, 0xb7, 0xb3, 0x67, 0x1f,
+    0xcb, 0x79, 0xe6, 0x4e, 0xcc, 0xc0, 0xe5, 0x78, 0x82, 0x5a, 0xd0, 0x7d, 0xcc, 0xff, 0x72, 0x21,
+    0xb8, 0x08, 0x46, 0x74, 0xf7, 0x43, 0x24, 0x8e, 0xe0, 0x35, 0x90, 0xe6, 0x81, 0x3a, 0x26, 0x4c,
+    0x3c, 0x28, 0x52, 0xbb, 0x91, 0xc3, 0x00, 0xcb, 0x88, 0xd0, 0x65, 0x8b, 0x1b, 0x53, 0x2e, 0xa3,
+    0x71, 0x64, 0x48, 0x97, 0xa2, 0x0d, 0xf9, 0x4e, 0x38, 0x19, 0xef, 0x46, 0xa9, 0xde, 0xac, 0xd8,
+    0xa8, 0xfa, 0x76, 0x3f, 0xe3, 0x9c, 0x34, 0x3f, 0xf9, 0xdc, 0xbb, 0xc7, 0xc7, 0x0b, 0x4f, 0x1d,
+    0x8a, 0x51, 0xe0, 0x4b, 0xcd, 0xb4, 0x59, 0x31, 0xc8, 0x9f, 0x7e, 0xc9, 0xd9, 0x78, 0x73, 0x64,
+    0xea, 0xc5, 0xac, 0x83, 0x34, 0xd3, 0xeb, 0xc3, 0xc5, 0x81, 0xa0, 0xff, 0xfa, 0x13, 0x63, 0xeb,
+    0x17, 0x0d, 0xdd, 0x51, 0xb7, 0xf0, 0xda, 0x49, 0xd3, 0x16, 0x55, 0x26, 0x29, 0xd4, 0x68, 0x9e,
+    0x2b, 0x16, 0xbe, 0x58, 0x7d, 0x47, 0xa1, 0xfc, 0x8f, 0xf8, 0xb8, 0xd1, 0x7a, 0xd0, 0x31, 0xce,
+    0x45, 0xcb, 0x3a, 0x8f, 0x95, 0x16, 0x04, 0x28, 0xaf, 0xd7, 0xfb, 0xca, 0xbb, 0x4b, 0x40, 0x7e,
+];
+
+const STRIPE_LEN: usize = 64;
+const SECRET_CONSUME_RATE: usize = 8;
+const STRIPES_PER_BLOCK: usize = (SECRET.len() - STRIPE_LEN) / SECRET_CONSUME_RATE;
+const BLOCK_LEN: usize = STRIPE_LEN * STRIPES_PER_BLOCK;
+const MIDSIZE_MAX: usize = 240;
+const LAST_STRIPE_OFFSET: usize = 7;
+
+fn read32(data: &[u8], offset: usize) -> u64 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as u64
+}
+
+fn read64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn mul128_fold64(a: u64, b: u64) -> u64 {
+    let product = a as u128 * b as u128;
+    (product as u64) ^ ((product >> 64) as u64)
+}
+
+fn xxh64_avalanche(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(PRIME64_2);
+    h ^= h >> 29;
+    h = h.wrapping_mul(PRIME64_3);
+    h ^ (h >> 32)
+}
+
+fn avalanche(mut h: u64) -> u64 {
+    h ^= h >> 37;
+    h = h.wrapping_mul(PRIME_MX1);
+    h ^ (h >> 32)
+}
+
+fn rrmxmx(mut h: u64, len: u64) -> u64 {
+    h ^= h.rotate_left(49) ^ h.rotate_left(24);
+    h = h.wrapping_mul(PRIME_MX2);
+    h ^= (h >> 35).wrapping_add(len);
+    h = h.wrapping_mul(PRIME_MX2);
+    h ^ (h >> 28)
+}
+
+fn mix16(data: &[u8], offset: usize, secret_offset: usize) -> u64 {
+    mul128_fold64(
+        read64(data, offset) ^ read64(&SECRET, secret_offset),
+        read64(data, offset + 8) ^ read64(&SECRET, secret_offset + 8),
+    )
+}
+
+fn hash_0to16(data: &[u8]) -> u64 {
+    let len = data.len();
+    match len {
+        0 => xxh64_avalanche(read64(&SECRET, 56) ^ read64(&SECRET, 64)),
+        1..=3 => {
+            let combined = ((data[0] as u32) << 16)
+                | ((data[len >> 1] as u32) << 24)
+                | (data[len - 1] as u32)
+                | ((len as u32) << 8);
+            let bitflip = read32(&SECRET, 0) ^ read32(&SECRET, 4);
+            xxh64_avalanche(combined as u64 ^ bitflip)
+        }
+        4..=8 => {
+            let input = read32(data, len - 4).wrapping_add(read32(data, 0) << 32);
+            let bitflip = read64(&SECRET, 8) ^ read64(&SECRET, 16);
+            rrmxmx(input ^ bitflip, len as u64)
+        }
+        _ => {
+            let lo = read64(data, 0) ^ (read64(&SECRET, 24) ^ read64(&SECRET, 32));
+            let hi = read64(data, len - 8) ^ (read64(&SECRET, 40) ^ read64(&SECRET, 48));
+            let acc = (len as u64)
+                .wrapping_add(lo.swap_bytes())
+                .wrapping_add(hi)
+                .wrapping_add(mul128_fold64(lo, hi));
+            avalanche(acc)
+        }
+    }
+}
+
+fn hash_17to128(data: &[u8]) -> u64 {
+    let len = data.len();
+    let mut acc = (len as u64).wrapping_mul(PRIME64_1);
+
+    if len > 32 {
+        if len > 64 {
+            if len > 96 {
+                acc = acc.wrapping_add(mix16(data, 48, 96));
+                acc = acc.wrapping_add(mix16(data, len - 64, 112));
+            }
+            acc = acc.wrapping_add(mix16(data, 32, 64));
+            acc = acc.wrapping_add(mix16(data, len - 48, 80));
+        }
+        acc = acc.wrapping_add(mix16(data, 16, 32));
+        acc = acc.wrapping_add(mix16(data, len - 32, 48));
+    }
+    acc = acc.wrapping_add(mix16(data, 0, 0));
+    acc = acc.wrapping_add(mix16(data, len - 16, 16));
+
+    avalanche(acc)
+}
+
+fn hash_129to240(data: &[u8]) -> u64 {
+    const START_OFFSET: usize = 3;
+    const LAST_OFFSET: usize = 17;
+    const SECRET_SIZE_MIN: usize = 136;
+
+    let len = data.len();
+    let mut acc = (len as u64).wrapping_mul(PRIME64_1);
+
+    for i in 0..8 {
+        acc = acc.wrapping_add(mix16(data, 16 * i, 16 * i));
+    }
+    acc = avalanche(acc);
+
+    for i in 8..len / 16 {
+        acc = acc.wrapping_add(mix16(data, 16 * i, 16 * (i - 8) + START_OFFSET));
+    }
+    acc = acc.wrapping_add(mix16(data, len - 16, SECRET_SIZE_MIN - LAST_OFFSET));
+
+    avalanche(acc)
+}
+
+fn accumulate_512(acc: &mut [u64; 8], stripe: &[u8], secret_offset: usize) {
+    for i in 0..8 {
+        let value = read64(stripe, 8 * i);
+        let key = value ^ read64(&SECRET, secret_offset + 8 * i);
+        acc[i ^ 1] = acc[i ^ 1].wrapping_add(value);
+        acc[i] = acc[i].wrapping_add((key & 0xFFFF_FFFF).wrapping_mul(key >> 32));
+    }
+}
+
+fn scramble(acc: &mut [u64; 8]) {
+    for (i, acc) in acc.iter_mut().enumerate() {
+        let key = read64(&SECRET, SECRET.len() - STRIPE_LEN + 8 * i);
+        *acc ^= *acc >> 47;
+        *acc ^= key;
+        *acc = acc.wrapping_mul(PRIME32_1);
+    }
+}
+
+fn accumulate(acc: &mut [u64; 8], stripes: &[u8]) {
+    for (n, stripe) in stripes.chunks_exact(STRIPE_LEN).enumerate() {
+        accumulate_512(acc, stripe, n * SECRET_CONSUME_RATE);
+    }
+}
+
+fn merge(acc: &[u64; 8], len: u64) -> u64 {
+    const MERGE_ACCS_START: usize = 11;
+
+    let mut result = len.wrapping_mul(PRIME64_1);
+    for i in 0..4 {
+        let offset = MERGE_ACCS_START + 16 * i;
+        result = result.wrapping_add(mul128_fold64(
+            acc[2 * i] ^ read64(&SECRET, offset),
+            acc[2 * i + 1] ^ read64(&SECRET, offset + 8),
+        ));
+    }
+    avalanche(result)
+}
+
+const INITIAL_ACC: [u64; 8] = [
+    PRIME32_3, PRIME64_1, PRIME64_2, PRIME64_3, PRIME64_4, PRIME32_2, PRIME64_5, PRIME32_1,
+];
+
+fn hash_long(data: &[u8]) -> u64 {
+    let len = data.len();
+    let mut acc = INITIAL_ACC;
+
+    let num_blocks = (len - 1) / BLOCK_LEN;
+    for block in data[..num_blocks * BLOCK_LEN].chunks_exact(BLOCK_LEN) {
+        accumulate(&mut acc, block);
+        scramble(&mut acc);
+    }
+
+    let tail = &data[num_blocks * BLOCK_LEN..];
+    let num_stripes = (tail.len() - 1) / STRIPE_LEN;
+    accumulate(&mut acc, &tail[..num_stripes * STRIPE_LEN]);
+
+    accumulate_512(
+        &mut acc,
+        &data[len - STRIPE_LEN..],
+        SECRET.len() - STRIPE_LEN - LAST_STRIPE_OFFSET,
+    );
+
+    merge(&acc, len as u64)
+}
+
+pub fn hash(data: &[u8]) -> u64 {
+    match data.len() {
+        0..=16 => hash_0to16(data),
+        17..=128 => hash_17to128(data),
+        129..=MIDSIZE_MAX => hash_129to240(data),
+        _ => hash_long(data),
+    }
+}
+
+/// Incremental XXH3 hasher, producing the same result as `hash` over the concatenated input.
+pub struct Hasher {
+    acc: [u64; 8],
+    /// Stripes accumulated in the current block.
+    stripes: usize,
+    /// Input that wasn't consumed yet. Short inputs are kept entirely, since they are hashed
+    /// differently.
+    buffer: Vec<u8>,
+    /// The last consumed stripe, needed when the final stripe overlaps already consumed input.
+    last_stripe: [u8; STRIPE_LEN],
+    len: u64,
+}
+
+impl Hasher {
+    pub fn new() -> Self {
+        Self {
+            acc: INITIAL_ACC,
+            stripes: 0,
+            buffer: Vec::new(),
+            last_stripe: [0; STRIPE_LEN],
+            len: 0,
+        }
+    }
+
+    pub fn update(&mut self, input: &[u8]) {
+        self.len += input.len() as u64;
+        self.buffer.extend_from_slice(input);
+
+        if self.len <= MIDSIZE_MAX as u64 {
+            return;
+        }
+
+        // Always keep at least one byte back, the final stripe is processed differently.
+        let mut offset = 0;
+        while self.buffer.len() - offset > STRIPE_LEN {
+            accumulate_512(
+                &mut self.acc,
+                &self.buffer[offset..offset + STRIPE_LEN],
+                self.stripes * SECRET_CONSUME_RATE,
+            );
+            offset += STRIPE_LEN;
+
+            self.stripes += 1;
+            if self.stripes == STRIPES_PER_BLOCK {
+                scramble(&mut self.acc);
+                self.stripes = 0;
+            }
+        }
+
+        if offset > 0 {
+            self.last_stripe.copy_from_slice(&self.buffer[offset - STRIPE_LEN..offset]);
+            self.buffer.drain(..offset);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        if self.len <= MIDSIZE_MAX as u64 {
+            return hash(&self.buffer);
+        }
+
+        let rest = self.buffer.len();
+        let mut stripe = [0; STRIPE_LEN];
+        stripe[..STRIPE_LEN - rest].copy_from_slice(&self.last_stripe[rest..]);
+        stripe[STRIPE_LEN - rest..].copy_from_slice(&self.buffer);
+
+        let mut acc = self.acc;
+        accumulate_512(&mut acc, &stripe, SECRET.len() - STRIPE_LEN - LAST_STRIPE_OFFSET);
+
+        merge(&acc, self.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Computed with a separate implementation of the specification. They cover every length
+    /// class of the algorithm and several blocks of stripes.
+    const VECTORS: [(usize, u64); 14] = [
+        (0, 0x2d06800538d394c2),
+        (1, 0xc44bdff4074eecdb),
+        (3, 0x5f4299fc161c9cbb),
+        (16, 0x8355e3a6f61770db),
+        (17, 0x9ef341a99de37328),
+        (128, 0x85c6174c7ff4c46b),
+        (129, 0xec7642b431ba3e5a),
+        (240, 0x375a384d957fe865),
+        (241, 0x02e8cd95421c6d02),
+        (1023, 0xd3d91d80ac495685),
+        (1024, 0xe5d78bafa45b2aa5),
+        (1025, 0xe95c42288f28186e),
+        (8193, 0xd6735a2b792cf505),
+        (102400, 0x1428e17f1cac2837),
+    ];
+
+    /// The input of the official BLAKE3 test vectors, the bytes 0 to 250 repeated.
+    fn input(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn known_answers() {
+        for (len, expected) in VECTORS {
+            assert_eq!(hash(&input(len)), expected, "{len} bytes");
+        }
+    }
+}
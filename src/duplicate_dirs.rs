@@ -0,0 +1,198 @@
+//! Directories whose whole contents are duplicated elsewhere, e.g. a copied folder, found by
+//! rolling the hashes of their files up the tree.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    digest::{Digest, HashAlgorithm},
+    FileData,
+};
+
+/// Directories with identical contents.
+#[derive(Debug, Clone)]
+pub struct DirGroup {
+    /// Hash of the contents of every directory of the group.
+    pub hash: Digest,
+    pub dirs: Vec<PathBuf>,
+    /// Number of files below each of the directories.
+    pub files: usize,
+    /// Total size of the files below each of the directories.
+    pub size: u64,
+}
+
+/// The duplicate directories below some roots, see `DuplicateDirs::find`.
+#[derive(Debug, Default)]
+pub struct DuplicateDirs {
+    pub groups: Vec<DirGroup>,
+    /// Index into `groups` for every directory in one of them.
+    members: HashMap<PathBuf, usize>,
+}
+
+/// Contents of a single directory below the roots, collected before it is hashed.
+#[derive(Default)]
+struct Node<'a> {
+    /// Full hashes and sizes of the files directly inside the directory that have duplicates.
+    files: Vec<(Digest, u64)>,
+    subdirs: Vec<&'a Path>,
+}
+
+/// Number of files found directly inside every directory, to tell whether all of them have a
+/// duplicate. Count the collected files before any are left out, e.g. for a unique size.
+pub fn file_counts<'a>(files: impl IntoIterator<Item = &'a FileData>) -> HashMap<PathBuf, usize> {
+    let mut counts = HashMap::new();
+    for file in files {
+        if let Some(parent) = file.path.parent() {
+            *counts.entry(parent.to_owned()).or_default() += 1;
+        }
+    }
+    counts
+}
+
+impl DuplicateDirs {
+    /// Find the directories below `roots` whose files are all duplicated in the same structure
+    /// somewhere else, given the exact duplicate `groups` and the `file_counts` of all collected
+    /// files. The names of the files don't matter, only their contents and the nesting of the
+    /// directories. A directory with a file without duplicates, or one that failed to hash, is
+    /// never a duplicate. Groups whose directories each lie in a different copy of the same
+    /// duplicate directory are left out, since the copy of the parent already covers them.
+    pub fn find(
+        groups: &[(Digest, Vec<&FileData>)],
+        counts: &HashMap<PathBuf, usize>,
+        roots: &[impl AsRef<Path>],
+        algorithm: HashAlgorithm,
+    ) -> Self {
+        let below_roots = |dir: &Path| roots.iter().any(|root| dir.starts_with(root));
+
+        let mut nodes: HashMap<&Path, Node> = HashMap::new();
+        let mut linked: HashSet<&Path> = HashSet::new();
+        for dir in counts.keys().filter(|dir| below_roots(dir)) {
+            nodes.entry(dir).or_default();
+            // Register every directory on the way up, stopping at the roots whose parents
+            // contain more than what was scanned.
+            let mut child = dir.as_path();
+            while !roots.iter().any(|root| child == root.as_ref()) {
+                let Some(parent) = child.parent().filter(|parent| below_roots(parent)) else {
+                    break;
+                };
+                if !linked.insert(child) {
+                    break;
+                }
+                nodes.entry(parent).or_default().subdirs.push(child);
+                child = parent;
+            }
+        }
+
+        for (hash, files) in groups {
+            for file in files {
+                if let Some(node) = file.path.parent().and_then(|parent| nodes.get_mut(parent)) {
+                    node.files.push((*hash, file.size.unwrap_or(0) as u64));
+                }
+            }
+        }
+
+        // Children before their parents, so that their hashes are known.
+        let mut dirs: Vec<&Path> = nodes.keys().copied().collect();
+        dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+
+        // Hash, number of files and size of every directory that is fully duplicated.
+        let mut hashes: HashMap<&Path, Option<(Digest, usize, u64)>> = HashMap::new();
+        for dir in dirs {
+            let node = &nodes[dir];
+            let complete = node.files.len() == counts.get(dir).copied().unwrap_or(0);
+
+            let mut entries: Vec<(u8, Digest)> = node.files.iter().map(|&(hash, _)| (0, hash)).collect();
+            let (mut files, mut size) = (node.files.len(), node.files.iter().map(|&(_, size)| size).sum());
+            let mut subdirs_complete = true;
+            for subdir in &node.subdirs {
+                match hashes[subdir] {
+                    Some((hash, subdir_files, subdir_size)) => {
+                        entries.push((1, hash));
+                        files += subdir_files;
+                        size += subdir_size;
+                    },
+                    None => subdirs_complete = false,
+                }
+            }
+
+            let hash = (complete && subdirs_complete).then(|| {
+                entries.sort();
+                let mut hasher = algorithm.hasher();
+                for (kind, hash) in &entries {
+                    hasher.update(&[*kind]);
+                    hasher.update(hash.as_bytes());
+                }
+                (hasher.finish(), files, size)
+            });
+            hashes.insert(dir, hash);
+        }
+
+        let mut by_hash: BTreeMap<Digest, DirGroup> = BTreeMap::new();
+        for (dir, (hash, files, size)) in hashes.into_iter().filter_map(|(dir, hash)| Some((dir, hash?))) {
+            by_hash
+                .entry(hash)
+                .or_insert_with(|| DirGroup { hash, dirs: Vec::new(), files, size })
+                .dirs
+                .push(dir.to_owned());
+        }
+
+        let mut found = Self::default();
+        for mut group in by_hash.into_values().filter(|group| group.dirs.len() > 1) {
+            group.dirs.sort();
+            for dir in &group.dirs {
+                found.members.insert(dir.clone(), found.groups.len());
+            }
+            found.groups.push(group);
+        }
+
+        let covered: Vec<bool> = found
+            .groups
+            .iter()
+            .map(|group| found.covers(group.dirs.iter().map(PathBuf::as_path)))
+            .collect();
+        found.groups = std::mem::take(&mut found.groups)
+            .into_iter()
+            .zip(covered)
+            .filter_map(|(group, covered)| (!covered).then_some(group))
+            .collect();
+        found.members = found
+            .groups
+            .iter()
+            .enumerate()
+            .flat_map(|(i, group)| group.dirs.iter().map(move |dir| (dir.clone(), i)))
+            .collect();
+
+        found
+    }
+
+    /// Whether every path lies in a different directory of the same group, so that they are
+    /// only duplicates because that directory was copied.
+    pub fn covers<'a>(&self, paths: impl IntoIterator<Item = &'a Path>) -> bool {
+        // Groups that contain all paths so far, with the directory holding each path.
+        let mut common: Option<HashMap<usize, HashSet<&Path>>> = None;
+        let mut count = 0;
+        for path in paths {
+            count += 1;
+            let containing: HashMap<usize, &Path> = path
+                .ancestors()
+                .skip(1)
+                .filter_map(|dir| Some((*self.members.get(dir)?, dir)))
+                .collect();
+
+            common = Some(match common {
+                None => containing.into_iter().map(|(group, dir)| (group, HashSet::from([dir]))).collect(),
+                Some(mut common) => {
+                    common.retain(|group, _| containing.contains_key(group));
+                    for (group, dirs) in &mut common {
+                        dirs.insert(containing[group]);
+                    }
+                    common
+                },
+            });
+        }
+
+        common.is_some_and(|common| common.values().any(|dirs| dirs.len() == count))
+    }
+}
@@ -0,0 +1,69 @@
+//! Errors returned by the scanning and hashing functions, so that callers can tell what failed.
+
+use std::{fmt, io, path::{Path, PathBuf}};
+
+#[derive(Debug)]
+pub enum DuplicateFinderError {
+    /// An operation on `path` failed. `action` names it, e.g. `open` or `read`.
+    Io { path: PathBuf, action: &'static str, source: io::Error },
+    /// An entry of a directory couldn't be read while walking it.
+    Walk(walkdir::Error),
+    /// The size of `path` changed from `expected`, the size it had when it was collected, to
+    /// `actual` while it was being scanned.
+    Changed { path: PathBuf, expected: usize, actual: usize },
+}
+
+pub type Result<T, E = DuplicateFinderError> = std::result::Result<T, E>;
+
+impl DuplicateFinderError {
+    /// Wraps an I/O error of the operation `action` on `path`, for `map_err`.
+    pub(crate) fn io<'a>(action: &'static str, path: &'a Path) -> impl FnOnce(io::Error) -> Self + 'a {
+        move |source| Self::Io { path: path.to_owned(), action, source }
+    }
+
+    /// The file or directory the error is about, if known.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Io { path, .. } | Self::Changed { path, .. } => Some(path),
+            Self::Walk(err) => err.path(),
+        }
+    }
+
+    /// Whether the error only means that the entry isn't accessible to the current user.
+    pub fn is_permission_denied(&self) -> bool {
+        let kind = match self {
+            Self::Io { source, .. } => Some(source.kind()),
+            Self::Walk(err) => err.io_error().map(io::Error::kind),
+            Self::Changed { .. } => None,
+        };
+        kind == Some(io::ErrorKind::PermissionDenied)
+    }
+}
+
+impl fmt::Display for DuplicateFinderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io { path, action, .. } => write!(f, "Failed to {action} {}", path.display()),
+            Self::Walk(_) => write!(f, "Invalid directory entry while iterating"),
+            Self::Changed { path, expected, actual } => {
+                write!(f, "{} changed during the scan, from {expected} to {actual} bytes", path.display())
+            },
+        }
+    }
+}
+
+impl std::error::Error for DuplicateFinderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Walk(source) => Some(source),
+            Self::Changed { .. } => None,
+        }
+    }
+}
+
+impl From<walkdir::Error> for DuplicateFinderError {
+    fn from(err: walkdir::Error) -> Self {
+        Self::Walk(err)
+    }
+}
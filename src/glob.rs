@@ -0,0 +1,255 @@
+//! Shell-style glob patterns for including or excluding paths while collecting.
+//!
+//! Supported syntax is `?`, `*`, `**`, character classes like `[a-z]` or `[!0-9]`, alternatives
+//! like `{jpg,png}` and `\` to escape any of these. `*` and `?` never match a `/`, `**/` matches
+//! any number of directories. Patterns without a `/` only match the file name, so `node_modules`
+//! matches that directory anywhere in the tree.
+
+use std::path::{Component, Path};
+
+use color_eyre::eyre::{bail, Result};
+
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pattern: String,
+    /// The tokens of every alternative after expanding `{a,b}` groups.
+    alternatives: Vec<Vec<Token>>,
+    /// Whether the pattern only matches the file name.
+    name_only: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Token {
+    Char(char),
+    /// `?`, a single character except `/`
+    Any,
+    /// `*`, any number of characters except `/`
+    Star,
+    /// `**/`, any number of complete directories
+    Dirs,
+    /// `**` anywhere else, any number of characters
+    All,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let alternatives = expand_braces(pattern)?
+            .iter()
+            .map(|alternative| tokenize(alternative))
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            pattern: pattern.to_owned(),
+            alternatives,
+            name_only: !pattern.contains('/'),
+        })
+    }
+
+    /// Like `new`, but the pattern always matches the whole relative path, even without a `/`.
+    pub fn anchored(pattern: &str) -> Result<Self> {
+        Ok(Self { name_only: false, ..Self::new(pattern)? })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Whether `path`, relative to the scanned root, matches the pattern.
+    pub fn matches(&self, path: &Path) -> bool {
+        self.matches_text(&self.text(path))
+    }
+
+    /// Like `matches`, but also matches patterns for the contents of the directory `path`, such
+    /// as `build/**`. Used to skip excluded directories entirely.
+    pub fn matches_dir(&self, path: &Path) -> bool {
+        let mut text = self.text(path);
+        if self.matches_text(&text) {
+            return true;
+        }
+
+        text.push('/');
+        !self.name_only && self.matches_text(&text)
+    }
+
+    fn text(&self, path: &Path) -> Vec<char> {
+        if self.name_only {
+            return path.file_name().unwrap_or_default().to_string_lossy().chars().collect();
+        }
+
+        let components: Vec<_> = path
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            })
+            .collect();
+        components.join("/").chars().collect()
+    }
+
+    fn matches_text(&self, text: &[char]) -> bool {
+        self.alternatives.iter().any(|tokens| matches(tokens, text))
+    }
+}
+
+fn matches(tokens: &[Token], text: &[char]) -> bool {
+    let Some((token, rest)) = tokens.split_first() else {
+        return text.is_empty();
+    };
+
+    let single = |accept: &dyn Fn(char) -> bool| {
+        text.first().is_some_and(|&c| c != '/' && accept(c)) && matches(rest, &text[1..])
+    };
+
+    match token {
+        Token::Char(expected) => text.first() == Some(expected) && matches(rest, &text[1..]),
+        Token::Any => single(&|_| true),
+        Token::Class { negated, ranges } => {
+            single(&|c| ranges.iter().any(|&(low, high)| low <= c && c <= high) != *negated)
+        },
+        Token::Star => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != '/')
+            .any(|i| matches(rest, &text[i..])),
+        Token::All => (0..=text.len()).any(|i| matches(rest, &text[i..])),
+        Token::Dirs => {
+            matches(rest, text)
+                || (0..text.len()).any(|i| text[i] == '/' && matches(rest, &text[i + 1..]))
+        },
+    }
+}
+
+fn tokenize(pattern: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let starts_component = i == 0 || chars[i - 1] == '/';
+                if starts_component && chars.get(i + 2) == Some(&'/') {
+                    tokens.push(Token::Dirs);
+                    i += 3;
+                } else {
+                    tokens.push(Token::All);
+                    i += 2;
+                }
+                continue;
+            },
+            '*' => tokens.push(Token::Star),
+            '?' => tokens.push(Token::Any),
+            '[' => {
+                let (class, end) = parse_class(&chars, i, pattern)?;
+                tokens.push(class);
+                i = end;
+            },
+            '\\' => {
+                i += 1;
+                let Some(&c) = chars.get(i) else {
+                    bail!("Trailing backslash in pattern '{pattern}'");
+                };
+                tokens.push(Token::Char(c));
+            },
+            c => tokens.push(Token::Char(c)),
+        }
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+/// Parse the class starting at `chars[start] == '['`, returns it and the index of the closing
+/// bracket.
+fn parse_class(chars: &[char], start: usize, pattern: &str) -> Result<(Token, usize)> {
+    let mut i = start + 1;
+
+    let negated = matches!(chars.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let first = i;
+    loop {
+        let Some(&low) = chars.get(i) else {
+            bail!("Unclosed character class in pattern '{pattern}'");
+        };
+
+        // A `]` right at the start is part of the class.
+        if low == ']' && i > first {
+            return Ok((Token::Class { negated, ranges }, i));
+        }
+
+        match (chars.get(i + 1), chars.get(i + 2)) {
+            (Some('-'), Some(&high)) if high != ']' => {
+                ranges.push((low, high));
+                i += 3;
+            },
+            _ => {
+                ranges.push((low, low));
+                i += 1;
+            },
+        }
+    }
+}
+
+/// Expand all `{a,b}` groups into separate patterns.
+fn expand_braces(pattern: &str) -> Result<Vec<String>> {
+    let chars: Vec<char> = pattern.chars().collect();
+
+    let Some(open) = find_unescaped(&chars, |c| c == '{') else {
+        return Ok(vec![pattern.to_owned()]);
+    };
+
+    let mut depth = 0;
+    let mut splits = vec![open];
+    let mut close = None;
+    let mut i = open;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(i);
+                    break;
+                }
+            },
+            ',' if depth == 1 => splits.push(i),
+            _ => {},
+        }
+        i += 1;
+    }
+
+    let Some(close) = close else {
+        bail!("Unclosed '{{' in pattern '{pattern}'");
+    };
+    splits.push(close);
+
+    let prefix: String = chars[..open].iter().collect();
+    let suffix: String = chars[close + 1..].iter().collect();
+
+    let mut expanded = Vec::new();
+    for bounds in splits.windows(2) {
+        let alternative: String = chars[bounds[0] + 1..bounds[1]].iter().collect();
+        expanded.extend(expand_braces(&format!("{prefix}{alternative}{suffix}"))?);
+    }
+
+    Ok(expanded)
+}
+
+fn find_unescaped(chars: &[char], predicate: impl Fn(char) -> bool) -> Option<usize> {
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == '\\' {
+            i += 2;
+            continue;
+        }
+        if predicate(chars[i]) {
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
+}
@@ -0,0 +1,171 @@
+//! Hashes of every file below a root saved to disk, so that the duplicates among them can be
+//! searched for again and again without rescanning the tree.
+
+use std::{
+    collections::HashSet, fs, path::{Path, PathBuf}, time::{Duration, UNIX_EPOCH}
+};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use rayon::{iter::Either, prelude::*};
+
+use crate::{
+    digest::{Digest, HashAlgorithm}, error::DuplicateFinderError, group_candates, json::{self, Value},
+    progress::{Phase, Progress}, FileData, HashOptions
+};
+
+/// Identifies index files, in case some other JSON file is passed instead.
+const FORMAT: &str = "duplicate-image-finder index";
+
+/// Bumped whenever the layout of the index changes, older indexes have to be rebuilt.
+const VERSION: u64 = 1;
+
+#[derive(Debug, Clone)]
+pub struct HashIndex {
+    /// Algorithm all hashes of the index were computed with.
+    pub algorithm: HashAlgorithm,
+    /// The indexed files, those that might have duplicates have `FileData::verified_hash` set.
+    pub files: Vec<FileData>,
+}
+
+impl HashIndex {
+    /// Index `files`, which were hashed with `options`. Files that share their size and prefix
+    /// hash with another file also get their full contents hashed now, so that the index can be
+    /// searched without reading any of them. Files that fail to hash are left out of the index.
+    pub fn new(
+        files: Vec<FileData>,
+        options: &HashOptions,
+        progress: &dyn Progress,
+    ) -> (Self, Vec<DuplicateFinderError>) {
+        let mut candidates = group_candates(&files);
+        candidates.retain(|_, v| v.len() > 1);
+        let candidates: HashSet<_> = candidates.into_values().flatten().map(|file| file.path.clone()).collect();
+
+        progress.on_phase(Phase::Verifying { files: candidates.len() });
+        let (files, errors) = files.into_par_iter().partition_map(|mut file| {
+            if !candidates.contains(&file.path) {
+                return Either::Left(file);
+            }
+
+            let hash = file.full_hash(options);
+            if hash.is_ok() {
+                progress.on_bytes_read(file.size.unwrap_or(0) as u64);
+            }
+            progress.on_file_hashed(&file.path);
+            match hash {
+                Ok(hash) => {
+                    file.verified_hash = Some(hash);
+                    Either::Left(file)
+                },
+                Err(err) => Either::Right(err),
+            }
+        });
+        progress.on_phase(Phase::Done);
+
+        (Self { algorithm: options.algorithm, files }, errors)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read index {}", path.display()))?;
+
+        Self::from_json(&json::parse(&text)?)
+            .wrap_err_with(|| format!("Invalid index file {}", path.display()))
+    }
+
+    /// Write the index to `path`. Files whose path isn't valid UTF-8 are left out.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_json().to_string())
+            .wrap_err_with(|| format!("Failed to write index {}", path.display()))
+    }
+
+    fn to_json(&self) -> Value {
+        let files = self
+            .files
+            .iter()
+            .filter_map(|file| {
+                let modified = file
+                    .modified
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|time| vec![time.as_secs(), time.subsec_nanos() as u64]);
+                Some(Value::object([
+                    ("path", file.path.to_str()?.into()),
+                    ("size", file.size?.into()),
+                    ("modified", modified.into()),
+                    ("inode", file.inode.map(|(dev, ino)| vec![dev, ino]).into()),
+                    ("file_hash", file.file_hash?.to_string().into()),
+                    ("pixel_hash", file.pixel_hash.map(|h| h.to_string()).into()),
+                    ("verified_hash", file.verified_hash.map(|h| h.to_string()).into()),
+                ]))
+            })
+            .collect();
+
+        Value::object([
+            ("format", FORMAT.into()),
+            ("version", VERSION.into()),
+            ("algorithm", self.algorithm.name().into()),
+            ("files", Value::Array(files)),
+        ])
+    }
+
+    fn from_json(value: &Value) -> Result<Self> {
+        if value.get("format").and_then(Value::as_str) != Some(FORMAT) {
+            bail!("Not an index written by `index build`");
+        }
+
+        // Unlike caches, indexes can't simply be rebuilt on the fly.
+        match value.get("version").and_then(Value::as_u64) {
+            Some(VERSION) => {},
+            Some(version) => bail!("Index has version {version}, but only version {VERSION} is supported. Rebuild it with `index build`"),
+            None => bail!("Missing index version"),
+        }
+
+        let algorithm = value
+            .get("algorithm")
+            .and_then(Value::as_str)
+            .and_then(HashAlgorithm::from_name)
+            .ok_or_else(|| eyre!("Missing or unknown hash algorithm"))?;
+
+        let files = value
+            .get("files")
+            .and_then(Value::as_array)
+            .ok_or_else(|| eyre!("Missing index entries"))?
+            .iter()
+            .map(|file| parse_file(file).ok_or_else(|| eyre!("Malformed index entry")))
+            .collect::<Result<_>>()?;
+
+        Ok(Self { algorithm, files })
+    }
+}
+
+fn parse_file(value: &Value) -> Option<FileData> {
+    let mut file = FileData::from_file(PathBuf::from(value.get("path")?.as_str()?));
+
+    file.size = Some(value.get("size")?.as_u64()? as usize);
+    file.modified = match value.get("modified")? {
+        Value::Null => None,
+        modified => {
+            let modified = modified.as_array()?;
+            Some(UNIX_EPOCH + Duration::new(modified.first()?.as_u64()?, modified.get(1)?.as_u64()? as u32))
+        },
+    };
+    file.inode = match value.get("inode")? {
+        Value::Null => None,
+        inode => {
+            let inode = inode.as_array()?;
+            Some((inode.first()?.as_u64()?, inode.get(1)?.as_u64()?))
+        },
+    };
+    file.file_hash = Some(Digest::from_hex(value.get("file_hash")?.as_str()?)?);
+    file.pixel_hash = parse_digest(value.get("pixel_hash")?)?;
+    file.verified_hash = parse_digest(value.get("verified_hash")?)?;
+
+    Some(file)
+}
+
+/// Parse an optional digest, the outer `None` means it is malformed.
+fn parse_digest(value: &Value) -> Option<Option<Digest>> {
+    match value {
+        Value::Null => Some(None),
+        digest => Digest::from_hex(digest.as_str()?).map(Some),
+    }
+}
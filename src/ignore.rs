@@ -0,0 +1,121 @@
+//! Support for `.gitignore` and `.ignore` files while walking a directory tree.
+//!
+//! Only ignore files inside the scanned roots are read, global excludes and the ignore files of
+//! parent directories are not considered.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{debug, glob::Glob};
+
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// The rules of the ignore files of all directories on the path to the current walk entry.
+#[derive(Default)]
+pub(crate) struct IgnoreStack {
+    frames: Vec<Frame>,
+}
+
+struct Frame {
+    depth: usize,
+    dir: PathBuf,
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    glob: Glob,
+    negated: bool,
+    dir_only: bool,
+}
+
+impl IgnoreStack {
+    /// Whether the entry at `path` and `depth` of a walk is ignored. Has to be called for every
+    /// entry in the order of a depth-first walk, since it loads the ignore files of every
+    /// directory that isn't ignored itself.
+    pub fn is_ignored(&mut self, path: &Path, depth: usize, is_dir: bool) -> bool {
+        while self.frames.last().is_some_and(|frame| frame.depth >= depth) {
+            self.frames.pop();
+        }
+
+        // `.git` itself is never part of a repository's contents.
+        if is_dir && depth > 0 && path.file_name().is_some_and(|name| name == ".git") {
+            return true;
+        }
+
+        if self.matches(path, is_dir) {
+            return true;
+        }
+
+        if is_dir {
+            let rules: Vec<_> = IGNORE_FILES.iter().flat_map(|name| load(&path.join(name))).collect();
+            if !rules.is_empty() {
+                self.frames.push(Frame { depth, dir: path.to_owned(), rules });
+            }
+        }
+
+        false
+    }
+
+    /// Deeper ignore files take precedence, within a file the last matching rule wins.
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        for frame in self.frames.iter().rev() {
+            let Ok(relative) = path.strip_prefix(&frame.dir) else {
+                continue;
+            };
+
+            let rule = frame
+                .rules
+                .iter()
+                .rev()
+                .find(|rule| (is_dir || !rule.dir_only) && rule.glob.matches(relative));
+            if let Some(rule) = rule {
+                return !rule.negated;
+            }
+        }
+
+        false
+    }
+}
+
+fn load(path: &Path) -> Vec<Rule> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+
+    text.lines()
+        .filter_map(|line| {
+            let rule = parse_rule(line);
+            if rule.is_none() && !line.trim().is_empty() && !line.starts_with('#') {
+                debug!("Ignoring invalid pattern '{line}' in {}", path.display());
+            }
+            rule
+        })
+        .collect()
+}
+
+fn parse_rule(line: &str) -> Option<Rule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let (negated, pattern) = match line.strip_prefix('!') {
+        Some(pattern) => (true, pattern),
+        None => (false, line),
+    };
+
+    let (dir_only, pattern) = match pattern.strip_suffix('/') {
+        Some(pattern) => (true, pattern),
+        None => (false, pattern),
+    };
+
+    // A slash anywhere but at the end anchors the pattern to the directory of the ignore file.
+    let glob = match pattern.strip_prefix('/') {
+        Some(pattern) => Glob::anchored(pattern),
+        None => Glob::new(pattern),
+    };
+
+    Some(Rule { glob: glob.ok()?, negated, dir_only })
+}
@@ -0,0 +1,56 @@
+//! Groups of files that are known to be duplicates on purpose, see `--ignore-pairs`.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use duplicate_image_finder::FileData;
+
+use crate::path_from_bytes;
+
+/// Expected duplicates, read from a file listing one path per line with the groups separated by
+/// blank lines, like `--output fdupes` writes them. Paths are matched as they are reported, so a
+/// file that was moved or renamed is no longer part of its group.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreList {
+    /// The group every listed path belongs to.
+    groups: HashMap<PathBuf, usize>,
+}
+
+impl IgnoreList {
+    /// Read the list at `path`, for clap.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|err| format!("Failed to read {path}: {err}"))?;
+        Ok(Self::parse(&bytes))
+    }
+
+    pub fn parse(bytes: &[u8]) -> Self {
+        let mut groups = HashMap::new();
+        let mut group = 0;
+        for line in bytes.split(|&b| b == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.is_empty() {
+                group += 1;
+                continue;
+            }
+            groups.insert(path_from_bytes(line), group);
+        }
+        Self { groups }
+    }
+
+    /// Whether all `files` are listed in the same group, so that they are expected to be
+    /// duplicates of each other. Groups with files that aren't listed are new duplicates.
+    pub fn covers(&self, files: &[&FileData]) -> bool {
+        let mut groups = files.iter().map(|file| self.group(&file.path));
+        match groups.next() {
+            Some(Some(first)) => groups.all(|group| group == Some(first)),
+            _ => false,
+        }
+    }
+
+    fn group(&self, path: &Path) -> Option<usize> {
+        self.groups.get(path).copied()
+    }
+}
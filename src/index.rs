@@ -0,0 +1,201 @@
+//! Metric indexes over perceptual hashes.
+
+use std::collections::{HashMap, HashSet};
+
+/// Finds the hashes within some Hamming distance of a query, see `BkTree` and `LshIndex`.
+pub trait NeighborIndex<T> {
+    fn insert(&mut self, hash: [u8; 32], value: T);
+
+    /// All values whose hash is at most `distance` bits away from `hash`, together with their
+    /// actual distance, in no particular order.
+    fn query_within(&self, hash: &[u8; 32], distance: u64) -> Vec<(u64, &T)>;
+}
+
+/// A BK-tree over 256 bit hashes in Hamming space.
+///
+/// Every child edge is labeled with the distance between parent and child, so a range query only
+/// has to descend into the children whose label is within `distance` of the query's distance to
+/// the current node (triangle inequality).
+pub struct BkTree<T> {
+    nodes: Vec<Node<T>>,
+}
+
+struct Node<T> {
+    hash: [u8; 32],
+    value: T,
+    children: Vec<(u64, usize)>,
+}
+
+impl<T> Default for BkTree<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> BkTree<T> {
+    pub fn new() -> Self {
+        Self { nodes: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn insert(&mut self, hash: [u8; 32], value: T) {
+        let new = self.nodes.len();
+        self.nodes.push(Node { hash, value, children: Vec::new() });
+
+        if new == 0 {
+            return;
+        }
+
+        let mut current = 0;
+        loop {
+            let distance = hamming::distance(&self.nodes[current].hash, &hash);
+
+            match self.nodes[current].children.iter().find(|(d, _)| *d == distance) {
+                Some(&(_, child)) => current = child,
+                None => {
+                    self.nodes[current].children.push((distance, new));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Return all values whose hash is at most `distance` bits away from `hash`, together with
+    /// their actual distance. The order of the results is unspecified.
+    pub fn query_within(&self, hash: &[u8; 32], distance: u64) -> Vec<(u64, &T)> {
+        let mut result = Vec::new();
+
+        if self.nodes.is_empty() {
+            return result;
+        }
+
+        let mut stack = vec![0];
+        while let Some(current) = stack.pop() {
+            let node = &self.nodes[current];
+            let d = hamming::distance(&node.hash, hash);
+
+            if d <= distance {
+                result.push((d, &node.value));
+            }
+
+            let range = d.saturating_sub(distance)..=d + distance;
+            stack.extend(
+                node.children
+                    .iter()
+                    .filter(|(edge, _)| range.contains(edge))
+                    .map(|&(_, child)| child),
+            );
+        }
+
+        result
+    }
+}
+
+impl<T> NeighborIndex<T> for BkTree<T> {
+    fn insert(&mut self, hash: [u8; 32], value: T) {
+        BkTree::insert(self, hash, value);
+    }
+
+    fn query_within(&self, hash: &[u8; 32], distance: u64) -> Vec<(u64, &T)> {
+        BkTree::query_within(self, hash, distance)
+    }
+}
+
+/// Parameters of an `LshIndex`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LshParams {
+    /// Number of bands the bits of the hashes are split into.
+    pub bands: usize,
+    /// Length of the hashes in bytes, see `PerceptualAlgorithm::hash_len`. The remaining bytes
+    /// are ignored.
+    pub hash_len: usize,
+}
+
+/// Locality-sensitive hashing over hashes in Hamming space.
+///
+/// The bits of every hash are split into bands, and only hashes that have at least one band in
+/// common with the query are compared with it. Hashes at most `distance` bits apart always share
+/// a band if there are more bands than `distance`, by the pigeonhole principle. With fewer bands
+/// queries are approximate and may miss some of the hashes within `distance`. More bands find
+/// more matches, but also compare more unrelated hashes, since narrower bands collide more often.
+pub struct LshIndex<T> {
+    /// The bits of every band.
+    masks: Vec<[u8; 32]>,
+    /// Indexes into `entries` of the hashes with the given bits in a band.
+    buckets: HashMap<(usize, [u8; 32]), Vec<usize>>,
+    entries: Vec<([u8; 32], T)>,
+}
+
+impl<T> LshIndex<T> {
+    /// The number of bands is clamped to between 1 and the number of bits of the hashes.
+    pub fn new(params: LshParams) -> Self {
+        let bits = params.hash_len.clamp(1, 32) * 8;
+        let bands = params.bands.clamp(1, bits);
+        let masks = (0..bands)
+            .map(|band| {
+                let mut mask = [0; 32];
+                for bit in band * bits / bands..(band + 1) * bits / bands {
+                    mask[bit / 8] |= 1 << (bit % 8);
+                }
+                mask
+            })
+            .collect();
+
+        Self { masks, buckets: HashMap::new(), entries: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn band_key(&self, band: usize, hash: &[u8; 32]) -> (usize, [u8; 32]) {
+        let mut key = *hash;
+        for (byte, mask) in key.iter_mut().zip(&self.masks[band]) {
+            *byte &= mask;
+        }
+        (band, key)
+    }
+}
+
+impl<T> NeighborIndex<T> for LshIndex<T> {
+    fn insert(&mut self, hash: [u8; 32], value: T) {
+        let new = self.entries.len();
+        self.entries.push((hash, value));
+        for band in 0..self.masks.len() {
+            self.buckets.entry(self.band_key(band, &hash)).or_default().push(new);
+        }
+    }
+
+    fn query_within(&self, hash: &[u8; 32], distance: u64) -> Vec<(u64, &T)> {
+        let mut compared = HashSet::new();
+        let mut result = Vec::new();
+        for band in 0..self.masks.len() {
+            let Some(bucket) = self.buckets.get(&self.band_key(band, hash)) else {
+                continue;
+            };
+
+            for &i in bucket {
+                if !compared.insert(i) {
+                    continue;
+                }
+                let (other, value) = &self.entries[i];
+                let d = hamming::distance(other, hash);
+                if d <= distance {
+                    result.push((d, value));
+                }
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,55 @@
+//! Stopping a scan early on Ctrl-C while keeping what was found so far.
+//!
+//! The first interrupt only sets a flag. Walking, hashing and decoding check it and don't start
+//! on any further file, so the search continues with the files that were already hashed. A
+//! second interrupt exits right away, unless a `Critical` section is running, which is only
+//! told to stop through the flag.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Number of `Critical` sections currently running.
+static CRITICAL: AtomicUsize = AtomicUsize::new(0);
+
+/// Exit status after a second interrupt, as if the process was killed by SIGINT.
+pub const EXIT_INTERRUPTED: u8 = 130;
+
+/// Handle Ctrl-C as described in the module docs. Without signals Ctrl-C keeps exiting at once.
+#[cfg(unix)]
+pub fn install_handler() {
+    extern "C" fn on_interrupt(_signal: libc::c_int) {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) && CRITICAL.load(Ordering::SeqCst) == 0 {
+            // Only async-signal-safe calls are allowed here, so no destructors or output.
+            unsafe { libc::_exit(EXIT_INTERRUPTED.into()) };
+        }
+    }
+
+    unsafe { libc::signal(libc::SIGINT, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+}
+
+#[cfg(not(unix))]
+pub fn install_handler() {}
+
+/// Whether Ctrl-C was pressed, after which no new work should be started.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// While alive, a second interrupt doesn't exit, so that changes to the filesystem are never
+/// cut short. Code inside should check `interrupted` before every change instead.
+#[must_use]
+pub struct Critical(());
+
+impl Critical {
+    pub fn enter() -> Self {
+        CRITICAL.fetch_add(1, Ordering::SeqCst);
+        Self(())
+    }
+}
+
+impl Drop for Critical {
+    fn drop(&mut self) {
+        CRITICAL.fetch_sub(1, Ordering::SeqCst);
+    }
+}
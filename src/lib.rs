@@ -0,0 +1,1881 @@
+pub mod actions;
+pub mod archive;
+pub mod bloom;
+pub mod cache;
+pub mod config;
+pub mod digest;
+pub mod duplicate_dirs;
+pub mod error;
+pub mod glob;
+pub mod hash_index;
+mod ignore;
+pub mod index;
+pub mod interrupt;
+pub mod json;
+pub mod log;
+pub mod perceptual;
+pub mod progress;
+#[cfg(feature = "raw")]
+mod raw;
+pub mod undo;
+
+use std::{
+    cmp::min,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{File, Metadata},
+    io::{self, BufRead, BufReader, Cursor, Read},
+    path::{Path, PathBuf},
+    sync::{Condvar, Mutex, OnceLock},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use memmap2::Mmap;
+use pdqhash::image::{DynamicImage, GenericImageView};
+use rayon::{iter::Either, prelude::*};
+use walkdir::WalkDir;
+
+use crate::{
+    archive::ArchiveMember, cache::HashCache, digest::{Digest, HashAlgorithm}, error::{DuplicateFinderError, Result}, glob::Glob, ignore::IgnoreStack, index::{BkTree, LshIndex, LshParams, NeighborIndex},
+    perceptual::{Distance, PerceptualAlgorithm}, progress::{NoProgress, Phase, Progress}
+};
+
+/// Perceptual hash bits and their quality, see `PerceptualAlgorithm::hash`.
+pub type PerceptualHash = ([u8; 32], f32);
+
+/// Lowercase hex representation of `bytes`.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Format `time` as `YYYY-MM-DDThh:mm:ss` in UTC.
+pub fn format_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Controls what `FileData::hash` computes.
+#[derive(Debug, Clone, Copy)]
+pub struct HashOptions<'a> {
+    /// Also try to decode the file as an image and compute its perceptual hash.
+    pub perception_hash: bool,
+    /// Algorithm used for the perceptual hashes.
+    pub perceptual_algorithm: PerceptualAlgorithm,
+    /// Additionally hash the rotated and mirrored versions of images, see
+    /// `FileData::transformed_hashes`.
+    pub dihedral: bool,
+    /// Additionally hash a central crop of images and the image without a uniform border, see
+    /// `FileData::crop_hashes`.
+    pub match_crops: bool,
+    /// Reuse the hashes of an earlier run for files whose size and mtime didn't change.
+    pub cache: Option<&'a HashCache>,
+    /// Algorithm used for the content hashes.
+    pub algorithm: HashAlgorithm,
+    /// Read all files in chunks instead of memory mapping them.
+    pub no_mmap: bool,
+    /// Compare images by their decoded pixels instead of their bytes for exact duplicates, so
+    /// that copies with different metadata match. See `FileData::pixel_hash`.
+    pub ignore_metadata: bool,
+    /// Hash every frame of animated images instead of only the first, see
+    /// `FileData::frame_hashes`.
+    pub all_frames: bool,
+    /// Number of bytes at the start of files that `FileData::file_hash` covers, 0 hashes the
+    /// whole file. Larger prefixes tell more files apart before the full contents are compared,
+    /// at the cost of reading more of every file.
+    pub prefix_len: usize,
+    /// Fail on files whose size differs from `FileData::size` as recorded when collecting them,
+    /// instead of warning and hashing their current contents. Files that change between hashing
+    /// their prefix and their full contents always fail.
+    pub skip_changed: bool,
+    /// Keep at most this many files open at once while hashing, decoding and verifying them,
+    /// across all threads, 0 doesn't limit them. Defaults to `default_max_open_files`.
+    pub max_open_files: usize,
+    /// Retry opening and memory mapping files this many times after transient errors, like
+    /// timeouts or stale handles on network filesystems. Other errors fail right away.
+    pub io_retries: u32,
+}
+
+impl Default for HashOptions<'_> {
+    fn default() -> Self {
+        Self {
+            perception_hash: false,
+            perceptual_algorithm: PerceptualAlgorithm::default(),
+            dihedral: false,
+            match_crops: false,
+            cache: None,
+            algorithm: HashAlgorithm::default(),
+            no_mmap: false,
+            ignore_metadata: false,
+            all_frames: false,
+            prefix_len: DEFAULT_PREFIX_LEN,
+            skip_changed: false,
+            max_open_files: default_max_open_files(),
+            io_retries: DEFAULT_IO_RETRIES,
+        }
+    }
+}
+
+/// Files larger than this are always read in chunks instead of being memory mapped. They are
+/// also not decoded as images, and archive members larger than this aren't read at all.
+pub const MMAP_LIMIT: u64 = 1 << 30;
+
+const CHUNK_SIZE: usize = 1 << 20;
+/// Default of `HashOptions::prefix_len`.
+pub const DEFAULT_PREFIX_LEN: usize = 4096;
+/// Default of `HashOptions::io_retries`.
+pub const DEFAULT_IO_RETRIES: u32 = 2;
+/// Wait before the first retry of a transient I/O error, doubled for every further one.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct FileData {
+    pub path: PathBuf,
+    pub file_hash: Option<Digest>,
+    pub size: Option<usize>,
+    pub modified: Option<SystemTime>,
+    /// Device and inode number, paths sharing them are hardlinks to the same data.
+    pub inode: Option<(u64, u64)>,
+
+    pub perception_hash: Option<PerceptualHash>,
+    /// Width and height in pixels, recorded with the perceptual hash.
+    pub dimensions: Option<(u32, u32)>,
+    /// PDQ hashes of the other 7 rotations and reflections of the image. Only computed with
+    /// `HashOptions::dihedral`, so that images still match after being rotated or mirrored.
+    pub transformed_hashes: Vec<[u8; 32]>,
+    /// PDQ hashes of the central part of the image and of the image without its border, if it
+    /// has a uniform one. Only computed with `HashOptions::match_crops`, so that cropped copies
+    /// and copies with an added frame still match.
+    pub crop_hashes: Vec<[u8; 32]>,
+    /// Perceptual hashes of the frames after the first one of animated images, the first frame
+    /// is `perception_hash`. Only computed with `HashOptions::all_frames`, so that animations
+    /// match if any of their frames do.
+    pub frame_hashes: Vec<[u8; 32]>,
+    /// Hash of the dimensions and decoded RGBA pixels of images, only computed with
+    /// `HashOptions::ignore_metadata`. Replaces the content hash when comparing exact duplicates.
+    pub pixel_hash: Option<Digest>,
+    /// Result of `full_hash`, if it is already known. Set for files loaded from a `HashIndex`, so
+    /// that they can be grouped without reading them again.
+    pub verified_hash: Option<Digest>,
+    /// Why the file wasn't decoded although it is an image, e.g. in a format that isn't
+    /// supported. Such files have no perceptual hash.
+    pub decode_error: Option<String>,
+    /// Where to read the contents from, if the file is a member of an archive. Its `path` is
+    /// then the one of `ArchiveMember::path`, which doesn't exist.
+    pub archive_member: Option<ArchiveMember>,
+    /// The root the file was found below by `collect`, `None` for files from a list or index.
+    pub root: Option<PathBuf>,
+}
+
+impl FileData {
+    pub fn from_file(path: PathBuf) -> Self {
+        Self {
+            path,
+            file_hash: None,
+            size: None,
+            modified: None,
+            inode: None,
+            perception_hash: None,
+            dimensions: None,
+            transformed_hashes: Vec::new(),
+            crop_hashes: Vec::new(),
+            frame_hashes: Vec::new(),
+            pixel_hash: None,
+            verified_hash: None,
+            decode_error: None,
+            archive_member: None,
+            root: None,
+        }
+    }
+
+    /// A file for the archive `member`, with the modification time of the archive.
+    pub fn from_member(member: ArchiveMember, modified: Option<SystemTime>) -> Self {
+        let mut file = Self::from_file(member.path());
+        file.size = Some(member.size as usize);
+        file.modified = modified;
+        file.archive_member = Some(member);
+        file
+    }
+
+    pub fn hash(&mut self, options: &HashOptions) -> Result<()> {
+        if options.cache.is_some_and(|cache| cache.restore(self, options)) {
+            return Ok(());
+        }
+
+        let permit = OpenPermit::acquire(options.max_open_files);
+
+        if let Some(data) = self.read_member()? {
+            self.size = Some(data.len());
+            let prefix_len = match options.prefix_len {
+                0 => data.len(),
+                len => min(len, data.len()),
+            };
+            self.file_hash = Some(options.algorithm.digest(&data[..prefix_len]));
+            if decode_images(options) {
+                self.image_hashes(&data, options);
+            }
+            return Ok(());
+        }
+
+        let file = self.open(options)?;
+        let metadata = file
+            .metadata()
+            .map_err(DuplicateFinderError::io("stat", &self.path))?;
+
+        let size = metadata.len() as usize;
+        if let Some(expected) = self.size.filter(|&expected| expected != size) {
+            let err = DuplicateFinderError::Changed { path: self.path.clone(), expected, actual: size };
+            if options.skip_changed {
+                return Err(err);
+            }
+            warn!("{err}, hashing its current contents");
+        }
+
+        self.size = Some(size);
+        self.modified = metadata.modified().ok();
+
+        let prefix_len = match options.prefix_len {
+            0 => metadata.len() as usize,
+            len => min(len, metadata.len() as usize),
+        };
+        if !use_mmap(metadata.len(), options) {
+            self.file_hash = Some(self.hash_chunks((&file).take(prefix_len as u64), prefix_len, options)?);
+        } else {
+            let mmap = self.map(&file, options)?;
+            let prefix = min(mmap.len(), prefix_len);
+            self.file_hash = Some(options.algorithm.digest(&mmap[0..prefix]));
+        }
+
+        // `decode` opens the file again with a permit of its own. Holding on to this one would
+        // wait for itself with a limit of one file, or for each other on every thread.
+        drop(file);
+        drop(permit);
+        if decode_images(options) {
+            self.decode(options)?;
+        }
+
+        Ok(())
+    }
+
+    /// Decode the file as an image and compute the hashes requested by `options` from its
+    /// pixels. Files larger than `MMAP_LIMIT` are not decoded.
+    fn decode(&mut self, options: &HashOptions) -> Result<()> {
+        let _permit = OpenPermit::acquire(options.max_open_files);
+        if let Some(data) = self.read_member()? {
+            self.image_hashes(&data, options);
+            return Ok(());
+        }
+
+        let file = self.open(options)?;
+        let len = file
+            .metadata()
+            .map_err(DuplicateFinderError::io("stat", &self.path))?
+            .len();
+
+        if len > MMAP_LIMIT {
+            return Ok(());
+        }
+
+        if use_mmap(len, options) {
+            let mmap = self.map(&file, options)?;
+            self.image_hashes(&mmap, options);
+        } else {
+            let data = std::fs::read(&self.path)
+                .map_err(DuplicateFinderError::io("read", &self.path))?;
+            self.image_hashes(&data, options);
+        }
+
+        Ok(())
+    }
+
+    /// Take over the results of `decode` from `other`, which has the same contents.
+    fn copy_image_hashes(&mut self, other: &FileData) {
+        self.perception_hash = other.perception_hash;
+        self.dimensions = other.dimensions;
+        self.transformed_hashes.clone_from(&other.transformed_hashes);
+        self.crop_hashes.clone_from(&other.crop_hashes);
+        self.frame_hashes.clone_from(&other.frame_hashes);
+        self.pixel_hash = other.pixel_hash;
+        self.decode_error.clone_from(&other.decode_error);
+    }
+
+    /// Hash the entire file contents, used to verify candidates that share a prefix hash. With
+    /// `HashOptions::ignore_metadata` this is the pixel hash for images. Returns
+    /// `verified_hash` instead if it is set.
+    pub fn full_hash(&self, options: &HashOptions) -> Result<Digest> {
+        if let Some(hash) = self.verified_hash {
+            return Ok(hash);
+        }
+
+        if let (true, Some(hash)) = (options.ignore_metadata, self.pixel_hash) {
+            return Ok(hash);
+        }
+
+        let _permit = OpenPermit::acquire(options.max_open_files);
+        if let Some(data) = self.read_member()? {
+            return Ok(options.algorithm.digest(&data));
+        }
+
+        let file = self.open(options)?;
+        let len = file
+            .metadata()
+            .map_err(DuplicateFinderError::io("stat", &self.path))?
+            .len();
+
+        // The prefix hash no longer describes the file, so it can't be compared to the others.
+        if let Some(expected) = self.size.filter(|&expected| expected != len as usize) {
+            return Err(DuplicateFinderError::Changed { path: self.path.clone(), expected, actual: len as usize });
+        }
+
+        if use_mmap(len, options) {
+            let mmap = self.map(&file, options)?;
+            return Ok(options.algorithm.digest(&mmap));
+        }
+
+        self.hash_chunks(file, len as usize, options)
+    }
+
+    /// Hash everything `reader` returns, reading it in chunks of at most `CHUNK_SIZE` bytes.
+    /// `len` is the expected length, which keeps the buffer small for short reads.
+    fn hash_chunks(&self, reader: impl Read, len: usize, options: &HashOptions) -> Result<Digest> {
+        let mut reader = BufReader::with_capacity(CHUNK_SIZE.min(len.max(1)), reader);
+        let mut hasher = options.algorithm.hasher();
+        loop {
+            let chunk = reader
+                .fill_buf()
+                .map_err(DuplicateFinderError::io("read", &self.path))?;
+            if chunk.is_empty() {
+                break;
+            }
+
+            hasher.update(chunk);
+            let read = chunk.len();
+            reader.consume(read);
+        }
+
+        Ok(hasher.finish())
+    }
+
+    /// Number of differing bits between the perceptual hashes of two images, `None` unless both
+    /// have one. If transformed or crop hashes are available the closest variant counts, for
+    /// animated images the closest pair of frames.
+    pub fn perceptual_distance(&self, other: &FileData) -> Option<Distance> {
+        let (own, theirs) = (self.perception_hash?.0, other.perception_hash?.0);
+
+        let transformed = self
+            .transformed_hashes
+            .iter()
+            .chain(&self.crop_hashes)
+            .map(|hash| Distance::between(hash, &theirs))
+            .chain(other.transformed_hashes.iter().chain(&other.crop_hashes).map(|hash| Distance::between(&own, hash)));
+
+        let frames = self
+            .frames()
+            .flat_map(|own| other.frames().map(move |theirs| Distance::between(own, theirs)));
+
+        frames.chain(transformed).min()
+    }
+
+    /// The perceptual hashes of all frames, starting with `perception_hash`.
+    pub fn frames(&self) -> impl Iterator<Item = &[u8; 32]> {
+        self.perception_hash.iter().map(|hash| &hash.0).chain(&self.frame_hashes)
+    }
+
+    fn image_hashes(&mut self, data: &[u8], options: &HashOptions) {
+        // RAW files are TIFF containers whose first image is often just a small thumbnail.
+        #[cfg(feature = "raw")]
+        let img = raw::preview(data).map_or_else(|| pdqhash::image::load_from_memory(data), Ok);
+        #[cfg(not(feature = "raw"))]
+        let img = pdqhash::image::load_from_memory(data);
+
+        let img = match img {
+            Ok(img) => img,
+            Err(err) => {
+                self.decode_error = match unsupported_format(data) {
+                    Some(format) => Some(format!("{format} images are not supported")),
+                    // Files that don't even look like an image aren't worth reporting.
+                    None if pdqhash::image::guess_format(data).is_ok() => Some(err.to_string()),
+                    None => None,
+                };
+                return;
+            },
+        };
+
+        self.dimensions = Some(img.dimensions());
+
+        if options.ignore_metadata {
+            let (width, height) = img.dimensions();
+            let mut hasher = options.algorithm.hasher();
+            hasher.update(&width.to_le_bytes());
+            hasher.update(&height.to_le_bytes());
+            hasher.update(&img.to_rgba8());
+            self.pixel_hash = Some(hasher.finish());
+        }
+
+        if !options.perception_hash {
+            return;
+        }
+
+        let algorithm = options.perceptual_algorithm;
+        self.perception_hash = algorithm.hash(&img);
+        if self.perception_hash.is_none() {
+            return;
+        }
+
+        if options.all_frames {
+            self.frame_hashes = later_frames(data)
+                .iter()
+                .filter_map(|frame| Some(algorithm.hash(frame)?.0))
+                .collect();
+        }
+
+        if options.match_crops {
+            self.crop_hashes = crop_variants(&img)
+                .iter()
+                .filter_map(|img| Some(algorithm.hash(img)?.0))
+                .collect();
+        }
+
+        if !options.dihedral {
+            return;
+        }
+
+        let flipped = img.fliph();
+        let transforms = [
+            img.rotate90(),
+            img.rotate180(),
+            img.rotate270(),
+            flipped.rotate90(),
+            flipped.rotate180(),
+            flipped.rotate270(),
+            flipped,
+        ];
+
+        self.transformed_hashes = transforms
+            .iter()
+            .filter_map(|img| Some(algorithm.hash(img)?.0))
+            .collect();
+    }
+
+    /// The decompressed contents of archive members, `None` for regular files.
+    /// Read the whole archive member, if this is one. Members larger than `MMAP_LIMIT` aren't
+    /// read into memory, and fail instead.
+    fn read_member(&self) -> Result<Option<Vec<u8>>> {
+        self.archive_member
+            .as_ref()
+            .map(|member| {
+                if member.size > MMAP_LIMIT {
+                    let source = io::Error::new(io::ErrorKind::InvalidData, "archive member is too large to read");
+                    return Err(DuplicateFinderError::Io { path: self.path.clone(), action: "read", source });
+                }
+                member.read().map_err(DuplicateFinderError::io("read", &self.path))
+            })
+            .transpose()
+    }
+
+    fn open(&self, options: &HashOptions) -> Result<File> {
+        retry_transient("open", &self.path, options.io_retries, || File::open(&self.path))
+    }
+
+    fn map(&self, file: &File, options: &HashOptions) -> Result<Mmap> {
+        retry_transient("memory map", &self.path, options.io_retries, || unsafe { Mmap::map(file) })
+    }
+}
+
+/// Run the I/O `operation` named `action` on `path`, and run it again up to `retries` times
+/// while it fails with a transient error, waiting longer before every attempt.
+fn retry_transient<T>(action: &'static str, path: &Path, retries: u32, mut operation: impl FnMut() -> io::Result<T>) -> Result<T> {
+    let mut backoff = RETRY_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Ok(value) => {
+                if attempt > 0 {
+                    warn!("Needed {attempt} retries to {action} {}", path.display());
+                }
+                return Ok(value);
+            },
+            Err(err) if attempt < retries && is_transient(&err) => {
+                debug!("Failed to {action} {}, retrying in {backoff:?}: {err}", path.display());
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            },
+            Err(err) => return Err(DuplicateFinderError::io(action, path)(err)),
+        }
+    }
+}
+
+/// Whether the I/O error `err` may go away when trying again, e.g. a timeout on a network
+/// filesystem. Missing files or permissions are permanent.
+fn is_transient(err: &io::Error) -> bool {
+    use io::ErrorKind::*;
+
+    matches!(
+        err.kind(),
+        Interrupted | WouldBlock | TimedOut | StaleNetworkFileHandle | ResourceBusy | ConnectionReset
+            | ConnectionAborted | NetworkDown | HostUnreachable | NetworkUnreachable
+    ) || is_transient_os_error(err)
+}
+
+/// `EIO` from the OS, which network filesystems report for dropped connections.
+#[cfg(unix)]
+fn is_transient_os_error(err: &io::Error) -> bool {
+    // The same on all Unix systems.
+    const EIO: i32 = 5;
+    err.raw_os_error() == Some(EIO)
+}
+
+#[cfg(not(unix))]
+fn is_transient_os_error(_err: &io::Error) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn inode(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Decode all frames after the first one of an animated GIF or PNG. Still images and frames
+/// that fail to decode yield nothing, the first frame is decoded as a regular image.
+fn later_frames(data: &[u8]) -> Vec<DynamicImage> {
+    use pdqhash::image::{
+        codecs::{gif::GifDecoder, png::PngDecoder}, AnimationDecoder, Frame, ImageFormat, ImageResult
+    };
+
+    let frames = match pdqhash::image::guess_format(data) {
+        Ok(ImageFormat::Gif) => GifDecoder::new(Cursor::new(data)).and_then(|d| d.into_frames().collect_frames()),
+        Ok(ImageFormat::Png) => PngDecoder::new(Cursor::new(data)).and_then(|d| {
+            if d.is_apng() { d.apng().into_frames().collect_frames() } else { ImageResult::Ok(Vec::new()) }
+        }),
+        _ => return Vec::new(),
+    };
+
+    frames
+        .unwrap_or_default()
+        .into_iter()
+        .skip(1)
+        .map(|frame: Frame| DynamicImage::ImageRgba8(frame.into_buffer()))
+        .collect()
+}
+
+/// Share of the width and height cut off on every side for the central crop of
+/// `HashOptions::match_crops`.
+const CROP_MARGIN: f32 = 0.1;
+/// Pixels whose channels differ from the color of the top left corner by at most this much are
+/// part of the border.
+const BORDER_TOLERANCE: u8 = 16;
+/// Crops narrower or lower than this many pixels are too small to be hashed meaningfully.
+const MIN_CROP_SIZE: u32 = 32;
+
+/// The central part of `img` and `img` without its uniform border, if it has one and they are
+/// large enough.
+fn crop_variants(img: &DynamicImage) -> Vec<DynamicImage> {
+    let (width, height) = img.dimensions();
+    let (dx, dy) = ((width as f32 * CROP_MARGIN) as u32, (height as f32 * CROP_MARGIN) as u32);
+
+    let mut variants = Vec::new();
+    if (width - 2 * dx).min(height - 2 * dy) >= MIN_CROP_SIZE {
+        variants.push(img.crop_imm(dx, dy, width - 2 * dx, height - 2 * dy));
+    }
+    if let Some((x, y, width, height)) = content_bounds(img).filter(|&(_, _, w, h)| w.min(h) >= MIN_CROP_SIZE) {
+        variants.push(img.crop_imm(x, y, width, height));
+    }
+    variants
+}
+
+/// The bounding box of the pixels that aren't part of a border in the color of the top left
+/// corner, as x, y, width and height. `None` if there is no border or nothing but the border.
+fn content_bounds(img: &DynamicImage) -> Option<(u32, u32, u32, u32)> {
+    let rgb = img.to_rgb8();
+    let (width, height) = rgb.dimensions();
+    let border = rgb.get_pixel(0, 0).0;
+    let is_border = |x, y| {
+        let pixel = rgb.get_pixel(x, y).0;
+        pixel.iter().zip(border).all(|(&a, b)| a.abs_diff(b) <= BORDER_TOLERANCE)
+    };
+    let row_is_border = |y| (0..width).all(|x| is_border(x, y));
+    let column_is_border = |x| (0..height).all(|y| is_border(x, y));
+
+    let top = (0..height).find(|&y| !row_is_border(y))?;
+    let bottom = (0..height).rev().find(|&y| !row_is_border(y))?;
+    let left = (0..width).find(|&x| !column_is_border(x))?;
+    let right = (0..width).rev().find(|&x| !column_is_border(x))?;
+
+    let bounds = (left, top, right - left + 1, bottom - top + 1);
+    (bounds != (0, 0, width, height)).then_some(bounds)
+}
+
+/// Recognize images in formats that the `image` crate can't decode: HEIF containers (as used by
+/// HEIC and AVIF), and WebP images that are lossless, animated or have an alpha channel.
+fn unsupported_format(data: &[u8]) -> Option<&'static str> {
+    let chunk = |range: std::ops::Range<usize>| data.get(range).unwrap_or_default();
+
+    if chunk(4..8) == b"ftyp" {
+        return match chunk(8..12) {
+            b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" => Some("HEIC"),
+            b"avif" | b"avis" => Some("AVIF"),
+            b"mif1" | b"msf1" => Some("HEIF"),
+            _ => None,
+        };
+    }
+
+    if chunk(0..4) == b"RIFF" && chunk(8..12) == b"WEBP" {
+        return match chunk(12..16) {
+            b"VP8L" => Some("Lossless WebP"),
+            b"VP8X" => Some("Extended WebP"),
+            _ => None,
+        };
+    }
+
+    None
+}
+
+/// Whether `FileData::hash` needs to decode images.
+fn decode_images(options: &HashOptions) -> bool {
+    options.perception_hash || options.ignore_metadata
+}
+
+fn use_mmap(len: u64, options: &HashOptions) -> bool {
+    !options.no_mmap && len <= MMAP_LIMIT
+}
+
+/// Default of `HashOptions::max_open_files`, half the soft limit of open files of the process,
+/// which leaves room for the files opened by the walk and everything else. 0 if there is no
+/// limit or it is unknown.
+pub fn default_max_open_files() -> usize {
+    static DEFAULT: OnceLock<usize> = OnceLock::new();
+    *DEFAULT.get_or_init(|| open_files_limit().map_or(0, |limit| (limit / 2).max(1)))
+}
+
+#[cfg(unix)]
+fn open_files_limit() -> Option<usize> {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    // SAFETY: `limit` is a valid rlimit for getrlimit to write to.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 || limit.rlim_cur == libc::RLIM_INFINITY {
+        return None;
+    }
+    usize::try_from(limit.rlim_cur).ok()
+}
+
+#[cfg(not(unix))]
+fn open_files_limit() -> Option<usize> {
+    None
+}
+
+/// Number of files opened through an `OpenPermit` that are still open.
+static OPEN_FILES: Mutex<usize> = Mutex::new(0);
+/// Signalled whenever an `OpenPermit` is dropped.
+static FILE_CLOSED: Condvar = Condvar::new();
+
+/// Permission to open a file while fewer than `HashOptions::max_open_files` are open, for as
+/// long as the permit is held.
+struct OpenPermit {
+    counted: bool,
+}
+
+impl OpenPermit {
+    /// Wait until fewer than `limit` files are open, a `limit` of 0 never waits.
+    fn acquire(limit: usize) -> Self {
+        if limit == 0 {
+            return Self { counted: false };
+        }
+
+        let mut open = OPEN_FILES.lock().unwrap();
+        while *open >= limit {
+            open = FILE_CLOSED.wait(open).unwrap();
+        }
+        *open += 1;
+        Self { counted: true }
+    }
+}
+
+impl Drop for OpenPermit {
+    fn drop(&mut self) {
+        if self.counted {
+            *OPEN_FILES.lock().unwrap() -= 1;
+            FILE_CLOSED.notify_one();
+        }
+    }
+}
+
+/// Find all groups of byte-identical files below `root`, reporting the progress to `progress`.
+pub fn find_exact_duplicates(root: &Path, progress: &dyn Progress) -> Result<Vec<Vec<FileData>>> {
+    let files = retain_size_collisions(collect(&[root], &CollectOptions::default(), progress));
+    let data = hash_files_logged(files, &HashOptions::default(), progress);
+
+    Ok(exact_groups(&data, &HashOptions::default(), progress)
+        .into_values()
+        .map(|group| group.into_iter().cloned().collect())
+        .collect())
+}
+
+/// Find all clusters of images below `root` whose perceptual hashes are at most `max_distance`
+/// bits apart, reporting the progress to `progress`.
+pub fn find_similar_images(root: &Path, max_distance: Distance, progress: &dyn Progress) -> Result<Vec<Vec<FileData>>> {
+    let options = HashOptions { perception_hash: true, ..Default::default() };
+    let data = hash_files_logged(collect(&[root], &CollectOptions::default(), progress), &options, progress);
+
+    Ok(similar_groups(&data, max_distance, Linkage::Single, None, progress)
+        .into_iter()
+        .map(|group| group.into_iter().cloned().collect())
+        .collect())
+}
+
+/// Drop all files whose size is unique, since they can't have an exact duplicate. Errors are
+/// kept so they still get reported when hashing.
+pub fn retain_size_collisions(files: Vec<Result<FileData>>) -> Vec<Result<FileData>> {
+    let mut sizes: HashMap<usize, usize> = HashMap::new();
+    for file in files.iter().flatten() {
+        *sizes.entry(file.size.unwrap_or(0)).or_default() += 1;
+    }
+
+    files
+        .into_iter()
+        .filter(|file| match file {
+            Ok(file) => sizes[&file.size.unwrap_or(0)] > 1,
+            Err(_) => true,
+        })
+        .collect()
+}
+
+/// Hash all successfully collected files. Files that couldn't be collected or hashed are
+/// returned separately as errors, so they can be reported once hashing is done.
+///
+/// Decoding images is by far the most expensive part, so the contents are hashed first and
+/// files with identical contents are only decoded once.
+pub fn hash_files(files: Vec<Result<FileData>>, options: &HashOptions, progress: &dyn Progress) -> (Vec<FileData>, Vec<DuplicateFinderError>) {
+    progress.on_phase(Phase::Hashing { files: files.len() });
+    let contents = HashOptions { perception_hash: false, ignore_metadata: false, cache: None, ..*options };
+
+    // Files that weren't started on before an interrupt are left out.
+    let results: Vec<_> = files
+        .into_par_iter()
+        .filter(|_| !interrupt::interrupted())
+        .map(|file| {
+            let mut file = file?;
+            let restored = options.cache.is_some_and(|cache| cache.restore(&mut file, options));
+            if !restored {
+                file.hash(&contents)?;
+                let size = file.size.unwrap_or(0);
+                progress.on_bytes_read(match options.prefix_len {
+                    0 => size,
+                    len => min(len, size),
+                } as u64);
+            }
+            progress.on_file_hashed(&file.path);
+            Ok((file, !restored && decode_images(options)))
+        })
+        .collect();
+    progress.on_phase(Phase::Done);
+
+    let mut hashed = Vec::with_capacity(results.len());
+    let mut undecoded = Vec::new();
+    let mut errors = Vec::new();
+    for result in results {
+        match result {
+            Ok((file, false)) => hashed.push(file),
+            Ok((file, true)) => undecoded.push(file),
+            Err(err) => errors.push(err),
+        }
+    }
+
+    if !undecoded.is_empty() {
+        let (decoded, decode_errors) = decode_distinct(undecoded, options, progress);
+        hashed.extend(decoded);
+        errors.extend(decode_errors);
+    }
+
+    (hashed, errors)
+}
+
+/// Decode every distinct content among `files` once and share the results with all files that
+/// have the same contents.
+fn decode_distinct(files: Vec<FileData>, options: &HashOptions, progress: &dyn Progress) -> (Vec<FileData>, Vec<DuplicateFinderError>) {
+    // Only files sharing size and prefix hash can be identical, the full hash tells for sure.
+    let mut candidates: HashMap<(usize, Digest), Vec<FileData>> = HashMap::new();
+    for file in files {
+        candidates.entry((file.size.unwrap_or(0), file.file_hash.unwrap())).or_default().push(file);
+    }
+
+    let candidates: Vec<_> = candidates.into_values().collect();
+    let verifying = candidates.iter().filter(|files| files.len() > 1).map(Vec::len).sum();
+    if verifying > 0 {
+        progress.on_phase(Phase::Verifying { files: verifying });
+    }
+
+    let groups: Vec<Vec<FileData>> = candidates
+        .into_par_iter()
+        .flat_map_iter(|files| {
+            if files.len() == 1 {
+                return vec![files];
+            }
+
+            // Files whose contents can't be read are decoded on their own and fail there.
+            let mut identical: HashMap<Digest, Vec<FileData>> = HashMap::new();
+            let mut unreadable = Vec::new();
+            for mut file in files {
+                let hash = file.full_hash(options);
+                if hash.is_ok() && file.verified_hash.is_none() {
+                    progress.on_bytes_read(file.size.unwrap_or(0) as u64);
+                }
+                progress.on_file_hashed(&file.path);
+                match hash {
+                    Ok(hash) => {
+                        // Spares reading the file again when verifying exact duplicates. With
+                        // `ignore_metadata` that is done with the pixel hash instead.
+                        if !options.ignore_metadata {
+                            file.verified_hash = Some(hash);
+                        }
+                        identical.entry(hash).or_default().push(file)
+                    },
+                    Err(_) => unreadable.push(vec![file]),
+                }
+            }
+            identical.into_values().chain(unreadable).collect()
+        })
+        .collect();
+    if verifying > 0 {
+        progress.on_phase(Phase::Done);
+    }
+
+    progress.on_phase(Phase::Decoding { images: groups.len() });
+    let results: Vec<_> = groups
+        .into_par_iter()
+        .filter(|_| !interrupt::interrupted())
+        .map(|mut group| {
+            progress.on_bytes_read(group[0].size.unwrap_or(0) as u64);
+            progress.on_file_hashed(&group[0].path);
+
+            // If a file vanished in the meantime, one of its copies can still be decoded.
+            let mut errors = Vec::new();
+            let Some(source) = group.iter_mut().position(|file| match file.decode(options) {
+                Ok(()) => true,
+                Err(err) => {
+                    errors.push(err);
+                    false
+                },
+            }) else {
+                return (Vec::new(), errors);
+            };
+
+            let mut group = group.split_off(source);
+            let (source, copies) = group.split_first_mut().unwrap();
+            for copy in copies {
+                copy.copy_image_hashes(source);
+            }
+            (group, errors)
+        })
+        .collect();
+    progress.on_phase(Phase::Done);
+
+    let mut decoded = Vec::new();
+    let mut errors = Vec::new();
+    for (files, file_errors) in results {
+        decoded.extend(files);
+        errors.extend(file_errors);
+    }
+
+    (decoded, errors)
+}
+
+fn hash_files_logged(files: Vec<Result<FileData>>, options: &HashOptions, progress: &dyn Progress) -> Vec<FileData> {
+    let (data, errors) = hash_files(files, options, progress);
+    let (denied, errors): (Vec<_>, Vec<_>) = errors.into_iter().partition(DuplicateFinderError::is_permission_denied);
+    if !denied.is_empty() {
+        warn!("Skipped {} inaccessible entries", denied.len());
+    }
+    for err in errors {
+        warn!("Failed to hash file: {err}");
+    }
+    data
+}
+
+/// Group byte-identical files, keyed by their full-file hash. `options` should be the ones the
+/// files were hashed with. Only groups with more than one member are returned.
+///
+/// Files sharing a prefix hash are only candidates, every group is one set of identical files.
+/// A prefix bucket holding two different pairs of duplicates yields two groups.
+pub fn exact_groups<'a>(data: &'a [FileData], options: &HashOptions, progress: &dyn Progress) -> BTreeMap<Digest, Vec<&'a FileData>> {
+    let mut candidates = group_candates(data);
+
+    candidates.retain(|_, v| v.len() > 1);
+
+    let mut groups = verify_candidates(candidates, options, progress);
+
+    groups.retain(|_, v| v.len() > 1);
+
+    groups
+}
+
+/// Find the exact duplicates below `roots` like `collect`, `hash_files` and `exact_groups` do
+/// together, without holding every file in memory at once. A first walk only counts the files of
+/// every size. The roots are then walked again for every batch of colliding sizes that adds up to
+/// about `batch_size` files. The batch is hashed and its duplicate groups are passed to
+/// `on_groups` before the next batch is collected.
+///
+/// Memory use depends on the number of distinct sizes and on `batch_size` instead of the number
+/// of files, at the cost of walking the roots once more per batch. Returns the totals of the run
+/// and the errors of the first walk and of hashing.
+pub fn stream_exact_groups<P: AsRef<Path>>(
+    roots: &[P],
+    collect_options: &CollectOptions,
+    options: &HashOptions,
+    batch_size: usize,
+    progress: &dyn Progress,
+    mut on_groups: impl FnMut(BTreeMap<Digest, Vec<&FileData>>),
+) -> (StreamTotals, Vec<DuplicateFinderError>) {
+    progress.on_phase(Phase::Collecting);
+    let (mut sizes, mut errors) = roots
+        .iter()
+        .map(|root| {
+            walk_root(root.as_ref(), collect_options, progress)
+                .fold(
+                    || (HashMap::new(), Vec::new()),
+                    |(mut sizes, mut errors), file| {
+                        match file {
+                            Ok(file) => *sizes.entry(file.size.unwrap_or(0)).or_default() += 1,
+                            Err(err) => errors.push(err),
+                        }
+                        (sizes, errors)
+                    },
+                )
+                .reduce(|| (HashMap::new(), Vec::new()), merge_counts)
+        })
+        .fold((HashMap::new(), Vec::new()), merge_counts);
+    progress.on_phase(Phase::Done);
+
+    // Files reachable from several roots are counted more than once, which at worst makes their
+    // size look like a collision. They are only hashed once per batch.
+    let mut totals = StreamTotals {
+        found: sizes.values().sum(),
+        bytes: sizes.iter().map(|(&size, &count)| (size * count) as u64).sum(),
+        hashed: 0,
+    };
+    sizes.retain(|_, count| *count > 1);
+
+    let mut colliding: Vec<_> = sizes.iter().map(|(&size, &count)| (size, count)).collect();
+    colliding.sort_unstable();
+
+    let mut batches: Vec<(usize, usize)> = Vec::new();
+    let mut pending = 0;
+    for (size, count) in colliding {
+        match batches.last_mut() {
+            Some((_, end)) if pending + count <= batch_size => {
+                *end = size;
+                pending += count;
+            },
+            _ => {
+                batches.push((size, size));
+                pending = count;
+            },
+        }
+    }
+
+    for (start, end) in batches {
+        if interrupt::interrupted() {
+            break;
+        }
+
+        // Errors were already reported by the first walk.
+        let files = roots
+            .iter()
+            .flat_map(|root| {
+                walk_root(root.as_ref(), collect_options, &NoProgress)
+                    .filter(|file| {
+                        file.as_ref().is_ok_and(|file| {
+                            let size = file.size.unwrap_or(0);
+                            (start..=end).contains(&size) && sizes.contains_key(&size)
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let files = if roots.len() < 2 { files } else { dedup_paths(files) };
+
+        let (files, hash_errors) = hash_files(files, options, progress);
+        errors.extend(hash_errors);
+        totals.hashed += files.len();
+        on_groups(exact_groups(&files, options, progress));
+    }
+
+    (totals, errors)
+}
+
+/// What `stream_exact_groups` found and hashed.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StreamTotals {
+    /// Files found by the first walk.
+    pub found: usize,
+    /// Total size of the files found.
+    pub bytes: u64,
+    /// Files that share their size with another one and were hashed successfully.
+    pub hashed: usize,
+}
+
+fn merge_counts(
+    (mut sizes, mut errors): (HashMap<usize, usize>, Vec<DuplicateFinderError>),
+    (other_sizes, other_errors): (HashMap<usize, usize>, Vec<DuplicateFinderError>),
+) -> (HashMap<usize, usize>, Vec<DuplicateFinderError>) {
+    for (size, count) in other_sizes {
+        *sizes.entry(size).or_default() += count;
+    }
+    errors.extend(other_errors);
+    (sizes, errors)
+}
+
+/// Keep only the first path of every physical file in each group, so hardlinks aren't reported
+/// as duplicates of each other. Groups that are left with a single file are removed. Files
+/// without an inode are always kept.
+pub fn collapse_hardlinks(mut groups: BTreeMap<Digest, Vec<&FileData>>) -> BTreeMap<Digest, Vec<&FileData>> {
+    for files in groups.values_mut() {
+        retain_first_links(files);
+    }
+
+    groups.retain(|_, v| v.len() > 1);
+    groups
+}
+
+/// `collapse_hardlinks` for the clusters of similar images.
+pub fn collapse_hardlinked_clusters(mut clusters: Vec<Vec<&FileData>>) -> Vec<Vec<&FileData>> {
+    for files in &mut clusters {
+        retain_first_links(files);
+    }
+
+    clusters.retain(|files| files.len() > 1);
+    clusters
+}
+
+fn retain_first_links(files: &mut Vec<&FileData>) {
+    let mut seen = HashSet::new();
+    files.retain(|file| file.inode.is_none_or(|inode| seen.insert(inode)));
+}
+
+/// Find the files in `data` that are byte-identical to a file in `reference`, together with their
+/// full-file hash and the reference files they duplicate. Duplicates within only one of the sets
+/// are not reported, neither are files of `data` that are files of `reference` themselves, as
+/// when the reference is below a root.
+pub fn reference_duplicates<'a>(
+    reference: &'a [FileData],
+    data: &'a [FileData],
+    options: &HashOptions,
+    progress: &dyn Progress,
+) -> Vec<(Digest, &'a FileData, Vec<&'a FileData>)> {
+    let mut known = group_candates(reference);
+
+    // A reference below one of the roots is scanned too, its files would match themselves.
+    let inodes: HashSet<_> = reference.iter().filter_map(|file| file.inode).collect();
+    let paths: HashSet<_> = reference
+        .iter()
+        .filter(|file| file.inode.is_none())
+        .filter_map(|file| std::fs::canonicalize(&file.path).ok())
+        .collect();
+    let in_reference = |file: &FileData| match file.inode {
+        Some(inode) => inodes.contains(&inode),
+        None => std::fs::canonicalize(&file.path).is_ok_and(|path| paths.contains(&path)),
+    };
+
+    let candidates: Vec<_> = data
+        .iter()
+        .filter(|file| known.contains_key(&candidate_key(file)) && !in_reference(file))
+        .collect();
+
+    let hit: HashSet<_> = candidates.iter().map(|file| candidate_key(file)).collect();
+    known.retain(|key, _| hit.contains(key));
+
+    let known = verify_candidates(known, options, progress);
+    let candidates = group_full_hashes(candidates, options, progress);
+
+    let mut matches: Vec<_> = candidates
+        .iter()
+        .filter_map(|(&hash, files)| Some((hash, files, known.get(&hash)?)))
+        .flat_map(|(hash, files, originals)| files.iter().map(move |&file| (hash, file, originals.clone())))
+        .collect();
+    matches.sort_by(|a, b| a.1.path.cmp(&b.1.path));
+
+    matches
+}
+
+/// Hash the full contents of all `files` and store it as their `verified_hash`, e.g. to check
+/// them against a `bloom::BloomFilter`. Files that fail to hash are left out.
+pub fn full_hash_files(
+    files: Vec<FileData>,
+    options: &HashOptions,
+    progress: &dyn Progress,
+) -> (Vec<FileData>, Vec<DuplicateFinderError>) {
+    progress.on_phase(Phase::Verifying { files: files.len() });
+    let (files, errors) = files.into_par_iter().partition_map(|mut file| {
+        let hash = file.full_hash(options);
+        if hash.is_ok() && file.verified_hash.is_none() {
+            progress.on_bytes_read(file.size.unwrap_or(0) as u64);
+        }
+        progress.on_file_hashed(&file.path);
+        match hash {
+            Ok(hash) => {
+                file.verified_hash = Some(hash);
+                Either::Left(file)
+            },
+            Err(err) => Either::Right(err),
+        }
+    });
+    progress.on_phase(Phase::Done);
+
+    (files, errors)
+}
+
+/// Bytes that would be freed by keeping only the first file of a group of exact duplicates.
+/// Their sizes only differ for images compared with `HashOptions::ignore_metadata`. Hardlinks
+/// to a file that was already counted free nothing, as with `--count-hardlinks`.
+pub fn reclaimable_bytes(group: &[&FileData]) -> u64 {
+    let mut seen = HashSet::new();
+    group
+        .iter()
+        .filter(|f| f.inode.is_none_or(|inode| seen.insert(inode)))
+        .skip(1)
+        .map(|f| f.size.unwrap_or(0) as u64)
+        .sum()
+}
+
+/// How `similar_groups` builds clusters from the pairs of similar images.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Linkage {
+    /// Images connected through a chain of similar images form a cluster, even if the ends of
+    /// the chain look quite different
+    #[default]
+    Single,
+    /// All images of a cluster are similar to each other. Slower for large groups of images
+    /// connected through chains
+    Complete,
+}
+
+/// Cluster images whose perceptual hashes are at most `max_distance` bits apart, according to
+/// `linkage`. Files without a perceptual hash are ignored and singletons are omitted. Images with
+/// transformed or crop hashes also match if any of these variants is close enough, at the cost of
+/// one query per variant. Likewise animated images with frame hashes match if any of their frames do.
+/// Images are compared through a BK-tree, or with `lsh` through an `LshIndex`, which is faster
+/// for large libraries but may miss matches unless it has more bands than `max_distance`.
+/// Sets of identical images, like exact copies, are compared as one and all join its clusters.
+pub fn similar_groups<'a>(
+    data: &'a [FileData],
+    max_distance: Distance,
+    linkage: Linkage,
+    lsh: Option<LshParams>,
+    progress: &dyn Progress,
+) -> Vec<Vec<&'a FileData>> {
+    // Only one representative of every set of identical images is compared, the clusters are
+    // the same for all of them.
+    let copies = identical_images(data);
+    let images: Vec<_> = copies.iter().map(|set| set[0]).collect();
+
+    let pairs = match lsh {
+        Some(params) => similar_pairs(&images, LshIndex::new(params), max_distance, progress),
+        None => similar_pairs(&images, BkTree::new(), max_distance, progress),
+    };
+
+    let mut sets = DisjointSet::new(images.len());
+    for &(i, j, _) in &pairs {
+        sets.union(i, j);
+    }
+
+    let mut components: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for i in 0..images.len() {
+        components.entry(sets.find(i)).or_default().push(i);
+    }
+
+    // Single representatives are kept, their copies still form a cluster.
+    let components = components.into_values();
+    let clusters: Vec<Vec<usize>> = match linkage {
+        Linkage::Single => components.collect(),
+        Linkage::Complete => {
+            // A pair may be found several times through transforms or frames, the closest counts.
+            let mut distances: HashMap<(usize, usize), u64> = HashMap::new();
+            for (i, j, distance) in pairs {
+                let entry = distances.entry((i.min(j), i.max(j))).or_insert(distance);
+                *entry = (*entry).min(distance);
+            }
+            components.flat_map(|members| complete_linkage(&members, &distances)).collect()
+        },
+    };
+
+    let mut clusters: Vec<Vec<&FileData>> = clusters
+        .into_iter()
+        .map(|c| c.into_iter().flat_map(|i| copies[i].iter().copied()).collect())
+        .filter(|c: &Vec<_>| c.len() > 1)
+        .collect();
+    for cluster in &mut clusters {
+        cluster.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    clusters.sort_by(|a, b| a[0].path.cmp(&b[0].path));
+
+    clusters
+}
+
+/// The images in `data` grouped into sets whose hashes, including those of their frames and
+/// variants, are all identical, like exact copies. Each set starts with its representative, the
+/// image with the shortest path, and the sets are ordered by the path of their representative.
+fn identical_images(data: &[FileData]) -> Vec<Vec<&FileData>> {
+    type Hashes<'a> = (Vec<&'a [u8; 32]>, &'a [[u8; 32]], &'a [[u8; 32]]);
+
+    let mut sets: HashMap<Hashes, Vec<&FileData>> = HashMap::new();
+    for image in data.iter().filter(|o| o.perception_hash.is_some()) {
+        let hashes = (image.frames().collect(), &image.transformed_hashes[..], &image.crop_hashes[..]);
+        sets.entry(hashes).or_default().push(image);
+    }
+
+    let mut sets: Vec<_> = sets.into_values().collect();
+    for set in &mut sets {
+        set.sort_by(|a, b| (a.path.as_os_str().len(), &a.path).cmp(&(b.path.as_os_str().len(), &b.path)));
+    }
+    sets.sort_by(|a, b| a[0].path.cmp(&b[0].path));
+    sets
+}
+
+/// Group images whose perceptual hashes start with the same `bits` bits, as a fast first pass
+/// over large libraries. Nothing is compared pairwise, so this only finds exact and very close
+/// matches: two hashes that differ in any of their first `bits` bits end up in different groups,
+/// however close they are. Transformed, crop and frame hashes are bucketed like the main hash.
+/// Files without a perceptual hash are ignored and singletons are omitted.
+pub fn prefix_groups(data: &[FileData], bits: usize) -> Vec<Vec<&FileData>> {
+    let mut images: Vec<_> = data.iter().filter(|o| o.perception_hash.is_some()).collect();
+    images.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut buckets: HashMap<[u8; 32], usize> = HashMap::new();
+    let mut sets = DisjointSet::new(images.len());
+    for (i, image) in images.iter().enumerate() {
+        for hash in image.frames().chain(&image.transformed_hashes).chain(&image.crop_hashes) {
+            let first = *buckets.entry(hash_prefix(hash, bits)).or_insert(i);
+            sets.union(first, i);
+        }
+    }
+
+    // Members are ordered by path, since `images` is sorted.
+    let mut components: BTreeMap<usize, Vec<&FileData>> = BTreeMap::new();
+    for (i, image) in images.iter().enumerate() {
+        components.entry(sets.find(i)).or_default().push(image);
+    }
+
+    let mut clusters: Vec<_> = components.into_values().filter(|c| c.len() > 1).collect();
+    clusters.sort_by(|a, b| a[0].path.cmp(&b[0].path));
+
+    clusters
+}
+
+/// The first `bits` bits of `hash`, with the remaining bits cleared.
+fn hash_prefix(hash: &[u8; 32], bits: usize) -> [u8; 32] {
+    let mut prefix = [0; 32];
+    let bytes = bits.min(256) / 8;
+    prefix[..bytes].copy_from_slice(&hash[..bytes]);
+    if let Some(partial) = prefix.get_mut(bytes) {
+        *partial = hash[bytes] & !(0xff >> (bits % 8));
+    }
+    prefix
+}
+
+/// The pairs of indexes into `images` whose hashes are at most `max_distance` bits apart, with
+/// their distance, found with `index`. Pairs may appear several times through transforms or
+/// frames.
+fn similar_pairs(
+    images: &[&FileData],
+    mut index: impl NeighborIndex<usize> + Sync,
+    max_distance: Distance,
+    progress: &dyn Progress,
+) -> Vec<(usize, usize, u64)> {
+    for (i, image) in images.iter().enumerate() {
+        for &hash in image.frames() {
+            index.insert(hash, i);
+        }
+    }
+
+    // Queries are independent, so run them in parallel and only merge the resulting pairs
+    // afterwards. The clusters don't depend on the order in which the pairs are merged.
+    progress.on_phase(Phase::Comparing { images: images.len() });
+    let pairs = images
+        .par_iter()
+        .enumerate()
+        .flat_map_iter(|(i, image)| {
+            progress.on_image_compared(&image.path);
+            image
+                .frames()
+                .chain(&image.transformed_hashes)
+                .chain(&image.crop_hashes)
+                .flat_map(|hash| index.query_within(hash, max_distance.0))
+                .filter(move |&(_, &j)| j != i)
+                .map(move |(distance, &j)| (i, j, distance))
+        })
+        .collect();
+    progress.on_phase(Phase::Done);
+
+    pairs
+}
+
+/// Split a connected component of the similarity graph into clusters whose members are all
+/// pairwise similar, by repeatedly merging the two clusters whose most distant members are
+/// closest. `distances` holds every similar pair, keyed with the smaller index first.
+fn complete_linkage(members: &[usize], distances: &HashMap<(usize, usize), u64>) -> Vec<Vec<usize>> {
+    if members.len() <= 2 {
+        return vec![members.to_vec()];
+    }
+
+    let n = members.len();
+    let mut clusters: Vec<Option<Vec<usize>>> = members.iter().map(|&i| Some(vec![i])).collect();
+
+    // Distance between the most distant members of two clusters, `None` if any pair of their
+    // members isn't similar.
+    let mut linkage: Vec<Vec<Option<u64>>> = members
+        .iter()
+        .map(|&i| members.iter().map(|&j| distances.get(&(i.min(j), i.max(j))).copied()).collect())
+        .collect();
+
+    loop {
+        let closest = (0..n)
+            .filter(|&a| clusters[a].is_some())
+            .flat_map(|a| (a + 1..n).map(move |b| (a, b)))
+            .filter(|&(_, b)| clusters[b].is_some())
+            .filter_map(|(a, b)| Some((linkage[a][b]?, a, b)))
+            .min();
+        let Some((_, a, b)) = closest else {
+            break;
+        };
+
+        let merged = clusters[b].take().unwrap();
+        clusters[a].as_mut().unwrap().extend(merged);
+        let merged: Vec<_> = linkage[a].iter().zip(&linkage[b]).map(|(x, y)| x.zip(*y).map(|(x, y)| x.max(y))).collect();
+        for (row, &distance) in linkage.iter_mut().zip(&merged) {
+            row[a] = distance;
+        }
+        linkage[a] = merged;
+    }
+
+    clusters
+        .into_iter()
+        .flatten()
+        .map(|mut cluster| {
+            cluster.sort_unstable();
+            cluster
+        })
+        .collect()
+}
+
+/// Find the images whose perceptual hashes are at most `max_distance` bits away from the one of
+/// `query`, sorted by ascending distance and then path. `query` itself is excluded in case it is
+/// part of `data`, even under a different path.
+pub fn nearest_images<'a>(query: &FileData, data: &'a [FileData], max_distance: Distance) -> Vec<(Distance, &'a FileData)> {
+    if query.perception_hash.is_none() {
+        return Vec::new();
+    }
+
+    let mut tree = BkTree::new();
+    for image in data {
+        for &hash in image.frames() {
+            tree.insert(hash, image);
+        }
+    }
+
+    // With transformed, crop or frame hashes an image may be found several times, the closest
+    // one counts.
+    let mut distances: HashMap<&Path, (Distance, &FileData)> = HashMap::new();
+    for query_hash in query.frames().chain(&query.transformed_hashes).chain(&query.crop_hashes) {
+        for (distance, &image) in tree.query_within(query_hash, max_distance.0) {
+            let distance = Distance(distance);
+            let entry = distances.entry(image.path.as_path()).or_insert((distance, image));
+            entry.0 = entry.0.min(distance);
+        }
+    }
+
+    let own_path = std::fs::canonicalize(&query.path).ok();
+    let mut matches: Vec<_> = distances
+        .into_values()
+        .filter(|(_, image)| own_path.is_none() || std::fs::canonicalize(&image.path).ok() != own_path)
+        .collect();
+    matches.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.path.cmp(&b.1.path)));
+
+    matches
+}
+
+/// Union-find over indices, used to merge similar images into connected clusters.
+struct DisjointSet {
+    parent: Vec<usize>,
+    rank: Vec<u8>,
+}
+
+impl DisjointSet {
+    fn new(len: usize) -> Self {
+        Self {
+            parent: (0..len).collect(),
+            rank: vec![0; len],
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+
+        match self.rank[a].cmp(&self.rank[b]) {
+            std::cmp::Ordering::Less => self.parent[a] = b,
+            std::cmp::Ordering::Greater => self.parent[b] = a,
+            std::cmp::Ordering::Equal => {
+                self.parent[b] = a;
+                self.rank[a] += 1;
+            }
+        }
+    }
+}
+
+/// Files can only be identical if they have the same size and prefix hash, or, when ignoring
+/// metadata, images if they have the same pixel hash.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum CandidateKey {
+    Content(usize, Digest),
+    Pixels(Digest),
+}
+
+fn candidate_key(file: &FileData) -> CandidateKey {
+    match file.pixel_hash {
+        Some(hash) => CandidateKey::Pixels(hash),
+        None => CandidateKey::Content(file.size.unwrap_or(0), file.file_hash.unwrap()),
+    }
+}
+
+fn group_candates<'a>(items: impl IntoIterator<Item=&'a FileData>) -> BTreeMap<CandidateKey, Vec<&'a FileData>> {
+    let mut map: BTreeMap<CandidateKey, Vec<&'a FileData>> = BTreeMap::new();
+
+    for item in items {
+        map.entry(candidate_key(item)).or_default().push(item);
+    }
+
+    map
+}
+
+/// Re-hash the full contents of every candidate and regroup them, so that files which merely share
+/// a prefix hash don't end up in the same group.
+fn verify_candidates<'a>(
+    candidates: BTreeMap<CandidateKey, Vec<&'a FileData>>,
+    options: &HashOptions,
+    progress: &dyn Progress,
+) -> BTreeMap<Digest, Vec<&'a FileData>> {
+    group_full_hashes(candidates.into_values().flatten().collect(), options, progress)
+}
+
+fn group_full_hashes<'a>(
+    files: Vec<&'a FileData>,
+    options: &HashOptions,
+    progress: &dyn Progress,
+) -> BTreeMap<Digest, Vec<&'a FileData>> {
+    progress.on_phase(Phase::Verifying { files: files.len() });
+
+    let hashed: Vec<_> = files
+        .into_par_iter()
+        .filter_map(|file| {
+            let hash = file.full_hash(options);
+            if hash.is_ok() && file.verified_hash.is_none() {
+                progress.on_bytes_read(file.size.unwrap_or(0) as u64);
+            }
+            progress.on_file_hashed(&file.path);
+            match hash {
+                Ok(hash) => Some((hash, file)),
+                Err(err) => {
+                    warn!("Failed to hash file: {err}");
+                    None
+                }
+            }
+        })
+        .collect();
+    progress.on_phase(Phase::Done);
+
+    let mut map: BTreeMap<Digest, Vec<&'a FileData>> = BTreeMap::new();
+
+    for (hash, file) in hashed {
+        map.entry(hash).or_default().push(file);
+    }
+
+    map
+}
+
+/// Restricts which files `collect` returns.
+#[derive(Debug, Default, Clone)]
+pub struct CollectOptions {
+    /// Only collect files with one of these extensions, compared case-insensitively.
+    pub extensions: Option<Vec<String>>,
+    /// Skip files with one of these extensions, compared case-insensitively.
+    pub exclude_extensions: Option<Vec<String>>,
+    /// Skip files smaller than this many bytes.
+    pub min_size: Option<u64>,
+    /// Skip files larger than this many bytes.
+    pub max_size: Option<u64>,
+    /// Also collect empty files. They are skipped by default, as they would all be reported as
+    /// duplicates of each other.
+    pub include_empty: bool,
+    /// If not empty, only collect files matching one of these patterns.
+    pub include: Vec<Glob>,
+    /// Skip files and whole directories matching one of these patterns. Takes precedence over
+    /// `include`.
+    pub exclude: Vec<Glob>,
+    /// Skip files and directories ignored by `.gitignore` or `.ignore` files, as well as `.git`
+    /// directories.
+    pub respect_gitignore: bool,
+    /// Also collect hidden files and descend into hidden directories, those whose name starts
+    /// with `.`. Roots are walked even if they are hidden themselves.
+    pub hidden: bool,
+    /// Descend into symlinked directories and collect symlinked files. Every directory is
+    /// visited only once, which also protects against symlink cycles. Without it, symlinks below
+    /// the roots are skipped, so that a file isn't collected next to the links to it.
+    pub follow_symlinks: bool,
+    /// Don't descend more than this many directories below the roots, 1 only collects the
+    /// direct children of every root.
+    pub max_depth: Option<usize>,
+    /// Skip files less than this many directories below the roots. Roots that are files
+    /// themselves have a depth of 0.
+    pub min_depth: usize,
+    /// Additionally collect the members of zip and tar archives as files of their own, see
+    /// `FileData::archive_member`. Archives are inspected even if their extension or size isn't
+    /// accepted, only excluding them by pattern also leaves out their members.
+    pub inspect_archives: bool,
+}
+
+impl CollectOptions {
+    fn accepts(&self, path: &Path) -> bool {
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let matches = |list: &[String]| {
+            list.iter()
+                .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(&extension))
+        };
+
+        if let Some(extensions) = &self.extensions {
+            if !matches(extensions) {
+                return false;
+            }
+        }
+
+        if let Some(excluded) = &self.exclude_extensions {
+            if matches(excluded) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Check the include and exclude patterns against `path`, relative to the root.
+    fn accepts_path(&self, path: &Path) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|glob| glob.matches(path)))
+            && !self.exclude.iter().any(|glob| glob.matches(path))
+    }
+
+    fn accepts_size(&self, size: u64) -> bool {
+        self.min_size.is_none_or(|min| size >= min) && self.max_size.is_none_or(|max| size <= max)
+    }
+
+    /// Whether to collect the members of the file at `path`, `relative` to the root.
+    fn inspects(&self, path: &Path, relative: &Path) -> bool {
+        self.inspect_archives && archive::is_archive(path) && !self.exclude.iter().any(|glob| glob.matches(relative))
+    }
+}
+
+/// The members of the archive at `path` below `root` that pass `options`, as files of their own.
+/// Archives that can't be read are reported and skipped.
+fn archive_members(path: &Path, root: &Path, options: &CollectOptions, progress: &dyn Progress) -> Vec<Result<FileData>> {
+    let members = match archive::members(path) {
+        Ok(members) => members,
+        Err(err) => {
+            warn!("Skipping the members of {}: {err}", path.display());
+            return Vec::new();
+        },
+    };
+    let modified = std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+
+    members
+        .into_iter()
+        .filter_map(|member| {
+            let path = member.path();
+            if !options.accepts(&path) {
+                debug!("Skipping {}: excluded extension", path.display());
+                return None;
+            }
+            if !options.accepts_path(path.strip_prefix(root).unwrap_or(&path)) {
+                debug!("Skipping {}: excluded by pattern", path.display());
+                return None;
+            }
+            if member.size == 0 && !options.include_empty {
+                debug!("Skipping {}: empty", path.display());
+                return None;
+            }
+            if !options.accepts_size(member.size) {
+                debug!("Skipping {}: size out of range", path.display());
+                return None;
+            }
+
+            progress.on_file_discovered(&path);
+            Some(Ok(FileData::from_member(member, modified)))
+        })
+        .collect()
+}
+
+/// Collect the regular files among `paths` that pass `options`, for a list of files produced by
+/// another program instead of a directory walk. Paths that don't exist or aren't regular files
+/// are reported and skipped, as are repeated paths.
+pub fn collect_paths(paths: impl IntoIterator<Item = PathBuf>, options: &CollectOptions) -> Vec<Result<FileData>> {
+    let mut seen = HashSet::new();
+
+    paths
+        .into_iter()
+        .flat_map(|path| {
+            let metadata = match std::fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    warn!("Skipping {}: {err}", path.display());
+                    return Vec::new();
+                },
+            };
+            if !metadata.is_file() {
+                warn!("Skipping {}: not a regular file", path.display());
+                return Vec::new();
+            }
+
+            if !seen.insert(path.canonicalize().unwrap_or_else(|_| path.clone())) {
+                return Vec::new();
+            }
+
+            // Archives are inspected even if they are left out themselves.
+            let mut files = match options.inspects(&path, &path) {
+                true => archive_members(&path, &path, options, &NoProgress),
+                false => Vec::new(),
+            };
+
+            if !options.accepts(&path) {
+                debug!("Skipping {}: excluded extension", path.display());
+                return files;
+            }
+            if !options.accepts_path(&path) {
+                debug!("Skipping {}: excluded by pattern", path.display());
+                return files;
+            }
+            if metadata.len() == 0 && !options.include_empty {
+                debug!("Skipping {}: empty", path.display());
+                return files;
+            }
+            if !options.accepts_size(metadata.len()) {
+                debug!("Skipping {}: size out of range", path.display());
+                return files;
+            }
+
+            let mut file = FileData::from_file(path);
+            file.size = Some(metadata.len() as usize);
+            file.modified = metadata.modified().ok();
+            file.inode = inode(&metadata);
+            files.insert(0, Ok(file));
+            files
+        })
+        .collect()
+}
+
+/// Walk all `roots` and collect the regular files below them that pass `options`. Files reachable
+/// from more than one root are only returned once. Every file found is reported to `progress`.
+pub fn collect<P: AsRef<Path>>(roots: &[P], options: &CollectOptions, progress: &dyn Progress) -> Vec<Result<FileData>> {
+    progress.on_phase(Phase::Collecting);
+    let files: Vec<_> = roots
+        .iter()
+        .flat_map(|root| collect_root(root.as_ref(), options, progress))
+        .collect();
+    progress.on_phase(Phase::Done);
+
+    if roots.len() < 2 {
+        return files;
+    }
+
+    dedup_paths(files)
+}
+
+/// Drop the files whose path, after resolving symlinks, was already seen earlier in `files`.
+fn dedup_paths(files: Vec<Result<FileData>>) -> Vec<Result<FileData>> {
+    let mut seen = HashSet::new();
+    files
+        .into_iter()
+        .filter(|file| match file {
+            Ok(file) => seen.insert(file.path.canonicalize().unwrap_or_else(|_| file.path.clone())),
+            Err(_) => true,
+        })
+        .collect()
+}
+
+fn collect_root(path: &Path, options: &CollectOptions, progress: &dyn Progress) -> Vec<Result<FileData>> {
+    walk_root(path, options, progress).collect()
+}
+
+/// The files below `path` that pass `options`, as they are found. Their `root` is `path`.
+fn walk_root<'a>(
+    path: &'a Path,
+    options: &'a CollectOptions,
+    progress: &'a dyn Progress,
+) -> impl ParallelIterator<Item = Result<FileData>> + 'a {
+    let mut visited = HashSet::new();
+    let mut ignores = IgnoreStack::default();
+
+    WalkDir::new(path)
+        .follow_links(options.follow_symlinks)
+        .min_depth(options.min_depth)
+        .max_depth(options.max_depth.unwrap_or(usize::MAX))
+        .into_iter()
+        .filter_entry(move |elem| {
+            let is_dir = elem.depth() > 0 && elem.file_type().is_dir();
+
+            // Checked first, so nothing inside an excluded directory is read, not even its
+            // ignore files.
+            let relative = elem.path().strip_prefix(path).unwrap_or(elem.path());
+            let excluded = || options.exclude.iter().find(|glob| glob.matches_dir(relative));
+            if let Some(glob) = is_dir.then(excluded).flatten() {
+                debug!("Skipping {}: excluded by {}", elem.path().display(), glob.as_str());
+                return false;
+            }
+
+            if !options.hidden && elem.depth() > 0 && is_hidden(elem) {
+                debug!("Skipping {}: hidden", elem.path().display());
+                return false;
+            }
+
+            if options.respect_gitignore && ignores.is_ignored(elem.path(), elem.depth(), elem.file_type().is_dir()) {
+                debug!("Skipping {}: ignored", elem.path().display());
+                return false;
+            }
+
+            if !is_dir || !options.follow_symlinks {
+                return true;
+            }
+
+            // Entries that can't be stat'ed are passed on and reported while hashing.
+            match elem.metadata().ok().as_ref().and_then(inode) {
+                Some(inode) => visited.insert(inode),
+                None => true,
+            }
+        })
+        // Stop walking on an interrupt, nothing is hashed after it anyway.
+        .take_while(|_| !interrupt::interrupted())
+        .par_bridge()
+        .flat_map_iter(move |elem| {
+            // Archives are inspected even if they are left out themselves.
+            let members = match &elem {
+                Ok(elem) if !elem.file_type().is_dir() && options.inspects(elem.path(), elem.path().strip_prefix(path).unwrap_or(elem.path())) => {
+                    archive_members(elem.path(), path, options, progress)
+                },
+                _ => Vec::new(),
+            };
+            walk_entry(path, elem, options, progress).into_iter().chain(members)
+        })
+        .map(move |file| {
+            file.map(|mut file| {
+                file.root = Some(path.to_owned());
+                file
+            })
+        })
+}
+
+/// Whether the name of `elem` starts with `.`, like `.DS_Store` or `.thumbnails`.
+fn is_hidden(elem: &walkdir::DirEntry) -> bool {
+    elem.file_name().as_encoded_bytes().starts_with(b".")
+}
+
+/// The file for the entry `elem` found below `root`, if it passes `options`.
+fn walk_entry(
+    root: &Path,
+    elem: walkdir::Result<walkdir::DirEntry>,
+    options: &CollectOptions,
+    progress: &dyn Progress,
+) -> Option<Result<FileData>> {
+    let elem = match elem {
+        Ok(e) => e,
+        Err(err) => return Some(Err(err.into())),
+    };
+
+    let path = elem.path();
+
+    if elem.file_type().is_dir() {
+        return None;
+    }
+
+    if !options.accepts(path) {
+        debug!("Skipping {}: excluded extension", path.display());
+        return None;
+    }
+
+    if !options.accepts_path(path.strip_prefix(root).unwrap_or(path)) {
+        debug!("Skipping {}: excluded by pattern", path.display());
+        return None;
+    }
+
+    // A link would be reported as a duplicate of its target, and cleaning it up could remove the
+    // target while keeping the link. Roots that are links were given explicitly.
+    if elem.path_is_symlink() && elem.depth() > 0 && !options.follow_symlinks {
+        debug!("Skipping {}: symlink", path.display());
+        return None;
+    }
+
+    // The entry only resolves symlinks when following them, roots are resolved in any case.
+    let metadata = if elem.path_is_symlink() {
+        std::fs::metadata(path)
+    } else {
+        elem.metadata().map_err(io::Error::from)
+    };
+    let metadata = match metadata {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            debug!("Skipping {}: {err}", path.display());
+            return None;
+        },
+    };
+    if !metadata.is_file() {
+        return None;
+    }
+    if metadata.len() == 0 && !options.include_empty {
+        debug!("Skipping {}: empty", path.display());
+        return None;
+    }
+    if !options.accepts_size(metadata.len()) {
+        debug!("Skipping {}: size out of range", path.display());
+        return None;
+    }
+
+    progress.on_file_discovered(path);
+
+    let mut file = FileData::from_file(path.to_owned());
+    file.size = Some(metadata.len() as usize);
+    file.modified = metadata.modified().ok();
+    file.inode = inode(&metadata);
+    Some(Ok(file))
+}
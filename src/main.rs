@@ -1,243 +1,2255 @@
 use std::{
-    cmp::min, collections::BTreeMap, fs::File, path::{Path, PathBuf}, ptr::addr_eq
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    error::Error,
+    ffi::OsString,
+    fs,
+    io::{self, BufRead, IsTerminal, Read, Write},
+    mem,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Instant,
 };
 
-use clap::Parser;
-use color_eyre::eyre::{Context, Result};
-use indicatif::{HumanBytes, ParallelProgressIterator, ProgressIterator as _};
-use memmap2::Mmap;
-use rayon::prelude::*;
-use walkdir::WalkDir;
+use clap::{builder::TypedValueParser, Args, CommandFactory, Parser, Subcommand};
+use color_eyre::eyre::{eyre, Context, Result};
+use duplicate_image_finder::{
+    actions::{self, KeepPolicy},
+    bloom::BloomFilter,
+    cache::HashCache,
+    config,
+    digest::{Digest, HashAlgorithm},
+    duplicate_dirs::{file_counts, DirGroup, DuplicateDirs},
+    error::DuplicateFinderError,
+    glob::Glob,
+    hash_index::HashIndex,
+    index::LshParams,
+    interrupt::{self, EXIT_INTERRUPTED},
+    log::{self, Level},
+    perceptual::{Distance, PerceptualAlgorithm},
+    progress::Progress,
+    undo::{self, UndoLog},
+    collapse_hardlinked_clusters, collapse_hardlinks, collect, collect_paths, debug, exact_groups, format_date, full_hash_files, hash_files, hex, nearest_images,
+    prefix_groups, reclaimable_bytes, reference_duplicates, retain_size_collisions, similar_groups, warn,
+    default_max_open_files, CollectOptions, FileData, HashOptions, Linkage, DEFAULT_IO_RETRIES,
+};
+use color::ColorChoice;
+use ignore_list::IgnoreList;
+use indicatif::HumanBytes;
+use output::{DirStats, ExactStats, Group, OutputFormat, SimilarStats, SortOrder, Stats, Summary, Timings};
+use progress_bars::{ProgressBars, Style};
+
+mod color;
+mod contact_sheet;
+mod ignore_list;
+mod output;
+mod progress_bars;
+mod report;
+
+/// Print a status line. In machine-readable output modes these go to stderr, so that stdout
+/// only contains the results. Nothing is printed with --quiet.
+macro_rules! status {
+    ($args:expr, $($arg:tt)*) => {
+        if log::enabled(Level::Normal) {
+            if !$args.machine_readable() {
+                println!($($arg)*);
+            } else {
+                eprintln!($($arg)*);
+            }
+        }
+    };
+}
+
+/// Exit status if any duplicates or similar images were found.
+const EXIT_FOUND: u8 = 1;
+/// Exit status if the search itself failed.
+const EXIT_ERROR: u8 = 2;
+
+/// Config file read from the working directory if no --config is given.
+const CONFIG_FILE: &str = "dup-finder.toml";
 
+/// Find duplicate files and similar images. Without a command, the roots are searched for exact
+/// duplicates like with `exact`.
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(after_help = "\
+Exit status:
+    0  No duplicates or similar images were found, or --exit-zero was given
+    1  Duplicates or similar images were found
+    2  An error occurred
+  130  Interrupted with Ctrl-C, after reporting what was found so far")]
 struct Cli {
-    root: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    exact: ExactArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Find files with identical contents
+    Exact(ExactArgs),
+    /// Find groups of visually similar images
+    Similar(SimilarArgs),
+    /// List the images that look like the given one, closest first
+    SimilarTo(SimilarToArgs),
+    /// Delete, link, trash or move away all but one file of every group of duplicates
+    Clean(CleanArgs),
+    /// Write an HTML page with thumbnails of every group of similar images
+    Report(ReportArgs),
+    /// Revert the changes recorded by `clean --undo-log`, where possible
+    Undo(UndoArgs),
+    /// Print the perceptual distance between two images, e.g. to choose --max-distance
+    Distance(DistanceArgs),
+    /// Check whether the given files are still byte-identical, e.g. a group reported earlier.
+    /// Exits with 1 if any of them differ from the first
+    Verify(VerifyArgs),
+    /// Hash a tree once and search the saved hashes for duplicates later, without rescanning it
+    #[command(subcommand)]
+    Index(IndexCommand),
+}
+
+#[derive(Subcommand)]
+enum IndexCommand {
+    /// Hash every file below the roots and write the hashes to an index file. Files that might
+    /// have duplicates are hashed entirely, others only by their first few KiB.
+    Build(IndexBuildArgs),
+    /// Report the exact duplicates recorded in an index file, without reading the indexed files.
+    /// Options for scanning and hashing have no effect, the index was built with its own.
+    FindDupes(FindDupesArgs),
+}
+
+/// Options shared by every command: what to scan, how to hash it and how to report it.
+#[derive(Args)]
+struct CommonArgs {
+    /// Read default options from this TOML file instead of `./dup-finder.toml`. Keys are the long
+    /// option names, e.g. `jobs = 4` or `exclude = ["*.tmp", ".git"]`. Options given on the
+    /// command line take precedence, options the command doesn't have are ignored.
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// Directories to scan. Duplicates are searched across all of them.
+    #[clap(required_unless_present = "files_from")]
+    roots: Vec<PathBuf>,
+
+    /// Scan the files listed in this file instead of walking directories, `-` reads the list
+    /// from stdin. Paths are separated by newlines, or by NUL if the list contains any, e.g.
+    /// from `find -print0`.
+    #[clap(long, conflicts_with = "roots")]
+    files_from: Option<PathBuf>,
 
     #[clap(long)]
     print_groups: bool,
 
+    /// Terminate printed paths with NUL instead of newline, for `xargs -0`. Groups are separated
+    /// by an additional NUL and their headers are omitted. Applies to --print-groups and
+    /// `--output fdupes`. Paths are printed as raw bytes on Unix, even if they aren't valid UTF-8.
+    #[clap(long)]
+    print0: bool,
+
+    /// Skip files ignored by `.gitignore` and `.ignore` files below the roots, and `.git`
+    /// directories
+    #[clap(long)]
+    respect_gitignore: bool,
+
+    /// Also scan hidden files and directories, whose name starts with `.`, like `.thumbnails`.
+    /// They are skipped by default, roots are scanned even if they are hidden themselves.
+    #[clap(long)]
+    hidden: bool,
+
+    /// Follow symlinks to directories and scan symlinks to files, which are skipped otherwise.
+    /// Directories reachable through several links are only scanned once.
+    #[clap(long)]
+    follow_symlinks: bool,
+
+    /// Only descend this many directories below the roots, 1 only scans their direct children
+    #[clap(long)]
+    max_depth: Option<usize>,
+
+    /// Skip files less than this many directories below the roots, e.g. 2 to leave out the
+    /// files directly inside them
+    #[clap(long, default_value_t = 0)]
+    min_depth: usize,
+
+    /// Only scan files with one of these comma separated extensions (case-insensitive)
+    #[clap(long, value_delimiter = ',', conflicts_with = "exclude_extensions")]
+    extensions: Option<Vec<String>>,
+
+    /// Skip files with one of these comma separated extensions (case-insensitive)
+    #[clap(long, value_delimiter = ',')]
+    exclude_extensions: Option<Vec<String>>,
+
+    /// Only scan files matching this glob, e.g. `*.jpg` or `**/photos/*`. Patterns without a `/`
+    /// match the file name, others the path below the root. Can be given multiple times.
+    #[clap(long, value_parser = parse_glob)]
+    include: Vec<Glob>,
+
+    /// Skip files and directories matching this glob, e.g. `node_modules` or `.git`. Excluded
+    /// directories aren't scanned at all. Takes precedence over --include and can be given
+    /// multiple times.
+    #[clap(long, value_parser = parse_glob)]
+    exclude: Vec<Glob>,
+
+    /// Skip files smaller than this, e.g. `500k` or `1MiB`
+    #[clap(long, value_parser = parse_size)]
+    min_size: Option<u64>,
+
+    /// Skip files larger than this, e.g. `500k` or `1MiB`
+    #[clap(long, value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// Also report empty files, which are otherwise skipped
+    #[clap(long)]
+    include_empty: bool,
+
+    /// Also compare the files inside `.zip` and `.tar` archives, reported as e.g.
+    /// `photos.zip!beach.jpg`. Slower, since every member is decompressed. Archives are searched
+    /// even if --extensions or the size limits leave them out, members can't be cleaned up.
+    #[clap(long)]
+    inspect_archives: bool,
+
+    /// Only consider files duplicates or similar if they also have the same file name. Groups
+    /// are split up by name, and similar-to only lists images named like the given one.
+    #[clap(long)]
+    same_name_only: bool,
+
+    /// Compare file names case-insensitively for --same-name-only
+    #[clap(long, requires = "same_name_only")]
+    ignore_name_case: bool,
+
+    /// Cache hashes in this file and reuse them for files whose size and mtime didn't change
+    #[clap(long)]
+    cache: Option<PathBuf>,
+
+    /// Algorithm used to hash file contents for exact duplicate detection
+    #[clap(long, value_enum, default_value_t = HashAlgorithm::Seahash)]
+    hash_algo: HashAlgorithm,
+
+    /// Number of bytes at the start of every file that are hashed to find possible duplicates,
+    /// e.g. `64k`, 0 hashes whole files. Larger prefixes tell apart more files with identical
+    /// headers before their full contents are compared, at the cost of reading more of every file.
+    #[clap(long, value_parser = parse_size, default_value = "4096")]
+    prefix_bytes: u64,
+
+    /// Read files in chunks instead of memory mapping them, e.g. for unreliable network
+    /// filesystems. Files above 1 GiB are always read in chunks.
+    #[clap(long)]
+    no_mmap: bool,
+
+    /// Skip files whose size changed since they were found, e.g. downloads that are still being
+    /// written, with a warning. Without it such files are hashed as they are by then. Files
+    /// that change while their full contents are compared are always skipped.
+    #[clap(long)]
+    skip_changed: bool,
+
+    /// Keep at most this many files open at once while hashing them, however many threads there
+    /// are, e.g. on systems with a low limit of open files. Defaults to half the soft limit of
+    /// the process (`ulimit -n`), 0 doesn't limit them.
+    #[clap(long)]
+    max_open_files: Option<usize>,
+
+    /// Retry opening or mapping a file this many times after transient errors like timeouts or
+    /// stale handles, waiting longer before every retry, e.g. on flaky network mounts. Errors
+    /// like missing files or denied permissions are never retried.
+    #[clap(long, default_value_t = DEFAULT_IO_RETRIES)]
+    io_retries: u32,
+
+    /// Compare images by their decoded pixels for exact duplicates, so that copies that only
+    /// differ in their EXIF or XMP metadata match, as do lossless re-encodings in another format,
+    /// e.g. PNG and WebP. Files that can't be decoded are still compared by contents.
+    #[clap(long, visible_alias = "pixel-hash")]
+    ignore_metadata: bool,
+
+    /// Format used to report the groups
+    #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Order of the reported groups and of the files within them
+    #[clap(long, value_enum, default_value_t = SortOrder::Path)]
+    sort: SortOrder,
+
+    /// Only print the first N groups after sorting, e.g. the biggest ones with `--sort size`.
+    /// The summary still counts all groups, and `clean` still processes all of them.
+    #[clap(long)]
+    limit: Option<usize>,
+
+    /// Additionally write the totals of the run to this file as a JSON object, whatever the
+    /// output format: files and bytes scanned, files hashed, duplicate groups, reclaimable bytes,
+    /// errors, and elapsed seconds in total and per phase. Its `version` only changes if fields
+    /// are removed or change their meaning.
+    #[clap(long)]
+    json_summary: Option<PathBuf>,
+
+    /// Additionally write the SHA-256 of every scanned file to this file, as `<hash>  <path>`
+    /// lines that `sha256sum --check` verifies. Whole files are hashed with SHA-256 instead of
+    /// hashing a prefix with --hash-algo, including those with a unique size. Members of archives
+    /// are left out.
+    #[clap(long, conflicts_with_all = ["prefix_bytes", "hash_algo"])]
+    checksum_file: Option<PathBuf>,
+
+    /// Don't list or clean groups whose files are all in the same group of this file, for
+    /// duplicates that are there on purpose. It lists one path per line with groups separated by
+    /// blank lines, like `--output fdupes` writes them. Paths are compared as they are reported,
+    /// so files that were renamed or moved since are reported again. Ignored groups still count
+    /// towards the totals.
+    #[clap(long, value_name = "FILE", value_parser = IgnoreList::load)]
+    ignore_pairs: Option<IgnoreList>,
+
+    /// Leave the groups of --ignore-pairs out of the totals, as if they weren't duplicates
+    #[clap(long, requires = "ignore_pairs")]
+    hide_ignored: bool,
+
+    /// Number of threads used for walking and hashing, 0 uses one per CPU core. Fewer threads
+    /// can be faster on spinning disks, where the work is bound by I/O.
+    #[clap(short, long, default_value_t = 0)]
+    jobs: usize,
+
+    /// Exit with status 0 even if duplicates were found
     #[clap(long)]
-    detect_similar_images: bool,
+    exit_zero: bool,
 
+    /// Only print the results, without status messages, errors or progress bars
+    #[clap(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print a progress line every few seconds instead of drawing progress bars, like
+    /// `--progress-style plain`. This is the default if stderr is not a terminal, e.g. in CI logs.
     #[clap(long)]
-    search: Option<PathBuf>,
+    no_progress: bool,
+
+    /// How to show the progress of every stage: the number of files processed, and for stages
+    /// that read files the bytes read so far and the current throughput
+    #[clap(long, value_enum, conflicts_with = "no_progress")]
+    progress_style: Option<Style>,
+
+    /// Whether to color the group headers, the file that `clean` keeps by default and the
+    /// reclaimable space in the text output
+    #[clap(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Additionally report every skipped file and the hashing throughput
+    #[clap(short, long)]
+    verbose: bool,
 }
 
-type PdqHash = ([u8; 32], f32);
+/// Options controlling which images count as similar.
+#[derive(Args)]
+struct PerceptualArgs {
+    /// Maximum number of differing bits between two perceptual hashes to consider the images
+    /// similar. Larger values find more, but looser, matches.
+    #[clap(long, default_value_t = Distance(3), value_parser = clap::value_parser!(u64).range(0..=256).map(Distance))]
+    max_distance: Distance,
+
+    /// Ignore images whose perceptual hash has a quality below this, between 0 and 1. Flat or
+    /// noisy images get low quality hashes, which often match unrelated images.
+    #[clap(long)]
+    min_quality: Option<f32>,
+
+    /// Algorithm used for the perceptual hashes. The 64 bit hashes are faster but less accurate,
+    /// so --max-distance should be lower for them.
+    #[clap(long, value_enum, default_value_t = PerceptualAlgorithm::Pdq)]
+    perceptual_algo: PerceptualAlgorithm,
+
+    /// Also match images that were rotated or mirrored. This hashes every image eight times
+    /// and multiplies the cost of comparing them accordingly.
+    #[clap(long)]
+    dihedral: bool,
+
+    /// Hash every frame of animated GIFs and PNGs instead of only the first, so that animations
+    /// match if any pair of frames is within --max-distance. Slow for long animations.
+    #[clap(long)]
+    all_frames: bool,
+
+    /// Also match cropped images and images with an added border, by additionally hashing the
+    /// central 80% of every image and the image without its uniform border. This triples the
+    /// cost of hashing images and of comparing them.
+    #[clap(long)]
+    match_crops: bool,
+
+    /// How images are grouped into clusters. With `single` an image joins a cluster if it is
+    /// similar to any of its images, with `complete` only if it is similar to all of them.
+    #[clap(long, value_enum, default_value_t = Linkage::Single)]
+    linkage: Linkage,
 
-#[derive(Debug)]
-struct FileData {
-    path: PathBuf,
-    file_hash: Option<u64>,
-    size: Option<usize>,
+    /// Only compare images whose hashes are identical in at least one of this many bands of
+    /// bits, instead of searching a BK-tree. Much faster for hundreds of thousands of images.
+    /// Finds every match as long as there are more bands than --max-distance, with fewer it is
+    /// approximate and misses more matches the higher --max-distance is. More bands compare more
+    /// unrelated images and are thus slower.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..=256).map(|bands| bands as usize))]
+    lsh_bands: Option<usize>,
 
-    perception_hash: Option<PdqHash>,
+    /// Only group images whose hashes start with the same BITS bits, without comparing any
+    /// images. Much faster than the default, as a first pass over huge libraries, but only finds
+    /// exact and very close matches: images whose hashes differ in any of these bits are missed
+    /// no matter how similar they are. It is no substitute for --max-distance at larger
+    /// distances. Fewer bits find more matches, but also more unrelated images.
+    #[clap(
+        long,
+        value_name = "BITS",
+        num_args = 0..=1,
+        default_missing_value = "32",
+        require_equals = true,
+        value_parser = clap::value_parser!(u64).range(1..=256).map(|bits| bits as usize),
+        conflicts_with_all = ["max_distance", "linkage", "lsh_bands"],
+    )]
+    fast_perceptual: Option<usize>,
 }
 
-impl FileData {
-    pub fn from_file(path: PathBuf) -> Self {
-        Self {
-            path,
-            file_hash: None,
-            size: None,
-            perception_hash: None,
+impl PerceptualArgs {
+    /// The parameters for `similar_groups` to use LSH, if requested.
+    fn lsh(&self) -> Option<LshParams> {
+        let bands = self.lsh_bands?;
+        if bands as u64 <= self.max_distance.0 {
+            warn!(
+                "--lsh-bands {bands} is at most --max-distance {}, some similar images may be missed",
+                self.max_distance
+            );
         }
+        Some(LshParams { bands, hash_len: self.perceptual_algo.hash_len() })
     }
 
-    pub fn hash(&mut self, try_perception_hash: bool) -> Result<()> {
-        let file = File::open(&self.path)
-            .wrap_err_with(|| format!("Trying to open {}", self.path.display()))?;
-
-        let mmap = unsafe {
-            Mmap::map(&file)
-                .wrap_err_with(|| format!("Failed to memory map {}", self.path.display()))?
+    /// Cluster the similar images in `data`, with `prefix_groups` for --fast-perceptual and
+    /// `similar_groups` otherwise.
+    fn clusters<'a>(&self, data: &'a [FileData], progress: &dyn Progress) -> Vec<Vec<&'a FileData>> {
+        let clusters = match self.fast_perceptual {
+            None => similar_groups(data, self.max_distance, self.linkage, self.lsh(), progress),
+            Some(bits) => {
+                let hash_bits = self.perceptual_algo.hash_len() * 8;
+                if bits > hash_bits {
+                    warn!("--fast-perceptual {bits} is longer than the {hash_bits} bit hashes, only identical hashes match");
+                }
+                prefix_groups(data, bits)
+            },
         };
+        collapse_hardlinked_clusters(clusters)
+    }
+}
 
-        let prefix = min(mmap.len(), 4096);
-        self.file_hash = Some(seahash::hash(&mmap[0..prefix]));
-        self.size = Some(mmap.len());
+#[derive(Args)]
+struct ExactArgs {
+    #[command(flatten)]
+    common: CommonArgs,
 
-        if try_perception_hash {
-            self.perception_hash = (||{
-                let img = pdqhash::image::load_from_memory(&mmap).ok()?;
-                pdqhash::generate_pdq(&img)
-            })();
-        }
+    /// Only report files below the roots that duplicate a file in this directory, ignoring
+    /// duplicates within the roots or within the reference itself
+    #[clap(long)]
+    reference: Option<PathBuf>,
+
+    /// Keep a compact filter of the full-file hashes of the reference in this file, which is
+    /// built from the reference if it doesn't exist yet. Later runs then only hash the reference
+    /// files with the size of a scanned file that the filter matches, since it also matches
+    /// about 1% of the files it doesn't contain. Delete the file to rebuild it once files were
+    /// added to the reference, or their copies are missed.
+    #[clap(long, requires = "reference")]
+    reference_bloom: Option<PathBuf>,
+
+    /// Report paths that are hardlinks to the same file as duplicates of each other
+    #[clap(long)]
+    count_hardlinks: bool,
+
+    /// Only print the number of scanned files, duplicate groups and duplicates and the space
+    /// that removing them would free, instead of the groups. With `--output json` as an object.
+    #[clap(long, conflicts_with_all = ["print_groups", "reference", "limit"])]
+    stats_only: bool,
+
+    /// Print the directories holding duplicates instead of the groups, with the bytes their
+    /// duplicates take up and their number, most bytes first. Files count towards their parent
+    /// directory and are reclaimable as long as a copy remains elsewhere. With `--output json`
+    /// as an array of objects.
+    #[clap(long, conflicts_with_all = ["print_groups", "reference", "stats_only", "detect_duplicate_dirs", "streaming"])]
+    by_directory: bool,
+
+    /// Also report directories whose files all have duplicates in another directory with the same
+    /// nesting, as one group instead of a group per file. The names of the files don't matter.
+    /// Duplicates that aren't explained by a copied directory are still reported as files. With
+    /// `--output json` both are in one array, the directory groups first and marked as such.
+    #[clap(long, conflicts_with_all = ["reference", "stats_only", "files_from"])]
+    detect_duplicate_dirs: bool,
+
+    /// Process the files in batches of similar sizes instead of all at once, so that memory use
+    /// stays about the same no matter how many files are scanned. The roots are walked once to
+    /// count the files of every size and once more for every batch, which makes this slower
+    /// than the default unless the files wouldn't fit into memory. Groups are printed as soon as
+    /// their batch is done, ordered by the size of their files.
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "reference", "stats_only", "files_from", "ignore_metadata", "cache", "limit", "detect_duplicate_dirs", "checksum_file",
+        ],
+    )]
+    streaming: bool,
+
+    /// Maximum number of files hashed in one batch with --streaming. Larger batches need more
+    /// memory but fewer walks. Files of the same size are always hashed in the same batch.
+    #[clap(long, requires = "streaming", default_value_t = 100_000)]
+    batch_size: usize,
+}
 
+#[derive(Args)]
+struct SimilarArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+
+    #[command(flatten)]
+    perceptual: PerceptualArgs,
+
+    /// Report exact duplicates first, and only compare one file of each of their groups. With
+    /// `--output json` the two reports are printed as separate arrays on their own lines.
+    #[clap(long)]
+    exact: bool,
+
+    /// Only print the number of scanned files, clusters and images in them, instead of the
+    /// clusters. With --exact also the exact duplicate counts. With `--output json` as an object.
+    #[clap(long, conflicts_with_all = ["print_groups", "limit"])]
+    stats_only: bool,
+
+    /// Also write a PNG for every cluster to this directory, showing its images as a grid of
+    /// thumbnails with their file names, e.g. to review them without this tool.
+    #[clap(long, conflicts_with = "stats_only")]
+    contact_sheet: Option<PathBuf>,
+
+    /// Number of thumbnails per row of the contact sheets
+    #[clap(long, default_value_t = 6, value_parser = clap::value_parser!(u32).range(1..), requires = "contact_sheet")]
+    sheet_columns: u32,
+
+    /// Images are scaled down to fit into a square of this many pixels on the contact sheets
+    #[clap(long, default_value_t = 200, value_parser = clap::value_parser!(u32).range(64..=4096), requires = "contact_sheet")]
+    thumbnail_size: u32,
+}
+
+impl SimilarArgs {
+    /// Write the contact sheets of the shown `clusters`, if requested.
+    fn write_contact_sheets(&self, clusters: &[Vec<&FileData>]) -> Result<()> {
+        let Some(dir) = &self.contact_sheet else {
+            return Ok(());
+        };
+
+        let clusters = self.common.listed(clusters, Vec::as_slice);
+        let clusters = self.common.limited(&clusters);
+        contact_sheet::write(dir, clusters, self.sheet_columns, self.thumbnail_size)?;
+        status!(self.common, "Wrote {} contact sheets to {}", clusters.len(), dir.display());
         Ok(())
     }
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+#[derive(Args)]
+struct SimilarToArgs {
+    /// The image to compare against. It is not reported if it is below a root itself. Matches
+    /// are listed closest first, in JSON and fdupes output as one group led by this image.
+    image: PathBuf,
 
-    color_eyre::install()?;
+    #[command(flatten)]
+    common: CommonArgs,
 
-    let data = collect(&cli.root);
+    #[command(flatten)]
+    perceptual: PerceptualArgs,
+}
 
-    println!("Found {} files", data.len());
+#[derive(Args)]
+struct CleanArgs {
+    #[command(flatten)]
+    common: CommonArgs,
 
+    /// Clean up groups of similar images instead of exact duplicates
+    #[clap(long)]
+    similar: bool,
 
-    println!("Calculating hashes...");
-    let need_perception_hash = cli.detect_similar_images || cli.search.is_some();
-    let data: Vec<_> = data
-        .into_par_iter()
-        .progress()
-        .filter_map(|file| {
-            let result = (move || -> Result<_>{
-                let mut file = file?;
-                file.hash(need_perception_hash)?;
-                Ok(file)
-            })();
-
-            match result {
-                Ok(file) => Some(file),
-                Err(err) => {
-                    println!("Failed to hash file: {err}");
-                    None
-                },
-            }
-        })
-        .collect();
+    #[command(flatten)]
+    perceptual: PerceptualArgs,
 
-    let num_files = data.len();
-    let total_size: usize = data.iter().map(|file| file.size.unwrap()).sum();
+    /// Replace the files with hardlinks to the kept one instead of deleting them. Groups
+    /// spanning multiple devices are skipped.
+    #[clap(long, conflicts_with_all = ["move_to", "trash"])]
+    hardlink: bool,
 
-    println!("Hashed {} files ({})", num_files, HumanBytes(total_size as u64));
+    /// Replace the files with symlinks to the kept one instead of deleting them, which also
+    /// works across devices. The links break if the kept file is moved or deleted later.
+    /// Files that can't link to it, like links to the kept file itself, are skipped.
+    #[clap(long, conflicts_with_all = ["hardlink", "move_to", "trash"])]
+    symlink: bool,
 
-    if let Some(needle) = cli.search {
+    /// Make the symlinks relative to their directory instead of absolute, so that they keep
+    /// working if the tree holding both ends is moved
+    #[clap(long, requires = "symlink")]
+    relative_symlinks: bool,
 
-        let mut needle = FileData::from_file(needle);
-        needle.hash(true).expect("Expected searched image to be an image");
+    /// Move the files into this directory instead of deleting them, at their path below the
+    /// scanned root. A counter is appended to the name if the destination already exists.
+    #[clap(long, conflicts_with = "trash")]
+    move_to: Option<PathBuf>,
 
-        const ALLOWED_DISTANCE: u64 = 3;
+    /// Move the files to the trash instead of deleting them, so they can be restored from the
+    /// file manager. Fails if there is no trash, e.g. on platforms other than Linux and BSD.
+    #[clap(long)]
+    trash: bool,
 
-        let mut images: Vec<_> = data.iter().filter(|o| o.perception_hash.is_some()).collect();
+    /// Which file of a group to keep
+    #[clap(long, value_enum, default_value_t = KeepPolicy::First)]
+    keep: KeepPolicy,
 
-        println!("Found {} images in dataset", images.len());
+    /// List every group and ask which file to keep, or whether to skip the group or quit.
+    /// The file chosen by --keep is suggested. Without --force the choices are only printed.
+    #[clap(long)]
+    interactive: bool,
 
-        images.retain(|other| {
-            hamming::distance(
-                &other.perception_hash.unwrap().0,
-                &needle.perception_hash.unwrap().0,
-            ) <= ALLOWED_DISTANCE
-        });
+    /// Record the deleted, linked, trashed and moved files in this file, to revert them with `undo`.
+    /// Entries are appended if the file already exists.
+    #[clap(long)]
+    undo_log: Option<PathBuf>,
+
+    /// Actually delete, link, trash or move the files instead of only printing what would be done
+    #[clap(long)]
+    force: bool,
 
-        if images.is_empty() {
-            println!("Found no simlar images");
+    /// Treat paths that are hardlinks to the same file as duplicates of each other
+    #[clap(long, conflicts_with = "similar")]
+    count_hardlinks: bool,
+}
+
+#[derive(Args)]
+struct UndoArgs {
+    /// The undo log written by `clean`. Entries that were reverted are removed from it.
+    log: PathBuf,
+
+    /// Only print errors
+    #[clap(short, long)]
+    quiet: bool,
+}
+
+impl UndoArgs {
+    /// For `status!`, undo never prints results for other programs.
+    fn machine_readable(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Args)]
+struct DistanceArgs {
+    /// The first image
+    first: PathBuf,
+
+    /// The image to compare the first one to
+    second: PathBuf,
+
+    /// Algorithm used for the perceptual hashes
+    #[clap(long, value_enum, default_value_t = PerceptualAlgorithm::Pdq)]
+    perceptual_algo: PerceptualAlgorithm,
+
+    /// Print the distance of the closest rotation or reflection of the images
+    #[clap(long)]
+    dihedral: bool,
+
+    /// Print the distance of the closest pair of frames of animated images
+    #[clap(long)]
+    all_frames: bool,
+
+    /// Print the distance of the closest crop of the images, see `similar --match-crops`
+    #[clap(long)]
+    match_crops: bool,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    /// The files to compare, every one is compared to the first
+    #[clap(required = true, num_args = 2..)]
+    paths: Vec<PathBuf>,
+
+    /// Algorithm used to hash the whole files. Defaults to a cryptographic hash, so that files
+    /// with the same hash are identical for all practical purposes
+    #[clap(long, value_enum, default_value_t = HashAlgorithm::Blake3)]
+    hash_algo: HashAlgorithm,
+
+    /// Read files in chunks instead of memory mapping them
+    #[clap(long)]
+    no_mmap: bool,
+}
+
+#[derive(Args)]
+struct IndexBuildArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+
+    /// File to write the index to, it is replaced if it exists
+    #[clap(short, long)]
+    out: PathBuf,
+}
+
+#[derive(Args)]
+#[command(mut_arg("roots", |arg| arg.required_unless_present("index").hide(true)))]
+struct FindDupesArgs {
+    /// The index written by `index build`
+    #[clap(conflicts_with_all = ["roots", "files_from"])]
+    index: PathBuf,
+
+    #[command(flatten)]
+    common: CommonArgs,
+
+    /// Report paths that are hardlinks to the same file as duplicates of each other
+    #[clap(long)]
+    count_hardlinks: bool,
+}
+
+#[derive(Args)]
+struct ReportArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+
+    #[command(flatten)]
+    perceptual: PerceptualArgs,
+
+    /// File to write the HTML report to
+    #[clap(short, long)]
+    out: PathBuf,
+}
+
+impl Cli {
+    /// The command to run, scanning for exact duplicates if none was given.
+    fn into_command(self) -> Command {
+        self.command.unwrap_or(Command::Exact(self.exact))
+    }
+}
+
+impl Command {
+    /// Options of the commands that scan for duplicates, `None` for `undo`.
+    fn common(&self) -> Option<&CommonArgs> {
+        match self {
+            Command::Exact(args) => Some(&args.common),
+            Command::Similar(args) => Some(&args.common),
+            Command::SimilarTo(args) => Some(&args.common),
+            Command::Clean(args) => Some(&args.common),
+            Command::Report(args) => Some(&args.common),
+            Command::Index(IndexCommand::Build(args)) => Some(&args.common),
+            Command::Index(IndexCommand::FindDupes(args)) => Some(&args.common),
+            Command::Distance(_) | Command::Undo(_) | Command::Verify(_) => None,
+        }
+    }
+
+    /// Options for perceptual hashing, `None` if the command only looks for exact duplicates.
+    fn perceptual(&self) -> Option<&PerceptualArgs> {
+        match self {
+            Command::Exact(_) | Command::Distance(_) | Command::Index(_) | Command::Undo(_) | Command::Verify(_) => None,
+            Command::Similar(args) => Some(&args.perceptual),
+            Command::SimilarTo(args) => Some(&args.perceptual),
+            Command::Clean(args) => args.similar.then_some(&args.perceptual),
+            Command::Report(args) => Some(&args.perceptual),
+        }
+    }
+}
+
+impl CleanArgs {
+    fn action(&self) -> Action<'_> {
+        match &self.move_to {
+            Some(target) => Action::Move(target),
+            None if self.hardlink => Action::Hardlink,
+            None if self.symlink => Action::Symlink { relative: self.relative_symlinks },
+            None if self.trash => Action::Trash,
+            None => Action::Delete,
+        }
+    }
+}
+
+impl CommonArgs {
+    fn log_level(&self) -> Level {
+        if self.quiet {
+            Level::Quiet
+        } else if self.verbose {
+            Level::Verbose
         } else {
-            println!("Found {} similar image(s)", images.len());
+            Level::Normal
+        }
+    }
 
-            for i in images {
-                println!("{}", i.path.display());
+    /// Progress bars, or plain progress lines for logs. Nothing is shown with --quiet.
+    fn progress(&self) -> ProgressBars {
+        ProgressBars::new(if self.quiet {
+            Style::Hidden
+        } else if let Some(style) = self.progress_style {
+            style
+        } else if self.no_progress || !io::stderr().is_terminal() {
+            Style::Lines
+        } else {
+            Style::Bars
+        })
+    }
+
+    /// Whether stdout only contains the results, in a format meant for other programs.
+    fn machine_readable(&self) -> bool {
+        self.output != OutputFormat::Text || (self.print0 && self.print_groups)
+    }
+
+    /// Hashing options shared by every stage, without perceptual hashing or a cache.
+    fn hash_options(&self) -> HashOptions<'static> {
+        HashOptions {
+            // Checksums cover whole files, in the format of `sha256sum`.
+            algorithm: if self.checksum_file.is_some() { HashAlgorithm::Sha256 } else { self.hash_algo },
+            no_mmap: self.no_mmap,
+            prefix_len: if self.checksum_file.is_some() { 0 } else { self.prefix_bytes as usize },
+            skip_changed: self.skip_changed,
+            max_open_files: self.max_open_files.unwrap_or_else(default_max_open_files),
+            io_retries: self.io_retries,
+            ignore_metadata: self.ignore_metadata,
+            ..Default::default()
+        }
+    }
+
+    /// The groups to print, according to --limit.
+    fn shown<'a, T>(&self, groups: &'a [T]) -> &'a [T] {
+        let shown = self.limited(groups);
+        if shown.len() < groups.len() {
+            status!(self, "Showing {} of {} groups", shown.len(), groups.len());
+        }
+        shown
+    }
+
+    /// Whether `files` are expected duplicates according to --ignore-pairs.
+    fn is_ignored(&self, files: &[&FileData]) -> bool {
+        self.ignore_pairs.as_ref().is_some_and(|list| list.covers(files))
+    }
+
+    /// The `groups` to list, without those expected by --ignore-pairs. `files` returns the
+    /// files of a group.
+    fn listed<'a, T: Clone>(&self, groups: &'a [T], files: impl Fn(&'a T) -> &'a [&'a FileData]) -> Vec<T> {
+        groups.iter().filter(|group| !self.is_ignored(files(group))).cloned().collect()
+    }
+
+    /// Mention the groups that `listed` left out of `groups`.
+    fn report_ignored<T>(&self, groups: &[T], listed: &[T]) {
+        if listed.len() < groups.len() {
+            status!(self, "Not listing {} groups expected by --ignore-pairs", groups.len() - listed.len());
+        }
+    }
+
+    /// Like `shown`, without mentioning the groups that were left out.
+    fn limited<'a, T>(&self, groups: &'a [T]) -> &'a [T] {
+        &groups[..groups.len().min(self.limit.unwrap_or(usize::MAX))]
+    }
+
+    /// The file name of `file` as compared by --same-name-only.
+    fn name_key(&self, file: &FileData) -> OsString {
+        let name = file.path.file_name().unwrap_or_default();
+        match self.ignore_name_case {
+            true => name.to_string_lossy().to_lowercase().into(),
+            false => name.to_owned(),
+        }
+    }
+
+    /// Split `files` into the groups of files with the same name that have more than one member,
+    /// keeping their order. Without --same-name-only all files stay in one group. Groups left
+    /// out by --hide-ignored are dropped.
+    fn split_by_name<'a>(&self, files: Vec<&'a FileData>) -> Vec<Vec<&'a FileData>> {
+        let mut groups = self.split_by_name_unfiltered(files);
+        if self.hide_ignored {
+            groups.retain(|group| !self.is_ignored(group));
+        }
+        groups
+    }
+
+    fn split_by_name_unfiltered<'a>(&self, files: Vec<&'a FileData>) -> Vec<Vec<&'a FileData>> {
+        if !self.same_name_only {
+            return vec![files];
+        }
+
+        let mut groups: Vec<(OsString, Vec<&FileData>)> = Vec::new();
+        for file in files {
+            let name = self.name_key(file);
+            match groups.iter_mut().find(|(other, _)| *other == name) {
+                Some((_, group)) => group.push(file),
+                None => groups.push((name, vec![file])),
             }
         }
 
+        groups.into_iter().map(|(_, group)| group).filter(|group| group.len() > 1).collect()
+    }
 
+    fn collect_options(&self) -> CollectOptions {
+        CollectOptions {
+            extensions: self.extensions.clone(),
+            exclude_extensions: self.exclude_extensions.clone(),
+            min_size: self.min_size,
+            max_size: self.max_size,
+            include_empty: self.include_empty,
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            respect_gitignore: self.respect_gitignore,
+            hidden: self.hidden,
+            follow_symlinks: self.follow_symlinks,
+            max_depth: self.max_depth,
+            min_depth: self.min_depth,
+            inspect_archives: self.inspect_archives,
+        }
+    }
+}
+
+fn parse_glob(s: &str) -> Result<Glob, String> {
+    Glob::new(s).map_err(|err| err.to_string())
+}
+
+/// Parse a human readable size like `500k` (decimal) or `1MiB` (binary) into bytes.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, suffix) = s.split_at(split);
+
+    let number: f64 = number.parse().map_err(|_| format!("invalid size '{s}'"))?;
+
+    let multiplier: u64 = match suffix.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "ki" | "kib" => 1 << 10,
+        "m" | "mb" => 1_000_000,
+        "mi" | "mib" => 1 << 20,
+        "g" | "gb" => 1_000_000_000,
+        "gi" | "gib" => 1 << 30,
+        "t" | "tb" => 1_000_000_000_000,
+        "ti" | "tib" => 1 << 40,
+        other => return Err(format!("unknown size suffix '{other}'")),
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Read a list of paths from `source`, or from stdin if it is `-`. See `CommonArgs::files_from`.
+fn read_path_list(source: &Path) -> Result<Vec<PathBuf>> {
+    let bytes = if source == Path::new("-") {
+        let mut bytes = Vec::new();
+        io::stdin().lock().read_to_end(&mut bytes).wrap_err("Failed to read file list from stdin")?;
+        bytes
     } else {
-        if cli.detect_similar_images {
-            build_perception_groups(&data, &cli);
-        } else {
-            build_exact_groups(&data, &cli);
+        fs::read(source).wrap_err_with(|| format!("Failed to read file list {}", source.display()))?
+    };
+
+    let separator = if bytes.contains(&0) { b'\0' } else { b'\n' };
+
+    let paths = bytes
+        .split(|&b| b == separator)
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.is_empty())
+        .map(path_from_bytes)
+        .collect();
+
+    Ok(paths)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Parse the command line, with the options of the config file as defaults, see
+/// `CommonArgs::config`.
+fn parse_cli() -> Result<Cli> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+
+    // Invalid arguments and --help are reported by the final parse.
+    let root = Cli::command();
+    let Ok(matches) = root.clone().try_get_matches_from(&args) else {
+        return Ok(Cli::parse_from(args));
+    };
+
+    let (mut command, mut matches, mut depth) = (&root, &matches, 0);
+    while let Some((name, sub)) = matches.subcommand() {
+        command = command.find_subcommand(name).expect("matched subcommands exist");
+        matches = sub;
+        depth += 1;
+    }
+
+    // Commands that don't scan take no options from the config.
+    if command.get_arguments().all(|arg| arg.get_id() != "config") {
+        return Ok(Cli::parse_from(args));
+    }
+    let path = match matches.get_one::<PathBuf>("config") {
+        Some(path) => path.clone(),
+        None if Path::new(CONFIG_FILE).is_file() => PathBuf::from(CONFIG_FILE),
+        None => return Ok(Cli::parse_from(args)),
+    };
+
+    // Right after the subcommand, so they can't be mistaken for the values of other options.
+    let defaults = config::to_args(&root, command, matches, &config::load(&path)?)?;
+    args.splice(1 + depth..1 + depth, defaults);
+    Ok(Cli::parse_from(args))
+}
+
+fn main() -> ExitCode {
+    let command = match parse_cli() {
+        Ok(cli) => cli.into_command(),
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            return ExitCode::from(EXIT_ERROR);
+        },
+    };
+
+    let start = Instant::now();
+    let mut summary = Summary::default();
+    let result = match (&command, command.common()) {
+        (Command::Undo(args), _) => undo(args),
+        (Command::Distance(args), _) => distance(args),
+        (Command::Verify(args), _) => verify(args),
+        (Command::Index(IndexCommand::FindDupes(args)), _) => find_indexed_duplicates(args, &mut summary),
+        (_, Some(common)) => run(&command, common, &mut summary),
+        (_, None) => unreachable!("every other command scans"),
+    };
+
+    summary.elapsed = start.elapsed();
+    if let (Ok(_), Some(common), Some(timings)) = (&result, command.common(), summary.timings) {
+        status!(
+            common,
+            "Took {:.1?}: collecting {:.1?}, hashing {:.1?}, grouping {:.1?}",
+            summary.elapsed,
+            timings.collect,
+            timings.hash,
+            timings.group
+        );
+    }
+
+    let result = result.and_then(|found| {
+        if let Some(path) = command.common().and_then(|common| common.json_summary.as_ref()) {
+            fs::write(path, summary.to_json() + "\n")
+                .wrap_err_with(|| format!("Failed to write summary to {}", path.display()))?;
         }
+        Ok(found)
+    });
+
+    match result {
+        Ok(_) if interrupt::interrupted() => ExitCode::from(EXIT_INTERRUPTED),
+        Ok(true) if !command.common().is_some_and(|common| common.exit_zero) => ExitCode::from(EXIT_FOUND),
+        Ok(_) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            ExitCode::from(EXIT_ERROR)
+        },
     }
+}
+
+/// Set up error reporting, logging and the thread pool according to `common`.
+fn init(common: &CommonArgs) -> Result<()> {
+    color_eyre::install()?;
+    log::set_level(common.log_level());
+    color::init(common.color);
+    interrupt::install_handler();
 
+    // Configure the global pool before anything runs on it, this also covers the walk.
+    if common.jobs != 0 {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(common.jobs)
+            .build_global()
+            .wrap_err("Failed to set up the thread pool")?;
+    }
 
     Ok(())
 }
 
-fn build_exact_groups(data: &[FileData], cli: &Cli) {
-    let mut groups = group_candates(data);
+/// Run the requested command, returns whether any duplicates or similar images were found.
+/// The totals of the run are recorded in `summary`.
+fn run(command: &Command, common: &CommonArgs, summary: &mut Summary) -> Result<bool> {
+    init(common)?;
+
+    if let Command::Exact(args @ ExactArgs { streaming: true, .. }) = command {
+        return stream_exact_groups(args, summary);
+    }
+
+    let mut timings = Timings::default();
+    let phase = Instant::now();
+
+    // Don't scan everything only to find out that the files can't be trashed.
+    if let Command::Clean(CleanArgs { trash: true, .. }) = command {
+        actions::trash::trash_dir()?;
+    }
+    if let Command::Clean(CleanArgs { interactive: true, .. }) = command {
+        if common.files_from.as_deref() == Some(Path::new("-")) {
+            return Err(eyre!("--interactive reads the choices from stdin, it can't be used with --files-from -"));
+        }
+    }
+    if matches!(command, Command::SimilarTo(_)) && common.sort != SortOrder::Path {
+        return Err(eyre!("--sort can't be used with `similar-to`, which lists the closest images first"));
+    }
+
+    let options = common.collect_options();
+    let data = match &common.files_from {
+        Some(list) => collect_paths(read_path_list(list)?, &options),
+        None => collect(&common.roots, &options, &common.progress()),
+    };
+
+    status!(common, "Found {} files", data.len());
+    let scanned = Stats::new(data.iter().flatten());
+    summary.files_scanned = scanned.files;
+    summary.bytes_scanned = scanned.bytes;
+    // Before files with unique sizes are left out, since those make their directory unique.
+    let dir_counts = match command {
+        Command::Exact(ExactArgs { detect_duplicate_dirs: true, .. }) => Some(file_counts(data.iter().flatten())),
+        _ => None,
+    };
+
+    let perceptual = command.perceptual();
+    let (reference_path, reference_bloom) = match command {
+        Command::Exact(args) => (args.reference.as_ref(), args.reference_bloom.as_ref()),
+        _ => (None, None),
+    };
+
+    // Perceptual matching needs every image, but exact duplicates must share their size. In
+    // reference mode the sizes to collide with are in the reference set instead, and images
+    // with different metadata may have any size. Indexes record every file, as do checksums.
+    let indexing = matches!(command, Command::Index(_));
+    let keep_all = indexing || common.checksum_file.is_some();
+    let data = if perceptual.is_some() || reference_path.is_some() || common.ignore_metadata || keep_all {
+        data
+    } else {
+        let num_found = data.len();
+        let data = retain_size_collisions(data);
+        status!(common, "Skipping {} files with a unique size", num_found - data.len());
+        data
+    };
+
+    timings.collect = phase.elapsed();
+    let phase = Instant::now();
+
+    let mut cache = match &common.cache {
+        Some(path) => {
+            let cache = HashCache::load(path)?;
+            status!(common, "Loaded {} cached hashes", cache.len());
+            Some(cache)
+        },
+        None => None,
+    };
+
+    status!(common, "Calculating hashes...");
+    let hash_options = HashOptions {
+        perception_hash: perceptual.is_some(),
+        perceptual_algorithm: perceptual.map(|p| p.perceptual_algo).unwrap_or_default(),
+        dihedral: perceptual.is_some_and(|p| p.dihedral),
+        match_crops: perceptual.is_some_and(|p| p.match_crops),
+        all_frames: perceptual.is_some_and(|p| p.all_frames),
+        cache: cache.as_ref(),
+        ..common.hash_options()
+    };
+    let bloom = match reference_bloom {
+        Some(path) => BloomFilter::load(path)?,
+        None => None,
+    };
+    if let Some(filter) = bloom.as_ref().filter(|filter| !filter.matches(&hash_options)) {
+        return Err(eyre!(
+            "The reference filter was built with --hash-algo {}{}, delete it to rebuild it with the current options",
+            filter.algorithm.name(),
+            if filter.ignore_metadata { " and --ignore-metadata" } else { "" }
+        ));
+    }
+
+    let num_collected = data.len();
+    let hashing = Instant::now();
+    let (data, errors) = hash_files(data, &hash_options, &common.progress());
+    let hashing = hashing.elapsed();
+    report_errors(&errors);
+    summary.errors += errors.len();
+
+    if !errors.is_empty() && errors.len() == num_collected {
+        return Err(eyre!("Failed to hash any of the {num_collected} files"));
+    }
+
+    let num_files = data.len();
+    summary.files_hashed = num_files;
+    let total_size: usize = data.iter().map(|file| file.size.unwrap()).sum();
+
+    status!(common, "Hashed {} files ({})", num_files, HumanBytes(total_size as u64));
+    if common.verbose {
+        let seconds = hashing.as_secs_f64();
+        status!(
+            common,
+            "Hashing throughput: {:.0} files/s ({}/s)",
+            num_files as f64 / seconds,
+            HumanBytes((total_size as f64 / seconds) as u64)
+        );
+    }
+
+    if interrupt::interrupted() {
+        warn!("Interrupted, only searching the {num_files} files hashed so far. Press Ctrl-C again to quit right away");
+    }
+
+    if perceptual.is_some() {
+        report_undecodable(&data);
+    }
+
+    if let Some(path) = &common.checksum_file {
+        fs::write(path, output::to_checksums(&data))
+            .wrap_err_with(|| format!("Failed to write checksums to {}", path.display()))?;
+        status!(common, "Wrote checksums to {}", path.display());
+    }
+
+    let data = match &bloom {
+        Some(filter) => {
+            status!(common, "Checking against the reference filter...");
+            let (data, errors) = full_hash_files(data, &hash_options, &common.progress());
+            report_errors(&errors);
+            summary.errors += errors.len();
+            let num_checked = data.len();
+            let data: Vec<_> = data
+                .into_iter()
+                .filter(|file| file.verified_hash.is_some_and(|hash| filter.may_contain(&hash)))
+                .collect();
+            status!(common, "{} of {} files may exist in the reference", data.len(), num_checked);
+            data
+        },
+        None => data,
+    };
+
+    let mut reference = reference_path.map(|reference| {
+        // Without a match of the filter there is nothing to confirm.
+        if bloom.is_some() && data.is_empty() {
+            return Vec::new();
+        }
+
+        let mut reference = collect(&[reference], &options, &common.progress());
+        status!(common, "Found {} files in reference", reference.len());
+        if bloom.is_some() && !hash_options.ignore_metadata {
+            // Only files of the same size can be copies of the files that the filter matched.
+            let sizes: HashSet<_> = data.iter().filter_map(|file| file.size).collect();
+            reference.retain(|file| file.as_ref().map_or(true, |file| file.size.is_some_and(|size| sizes.contains(&size))));
+            status!(common, "Comparing with {} reference files of the same size", reference.len());
+        }
+
+        status!(common, "Calculating reference hashes...");
+        let (reference, errors) = hash_files(reference, &hash_options, &common.progress());
+        report_errors(&errors);
+        summary.errors += errors.len();
+        reference
+    });
+
+    if let (Some(path), None, Some(reference)) = (reference_bloom, &bloom, &mut reference) {
+        // The filter has to contain the whole reference, or later runs miss copies.
+        if interrupt::interrupted() {
+            return Err(eyre!("Interrupted, the reference filter {} was not written", path.display()));
+        }
+        status!(common, "Building the reference filter...");
+        let (hashed, errors) = full_hash_files(mem::take(reference), &hash_options, &common.progress());
+        report_errors(&errors);
+        summary.errors += errors.len();
+        BloomFilter::new(&hashed, &hash_options).save(path)?;
+        status!(common, "Wrote the reference filter for {} files to {}", hashed.len(), path.display());
+        *reference = hashed;
+    }
+
+    let hash_options = HashOptions { cache: None, ..hash_options };
+    if let (Some(cache), Some(path)) = (&mut cache, &common.cache) {
+        cache.update(data.iter().chain(reference.iter().flatten()), &hash_options);
+        cache.save(path)?;
+    }
+    timings.hash = phase.elapsed();
+    let phase = Instant::now();
+
+    let found = match command {
+        Command::Exact(args) if args.stats_only => {
+            let groups = verify_exact_groups(&data, common, args.count_hardlinks);
+            summary.add_groups(groups.iter().map(|(_, files)| files.as_slice()));
+            let stats = Stats { exact: Some(ExactStats::new(&groups)), ..scanned };
+            Ok(print_stats(&stats, common))
+        },
+        Command::Exact(args) if args.by_directory => {
+            let groups = verify_exact_groups(&data, common, args.count_hardlinks);
+            summary.add_groups(groups.iter().map(|(_, files)| files.as_slice()));
+            print_directories(&groups, common);
+            Ok(!groups.is_empty())
+        },
+        Command::Exact(args) => match (&reference, &dir_counts) {
+            (Some(reference), _) => Ok(build_reference_matches(reference, &data, common, summary)),
+            (None, Some(counts)) => Ok(build_dir_groups(&data, counts, common, args.count_hardlinks, summary)),
+            (None, None) => {
+                let groups = build_exact_groups(&data, common, args.count_hardlinks);
+                summary.add_groups(groups.iter().map(|(_, files)| files.as_slice()));
+                Ok(!groups.is_empty())
+            },
+        },
+        Command::Similar(args) if args.stats_only => {
+            let mut stats = scanned;
+            let remaining = if args.exact {
+                let groups = verify_exact_groups(&data, common, false);
+                summary.add_groups(groups.iter().map(|(_, files)| files.as_slice()));
+                stats.exact = Some(ExactStats::new(&groups));
+                Cow::Owned(without_duplicates(&data, &groups))
+            } else {
+                Cow::Borrowed(&data[..])
+            };
+
+            let images = retain_quality(&remaining, &args.perceptual, common);
+            let clusters = args.perceptual.clusters(&images, &common.progress());
+            let clusters: Vec<_> = clusters.into_iter().flat_map(|c| common.split_by_name(c)).collect();
+            summary.add_groups(clusters.iter().map(Vec::as_slice));
+            stats.similar = Some(SimilarStats::new(&clusters));
+            Ok(print_stats(&stats, common))
+        },
+        Command::Similar(args) if args.exact => {
+            let groups = build_exact_groups(&data, common, false);
+            let remaining = without_duplicates(&data, &groups);
+            let images = retain_quality(&remaining, &args.perceptual, common);
+            let clusters = build_perception_groups(&images, &args.perceptual, common);
+            summary.add_groups(groups.iter().map(|(_, files)| files.as_slice()));
+            summary.add_groups(clusters.iter().map(Vec::as_slice));
+            args.write_contact_sheets(&clusters)?;
+            Ok(!groups.is_empty() || !clusters.is_empty())
+        },
+        Command::Similar(args) => {
+            let images = retain_quality(&data, &args.perceptual, common);
+            let clusters = build_perception_groups(&images, &args.perceptual, common);
+            summary.add_groups(clusters.iter().map(Vec::as_slice));
+            args.write_contact_sheets(&clusters)?;
+            Ok(!clusters.is_empty())
+        },
+        Command::SimilarTo(args) => build_nearest_matches(args, &data),
+        Command::Clean(args) if args.similar => {
+            let images = retain_quality(&data, &args.perceptual, common);
+            let clusters = build_perception_groups(&images, &args.perceptual, common);
+            summary.add_groups(clusters.iter().map(Vec::as_slice));
+            clean(clusters.iter().map(Vec::as_slice), args)?;
+            Ok(!clusters.is_empty())
+        },
+        Command::Clean(args) => {
+            let groups = build_exact_groups(&data, common, args.count_hardlinks);
+            summary.add_groups(groups.iter().map(|(_, files)| files.as_slice()));
+            clean(groups.iter().map(|(_, files)| files.as_slice()), args)?;
+            Ok(!groups.is_empty())
+        },
+        Command::Report(args) => {
+            let images = retain_quality(&data, &args.perceptual, common);
+            let clusters = build_perception_groups(&images, &args.perceptual, common);
+            summary.add_groups(clusters.iter().map(Vec::as_slice));
+            report::write(&args.out, common.limited(&common.listed(&clusters, Vec::as_slice)))?;
+            status!(common, "Wrote report to {}", args.out.display());
+            Ok(!clusters.is_empty())
+        },
+        Command::Index(IndexCommand::Build(args)) => {
+            status!(common, "Hashing possible duplicates...");
+            let (index, errors) = HashIndex::new(data, &hash_options, &common.progress());
+            report_errors(&errors);
+            summary.errors += errors.len();
+            // A partial index would silently miss the files that weren't hashed.
+            if interrupt::interrupted() {
+                return Err(eyre!("Interrupted, {} was not written", args.out.display()));
+            }
+
+            index.save(&args.out)?;
+            status!(common, "Wrote {} files to {}", index.files.len(), args.out.display());
+            Ok(false)
+        },
+        Command::Distance(_) | Command::Index(IndexCommand::FindDupes(_)) | Command::Undo(_) | Command::Verify(_) => {
+            unreachable!("distance, find-dupes, undo and verify don't scan")
+        },
+    };
+
+    timings.group = phase.elapsed();
+    summary.timings = Some(timings);
+    found
+}
+
+/// Report the duplicates among the files of an index, without scanning or reading them.
+fn find_indexed_duplicates(args: &FindDupesArgs, summary: &mut Summary) -> Result<bool> {
+    let common = &args.common;
+    init(common)?;
+    if common.checksum_file.is_some() {
+        return Err(eyre!("--checksum-file can't be used with `index find-dupes`, which doesn't read the files"));
+    }
+
+    let index = HashIndex::load(&args.index)?;
+    status!(common, "Loaded {} files from {}", index.files.len(), args.index.display());
 
-    groups.retain(|_, v| v.len() > 1);
+    let scanned = Stats::new(&index.files);
+    summary.files_scanned = scanned.files;
+    summary.bytes_scanned = scanned.bytes;
+
+    let groups = build_exact_groups(&index.files, common, args.count_hardlinks);
+    summary.add_groups(groups.iter().map(|(_, files)| files.as_slice()));
+    Ok(!groups.is_empty())
+}
 
-    println!("Got {} possible duplicates", groups.len());
+/// Group the exact duplicates in `data`, without sorting or reporting them.
+fn verify_exact_groups<'a>(
+    data: &'a [FileData],
+    common: &CommonArgs,
+    count_hardlinks: bool,
+) -> Vec<(Digest, Vec<&'a FileData>)> {
+    status!(common, "Verifying possible duplicates...");
+
+    let groups = exact_groups(data, &common.hash_options(), &common.progress());
+    let groups = if count_hardlinks { groups } else { collapse_hardlinks(groups) };
+
+    groups
+        .into_iter()
+        .flat_map(|(hash, files)| common.split_by_name(files).into_iter().map(move |files| (hash, files)))
+        .collect()
+}
+
+fn build_exact_groups<'a>(
+    data: &'a [FileData],
+    common: &CommonArgs,
+    count_hardlinks: bool,
+) -> Vec<(Digest, Vec<&'a FileData>)> {
+    report_exact_groups(verify_exact_groups(data, common, count_hardlinks), Vec::new(), common)
+}
+
+/// Sort and report the exact duplicate `groups`. In machine-readable mode they follow `leading`,
+/// so that both end up in the same JSON array.
+fn report_exact_groups<'a>(
+    mut groups: Vec<(Digest, Vec<&'a FileData>)>,
+    leading: Vec<Group>,
+    common: &CommonArgs,
+) -> Vec<(Digest, Vec<&'a FileData>)> {
+    for (_, files) in &mut groups {
+        common.sort.sort_files(files);
+    }
+    groups.sort_by_cached_key(|(_, files)| common.sort.group_key(files));
+
+    if groups.is_empty() {
+        status!(common, "No duplicates found");
+        if common.machine_readable() && (!leading.is_empty() || common.output == OutputFormat::Json) {
+            print_groups(&leading, common);
+        }
+        return groups;
+    }
+
+    status!(common, "Got {} duplicates", groups.len());
 
     let avg = groups.iter().map(|(_, v)| v.len()).sum::<usize>()/groups.len();
-    println!("On average {avg} elements per group");
+    status!(common, "On average {avg} elements per group");
+
+    let reclaimable: u64 = groups.iter().map(|(_, files)| reclaimable_bytes(files)).sum();
+    status!(
+        common,
+        "Removing duplicates would free {}",
+        color::status_reclaimable(HumanBytes(reclaimable), common.machine_readable())
+    );
+
+    let listed = common.listed(&groups, |(_, files)| files);
+    common.report_ignored(&groups, &listed);
 
-    if cli.print_groups {
-        for (hash, files) in &groups {
-            println!("=== {hash} ===");
-            for file in files {
-                println!("{}", file.path.display());
+    if common.machine_readable() {
+        let mut shown = leading;
+        shown.extend(common.shown(&listed).iter().map(|(hash, files)| Group::new(hash.to_string(), files)));
+        print_groups(&shown, common);
+    } else if common.print_groups {
+        for (hash, files) in common.shown(&listed) {
+            print_exact_group(hash, files, common);
+        }
+    }
+
+    groups
+}
+
+/// Report the directories among `data` whose contents are duplicated, followed by the exact
+/// duplicates that aren't part of them. Returns whether anything was found.
+fn build_dir_groups(
+    data: &[FileData],
+    counts: &HashMap<PathBuf, usize>,
+    common: &CommonArgs,
+    count_hardlinks: bool,
+    summary: &mut Summary,
+) -> bool {
+    let groups = verify_exact_groups(data, common, count_hardlinks);
+    let mut dirs = DuplicateDirs::find(&groups, counts, &common.roots, common.hash_algo);
+    let groups: Vec<_> = groups
+        .into_iter()
+        .filter(|(_, files)| !dirs.covers(files.iter().map(|file| file.path.as_path())))
+        .collect();
+
+    let reclaimable = |group: &DirGroup| group.size * (group.dirs.len() as u64 - 1);
+    dirs.groups.sort_by_cached_key(|group| {
+        let rank = match common.sort {
+            SortOrder::Path => 0,
+            SortOrder::Size => reclaimable(group),
+            SortOrder::Count => group.dirs.len() as u64,
+        };
+        (Reverse(rank), group.dirs[0].clone())
+    });
+
+    status!(common, "Got {} duplicate directories", dirs.groups.len());
+    if !dirs.groups.is_empty() {
+        let total: u64 = dirs.groups.iter().map(reclaimable).sum();
+        status!(
+            common,
+            "Removing the copies of these directories would free {}",
+            color::status_reclaimable(HumanBytes(total), common.machine_readable())
+        );
+    }
+
+    let shown = common.shown(&dirs.groups);
+    let mut leading = Vec::new();
+    if common.machine_readable() {
+        leading = shown.iter().map(Group::directories).collect();
+    } else if common.print_groups {
+        for group in shown {
+            println!(
+                "{} {} {}",
+                color::header(format!("=== {} directories of {} files", group.dirs.len(), group.files)),
+                color::reclaimable(format!("({} reclaimable)", HumanBytes(reclaimable(group)))),
+                color::header("===")
+            );
+            for dir in &group.dirs {
+                println!("{}", dir.display());
             }
             println!();
         }
     }
-}
 
-fn build_perception_groups(data: &[FileData], cli: &Cli)  {
-    const ALLOWED_DISTANCE: u64 = 3;
+    let groups = report_exact_groups(groups, leading, common);
+    summary.groups += dirs.groups.len();
+    summary.reclaimable += dirs.groups.iter().map(reclaimable).sum::<u64>();
+    summary.add_groups(groups.iter().map(|(_, files)| files.as_slice()));
+    !dirs.groups.is_empty() || !groups.is_empty()
+}
 
-    let images: Vec<_> = data.iter().filter(|o| o.perception_hash.is_some()).collect();
+fn print_exact_group(hash: &Digest, files: &[&FileData], common: &CommonArgs) {
+    println!(
+        "{} {} {}",
+        color::header(format!("=== {hash}")),
+        color::reclaimable(format!("({} reclaimable)", HumanBytes(reclaimable_bytes(files)))),
+        color::header("===")
+    );
+    for (i, file) in files.iter().enumerate() {
+        let path = file.path.display();
+        match i {
+            0 => println!("{} ({})", color::survivor(path), details(file, common)),
+            _ => println!("{path} ({})", details(file, common)),
+        }
+    }
+    println!();
+}
 
-    println!("Found {} images in dataset", images.len());
+/// Report the exact duplicates below the roots batch by batch, for --streaming.
+fn stream_exact_groups(args: &ExactArgs, summary: &mut Summary) -> Result<bool> {
+    let common = &args.common;
+    let (mut count, mut reclaimable, mut ignored) = (0, 0, 0);
 
-    let mut groups = Vec::new();
+    // JSON is written as one array, whose elements are printed as they are found.
+    let json = common.output == OutputFormat::Json;
+    if json {
+        print!("[");
+    }
 
-    for &image in images.iter().progress() {
-        let self_hash = image.perception_hash.unwrap();
+    status!(common, "Searching for duplicates in batches of up to {} files...", args.batch_size);
+    let (totals, errors) = duplicate_image_finder::stream_exact_groups(
+        &common.roots,
+        &common.collect_options(),
+        &common.hash_options(),
+        args.batch_size,
+        &common.progress(),
+        |groups| {
+            let groups = if args.count_hardlinks { groups } else { collapse_hardlinks(groups) };
+            for (hash, files) in groups {
+                for mut files in common.split_by_name(files) {
+                    common.sort.sort_files(&mut files);
+                    count += 1;
+                    reclaimable += reclaimable_bytes(&files);
+                    if common.is_ignored(&files) {
+                        ignored += 1;
+                        continue;
+                    }
 
-        let similars: Vec<_> = images.iter().filter(|&&other| {
-            if addr_eq(image, other) {
-                return false;
+                    if json {
+                        let separator = if count - ignored > 1 { "," } else { "" };
+                        print!("{separator}{}", Group::new(hash.to_string(), &files).to_json());
+                    } else if common.machine_readable() {
+                        print_groups(&[Group::new(hash.to_string(), &files)], common);
+                    } else if common.print_groups {
+                        print_exact_group(&hash, &files, common);
+                    }
+                }
             }
+        },
+    );
+    if json {
+        println!("]");
+    }
+
+    if interrupt::interrupted() {
+        // Batches are searched from the smallest files up.
+        warn!("Interrupted after hashing {} files, duplicates of larger files are missing", totals.hashed);
+    }
+    report_errors(&errors);
+    status!(common, "Found {} files", totals.found);
+    status!(common, "Got {count} duplicates");
+    if ignored > 0 {
+        status!(common, "Not listing {ignored} groups expected by --ignore-pairs");
+    }
+    status!(
+        common,
+        "Removing duplicates would free {}",
+        color::status_reclaimable(HumanBytes(reclaimable), common.machine_readable())
+    );
+
+    *summary = Summary {
+        files_scanned: totals.found,
+        bytes_scanned: totals.bytes,
+        files_hashed: totals.hashed,
+        groups: count,
+        reclaimable,
+        errors: errors.len(),
+        ..Default::default()
+    };
+
+    Ok(count > 0)
+}
+
+fn build_nearest_matches(args: &SimilarToArgs, data: &[FileData]) -> Result<bool> {
+    let (common, query) = (&args.common, &args.image);
+
+    let mut needle = FileData::from_file(query.clone());
+    needle
+        .hash(&HashOptions {
+            perception_hash: true,
+            perceptual_algorithm: args.perceptual.perceptual_algo,
+            dihedral: args.perceptual.dihedral,
+            match_crops: args.perceptual.match_crops,
+            all_frames: args.perceptual.all_frames,
+            ..common.hash_options()
+        })
+        .wrap_err_with(|| format!("Failed to hash {}", query.display()))?;
+    if needle.perception_hash.is_none() {
+        return Err(eyre!("{} is not an image", query.display()));
+    }
+
+    let images = retain_quality(data, &args.perceptual, common);
+    let mut matches = nearest_images(&needle, &images, args.perceptual.max_distance);
+    if common.same_name_only {
+        let name = common.name_key(&needle);
+        matches.retain(|(_, file)| common.name_key(file) == name);
+    }
+
+    let shown = common.limited(&matches);
+    if shown.len() < matches.len() {
+        status!(common, "Showing the {} closest of {} similar images", shown.len(), matches.len());
+    }
+
+    if common.machine_readable() {
+        // A single group led by the query, so the distances are relative to it.
+        let groups: Vec<_> = if shown.is_empty() {
+            Vec::new()
+        } else {
+            let files: Vec<_> = std::iter::once(&needle).chain(shown.iter().map(|&(_, file)| file)).collect();
+            let hash = &needle.perception_hash.unwrap().0[..args.perceptual.perceptual_algo.hash_len()];
+            vec![Group::similar(hex(hash), &files)]
+        };
+        print_groups(&groups, common);
+    } else if matches.is_empty() {
+        println!("Found no similar images");
+    } else {
+        println!("Found {} similar image(s)", matches.len());
+
+        for (distance, file) in shown {
+            println!("{} (distance: {distance})", file.path.display());
+        }
+    }
 
-            let other_hash = other.perception_hash.unwrap();
+    Ok(!matches.is_empty())
+}
 
-            hamming::distance(&self_hash.0, &other_hash.0) <= ALLOWED_DISTANCE
-        }).collect();
+fn build_reference_matches(reference: &[FileData], data: &[FileData], common: &CommonArgs, summary: &mut Summary) -> bool {
+    status!(common, "Comparing against reference...");
 
-        if !similars.is_empty() {
-            groups.push((image, similars));
+    let mut matches = reference_duplicates(reference, data, &common.hash_options(), &common.progress());
+    if common.same_name_only {
+        for (_, file, originals) in &mut matches {
+            let name = common.name_key(file);
+            originals.retain(|original| common.name_key(original) == name);
         }
+        matches.retain(|(_, _, originals)| !originals.is_empty());
     }
 
-    for (image, similars) in groups {
-        println!("Found {} images similar to {}", similars.len(), image.path.display());
+    status!(common, "Found {} files that already exist in the reference", matches.len());
+    // Only the scanned copies would be removed, the reference is kept.
+    summary.groups += matches.len();
+    summary.reclaimable += matches.iter().map(|(_, file, _)| file.size.unwrap_or(0) as u64).sum::<u64>();
 
-        if cli.print_groups {
-            for file in similars {
-                println!("{}", file.path.display());
+    if common.machine_readable() {
+        // The first path of every group is the scanned file, the rest are its reference copies.
+        let groups: Vec<_> = matches
+            .iter()
+            .map(|(hash, file, originals)| {
+                let files: Vec<_> = std::iter::once(*file).chain(originals.iter().copied()).collect();
+                Group::new(hash.to_string(), &files)
+            })
+            .collect();
+        print_groups(&groups, common);
+    } else if common.print_groups {
+        for (_, file, originals) in &matches {
+            println!("{} ({})", file.path.display(), details(file, common));
+            for original in originals {
+                println!("  duplicate of {} ({})", original.path.display(), details(original, common));
             }
-            println!();
         }
     }
+
+    !matches.is_empty()
 }
 
-fn group_candates<'a>(items: impl IntoIterator<Item=&'a FileData>) -> BTreeMap<u64, Vec<&'a FileData>> {
-    let mut map: BTreeMap<u64, Vec<&'a FileData>> = BTreeMap::new();
+/// The files of `data` except for all but the first file of every group, so that byte-identical
+/// images aren't reported as similar to each other again.
+fn without_duplicates(data: &[FileData], groups: &[(Digest, Vec<&FileData>)]) -> Vec<FileData> {
+    let duplicates: HashSet<_> = groups
+        .iter()
+        .flat_map(|(_, files)| &files[1..])
+        .map(|file| file.path.as_path())
+        .collect();
 
-    for item in items {
-        map.entry(item.file_hash.unwrap()).or_default().push(item);
+    data.iter()
+        .filter(|file| !duplicates.contains(file.path.as_path()))
+        .cloned()
+        .collect()
+}
+
+/// Print the directories holding the duplicates in `groups`, for --by-directory.
+fn print_directories(groups: &[(Digest, Vec<&FileData>)], common: &CommonArgs) {
+    let listed = common.listed(groups, |(_, files)| files);
+    common.report_ignored(groups, &listed);
+
+    let dirs = DirStats::by_directory(&listed);
+    status!(common, "Found duplicates in {} directories", dirs.len());
+
+    let shown = common.shown(&dirs);
+    match common.output {
+        OutputFormat::Json => println!("{}", output::dirs_to_json(shown)),
+        OutputFormat::Jsonl => {
+            for dir in shown {
+                println!("{}", dir.to_json());
+            }
+        },
+        OutputFormat::Text | OutputFormat::Fdupes => {
+            println!("{}", DirStats::HEADER);
+            for dir in shown {
+                println!("{}", dir.to_text());
+            }
+        },
+    }
+}
+
+/// Print the `--stats-only` aggregates, returns whether anything was found.
+fn print_stats(stats: &Stats, common: &CommonArgs) -> bool {
+    match common.output {
+        OutputFormat::Json | OutputFormat::Jsonl => println!("{}", stats.to_json()),
+        OutputFormat::Text | OutputFormat::Fdupes => print!("{}", stats.to_text()),
     }
 
-    map
+    stats.found()
 }
 
-fn collect(path: &Path) -> Vec<Result<FileData>> {
-    WalkDir::new(path)
-        .into_iter()
-        .par_bridge()
-        .filter_map(|elem| {
-            let elem = match elem {
-                Ok(e) => e,
+/// Summarize the errors of a hashing stage, grouped by their underlying cause.
+fn report_errors(errors: &[DuplicateFinderError]) {
+    const MAX_CAUSES: usize = 5;
+
+    let (denied, errors): (Vec<_>, Vec<_>) = errors.iter().partition(|err| err.is_permission_denied());
+    if !denied.is_empty() {
+        warn!("Skipped {} inaccessible entries", denied.len());
+        for err in &denied {
+            match err.path() {
+                Some(path) => debug!("Skipping {}: permission denied", path.display()),
+                None => debug!("Skipping entry: {err}"),
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        return;
+    }
+
+    // (cause, number of occurrences, first affected file)
+    let mut causes: Vec<(String, usize, &DuplicateFinderError)> = Vec::new();
+    for &err in &errors {
+        let root: &(dyn Error + 'static) = err;
+        let cause = std::iter::successors(Some(root), |&err| err.source()).last().unwrap().to_string();
+        match causes.iter_mut().find(|(c, _, _)| *c == cause) {
+            Some((_, count, _)) => *count += 1,
+            None => causes.push((cause, 1, err)),
+        }
+    }
+    causes.sort_by_key(|(_, count, _)| Reverse(*count));
+
+    warn!("Failed to hash {} files", errors.len());
+    for (cause, count, example) in causes.iter().take(MAX_CAUSES) {
+        warn!("  {count}x {cause} (e.g. {example})");
+    }
+    if causes.len() > MAX_CAUSES {
+        warn!("  ...and {} other errors", causes.len() - MAX_CAUSES);
+    }
+}
+
+/// Summarize the images that couldn't be decoded, and are thus missing from perceptual matching.
+/// Every file is listed with --verbose.
+fn report_undecodable(data: &[FileData]) {
+    const MAX_REASONS: usize = 5;
+
+    // (reason, number of occurrences, first affected file)
+    let mut reasons: Vec<(&str, usize, &FileData)> = Vec::new();
+    for file in data {
+        let Some(error) = file.decode_error.as_deref() else {
+            continue;
+        };
+        debug!("Failed to decode {}: {error}", file.path.display());
+
+        match reasons.iter_mut().find(|(reason, _, _)| *reason == error) {
+            Some((_, count, _)) => *count += 1,
+            None => reasons.push((error, 1, file)),
+        }
+    }
+
+    if reasons.is_empty() {
+        return;
+    }
+    reasons.sort_by_key(|(_, count, _)| Reverse(*count));
+
+    let total: usize = reasons.iter().map(|(_, count, _)| count).sum();
+    warn!("{total} files could not be decoded as images and are left out of the comparison");
+    for (reason, count, example) in reasons.iter().take(MAX_REASONS) {
+        warn!("  {count}x {reason} (e.g. {})", example.path.display());
+    }
+    if reasons.len() > MAX_REASONS {
+        warn!("  ...and {} other errors", reasons.len() - MAX_REASONS);
+    }
+}
+
+/// `root: <root>, modified: <date>` for listing `file` with --print-groups. The root is only
+/// mentioned if it tells something, i.e. with several roots or for files of the reference.
+fn details(file: &FileData, common: &CommonArgs) -> String {
+    let modified = match file.modified {
+        Some(time) => format!("modified: {}", format_date(time)),
+        None => "modified: unknown".to_owned(),
+    };
+
+    match &file.root {
+        Some(root) if common.roots.len() > 1 || !common.roots.contains(root) => {
+            format!("root: {}, {modified}", root.display())
+        },
+        _ => modified,
+    }
+}
+
+/// Print `groups` in one of the machine-readable output formats, see `CommonArgs::machine_readable`.
+fn print_groups(groups: &[Group], common: &CommonArgs) {
+    match common.output {
+        OutputFormat::Text | OutputFormat::Fdupes => {
+            let lines = output::to_lines(groups, if common.print0 { b'\0' } else { b'\n' });
+            io::stdout().write_all(&lines).expect("failed printing to stdout");
+        },
+        OutputFormat::Json => println!("{}", output::to_json(groups)),
+        OutputFormat::Jsonl => {
+            output::write_json_lines(&mut io::stdout().lock(), groups).expect("failed printing to stdout");
+        },
+    }
+}
+
+/// What `clean` does with the files that aren't kept.
+#[derive(Clone, Copy)]
+enum Action<'a> {
+    Delete,
+    Hardlink,
+    /// Replace them with symlinks, relative to their directory if set
+    Symlink { relative: bool },
+    /// Move them below this directory
+    Move(&'a Path),
+    Trash,
+}
+
+/// Clean up all `groups`, recording the changes in the undo log if one was requested.
+fn clean<'a, 'b: 'a>(groups: impl IntoIterator<Item = &'a [&'b FileData]>, args: &CleanArgs) -> Result<()> {
+    // Load the log first, so an invalid one is noticed before anything is changed.
+    let mut log = match &args.undo_log {
+        Some(path) if args.force => Some(UndoLog::load(path)?),
+        _ => None,
+    };
+
+    // Every file is completely handled once started on, and a second Ctrl-C still writes the
+    // undo log. An interrupt leaves the remaining groups untouched.
+    let _critical = interrupt::Critical::enter();
+    let mut cleaned = Cleaned::default();
+    // An error while asking for a survivor stops the cleanup, but the changes made so far still
+    // have to end up in the undo log.
+    let mut failed = None;
+    for group in groups {
+        if interrupt::interrupted() {
+            warn!("Interrupted, leaving the remaining duplicates untouched");
+            break;
+        }
+        if args.common.is_ignored(group) {
+            debug!("Skipping the expected duplicates of {}", group[0].path.display());
+            continue;
+        }
+
+        let survivor = if args.interactive {
+            match choose_survivor(group, args.keep.survivor(group), &args.common) {
+                Ok(Choice::Keep(survivor)) => survivor,
+                Ok(Choice::Skip) => continue,
+                Ok(Choice::Quit) => break,
                 Err(err) => {
-                    return Some(
-                        Err(err)
-                            .wrap_err_with(|| format!("Invalid directory entry while iterating!")),
-                    )
+                    failed = Some(err);
+                    break;
+                },
+            }
+        } else {
+            args.keep.survivor(group)
+        };
+
+        let group = replace_duplicates(group, survivor, args, log.as_mut());
+        cleaned.files += group.files;
+        cleaned.bytes += group.bytes;
+    }
+
+    let (verb, done) = match args.action() {
+        Action::Delete => ("delete", "Deleted"),
+        Action::Hardlink => ("link", "Linked"),
+        Action::Symlink { .. } => ("symlink", "Symlinked"),
+        Action::Move(_) => ("move", "Moved"),
+        Action::Trash => ("trash", "Trashed"),
+    };
+    let reclaimed = format!("{} bytes ({})", cleaned.bytes, HumanBytes(cleaned.bytes));
+    if args.force {
+        status!(args.common, "{done} {} files, reclaiming {reclaimed}", cleaned.files);
+    } else {
+        status!(args.common, "Would {verb} {} files, reclaiming {reclaimed}. Pass --force to do so", cleaned.files);
+    }
+
+    if let (Some(log), Some(path)) = (&log, &args.undo_log) {
+        log.save(path)?;
+        status!(args.common, "Wrote undo log to {}", path.display());
+    }
+
+    failed.map_or(Ok(()), Err)
+}
+
+/// Files that `clean` processed, or would process without `--force`, and the bytes that frees.
+#[derive(Default)]
+struct Cleaned {
+    files: usize,
+    bytes: u64,
+}
+
+/// What to do with a group, as chosen with --interactive.
+enum Choice {
+    /// Keep the file at this index and clean up the others
+    Keep(usize),
+    /// Keep all files of the group
+    Skip,
+    /// Keep all files of this and every remaining group
+    Quit,
+}
+
+/// List the files of `group` and ask on stdin which one to keep, suggesting the one at index
+/// `suggested`. The prompt goes to stderr, so it is shown even with machine-readable output.
+/// The end of the input quits.
+fn choose_survivor(group: &[&FileData], suggested: usize, common: &CommonArgs) -> Result<Choice> {
+    eprintln!("=== {} files ({} reclaimable) ===", group.len(), HumanBytes(reclaimable_bytes(group)));
+    for (i, file) in group.iter().enumerate() {
+        let marker = if i == suggested { '*' } else { ' ' };
+        let size = HumanBytes(file.size.unwrap_or(0) as u64);
+        eprintln!("{marker}{:>3}) {} ({size}, {})", i + 1, file.path.display(), details(file, common));
+    }
+
+    let mut line = String::new();
+    loop {
+        eprint!("Keep which file? [1-{}, Enter for {}, s to skip, q to quit] ", group.len(), suggested + 1);
+        line.clear();
+        if io::stdin().lock().read_line(&mut line).wrap_err("Failed to read choice from stdin")? == 0 {
+            eprintln!();
+            return Ok(Choice::Quit);
+        }
+        // Reading the answer isn't interrupted by Ctrl-C, only noticed once it was entered.
+        if interrupt::interrupted() {
+            return Ok(Choice::Quit);
+        }
+
+        match line.trim() {
+            "" => return Ok(Choice::Keep(suggested)),
+            "s" | "S" => return Ok(Choice::Skip),
+            "q" | "Q" => return Ok(Choice::Quit),
+            input => match input.parse::<usize>() {
+                Ok(n) if (1..=group.len()).contains(&n) => return Ok(Choice::Keep(n - 1)),
+                _ => eprintln!("Invalid choice '{input}'"),
+            },
+        }
+    }
+}
+
+/// Delete, link, trash or move every file of `group` except the one at index `survivor`.
+/// Failures are reported but don't stop the remaining files from being processed. Without
+/// `--force` only prints what would be done, below the file that is kept.
+fn replace_duplicates(group: &[&FileData], survivor: usize, args: &CleanArgs, mut log: Option<&mut UndoLog>) -> Cleaned {
+    let survivor = group[survivor];
+    let common = &args.common;
+    let action = args.action();
+
+    if let Action::Hardlink = action {
+        match actions::same_device(group) {
+            Ok(true) => {},
+            Ok(false) => {
+                warn!("Skipping group of {}, its files are on different devices", survivor.path.display());
+                return Cleaned::default();
+            },
+            Err(err) => {
+                warn!("Skipping group of {}: {err}", survivor.path.display());
+                return Cleaned::default();
+            },
+        }
+    }
+
+    if !args.force {
+        status!(common, "Keeping {}", survivor.path.display());
+    }
+
+    let mut cleaned = Cleaned::default();
+    for &file in group {
+        if std::ptr::eq(file, survivor) {
+            continue;
+        }
+        if file.archive_member.is_some() {
+            warn!("Skipping {}, files inside archives can't be changed", file.path.display());
+            continue;
+        }
+
+        // Hardlinks of and symlinks to the survivor share its data, removing them frees nothing
+        // and replacing the survivor's own path would lose it.
+        if actions::same_data(file, survivor) {
+            status!(common, "Skipping {}, it is the same file as {}", file.path.display(), survivor.path.display());
+            continue;
+        }
+
+        let bytes = file.size.unwrap_or(0) as u64;
+        let path = file.path.display();
+        let size = HumanBytes(bytes);
+        let mut record = |action, reversible| {
+            if let Some(log) = log.as_deref_mut() {
+                // Absolute paths, so the log can be replayed from any directory.
+                let absolute = |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+                let action = match action {
+                    undo::Action::Move { destination } => undo::Action::Move { destination: absolute(&destination) },
+                    undo::Action::Trash { destination } => undo::Action::Trash { destination: absolute(&destination) },
+                    action => action,
+                };
+                log.entries.push(undo::Entry {
+                    action,
+                    path: absolute(&file.path),
+                    survivor: absolute(&survivor.path),
+                    reversible,
+                });
+            }
+        };
+        let result = match action {
+            Action::Delete if !args.force => Ok(format!("  Would delete {path} ({size})")),
+            Action::Delete => actions::delete(file).map(|()| {
+                record(undo::Action::Delete, false);
+                format!("Deleted {path}")
+            }),
+            Action::Hardlink if !args.force => Ok(format!("  Would link {path} ({size})")),
+            // Only identical files can be restored from the one they were linked to.
+            Action::Hardlink => actions::hardlink(survivor, file).map(|()| {
+                record(undo::Action::Hardlink, !args.similar);
+                format!("Linked {path}")
+            }),
+            Action::Symlink { relative } if !args.force => actions::symlink_target(survivor, file, relative)
+                .map(|target| format!("  Would symlink {path} to {} ({size})", target.display())),
+            Action::Symlink { relative } => actions::symlink(survivor, file, relative).map(|()| {
+                record(undo::Action::Symlink, !args.similar);
+                format!("Symlinked {path}")
+            }),
+            Action::Trash if !args.force => Ok(format!("  Would trash {path} ({size})")),
+            Action::Trash => actions::trash::trash(&file.path).map(|trashed| {
+                record(undo::Action::Trash { destination: trashed }, true);
+                format!("Trashed {path}")
+            }),
+            Action::Move(target) => {
+                let destination = actions::quarantine_path(target, &file.path, &common.roots);
+                if args.force {
+                    actions::move_file(file, &destination).map(|moved| {
+                        let message = format!("Moved {path} to {}", moved.display());
+                        record(undo::Action::Move { destination: moved }, true);
+                        message
+                    })
+                } else {
+                    Ok(format!("  Would move {path} to {} ({size})", destination.display()))
                 }
-            };
+            },
+        };
 
-            let path = elem.path();
+        match result {
+            Ok(message) => {
+                status!(common, "{message}");
+                cleaned.files += 1;
+                cleaned.bytes += bytes;
+            },
+            Err(err) => eprintln!("{err}"),
+        }
+    }
 
-            if !elem.path().is_file() {
-                return None;
+    cleaned
+}
+
+/// Drop the images whose perceptual hash quality is below --min-quality, if it was given.
+fn retain_quality<'a>(data: &'a [FileData], perceptual: &PerceptualArgs, common: &CommonArgs) -> Cow<'a, [FileData]> {
+    let images = data.iter().filter(|o| o.perception_hash.is_some()).count();
+
+    status!(common, "Found {images} images in dataset");
+
+    let Some(min) = perceptual.min_quality else {
+        return Cow::Borrowed(data);
+    };
+
+    let data: Vec<_> = data
+        .iter()
+        .filter(|file| file.perception_hash.is_some_and(|hash| hash.1 >= min))
+        .cloned()
+        .collect();
+    status!(common, "Skipping {} images with a hash quality below {min}", images - data.len());
+
+    Cow::Owned(data)
+}
+
+fn build_perception_groups<'a>(
+    data: &'a [FileData],
+    perceptual: &PerceptualArgs,
+    common: &CommonArgs,
+) -> Vec<Vec<&'a FileData>> {
+    let clusters = perceptual.clusters(data, &common.progress());
+    let mut clusters: Vec<_> = clusters.into_iter().flat_map(|c| common.split_by_name(c)).collect();
+    for files in &mut clusters {
+        common.sort.sort_files(files);
+    }
+    clusters.sort_by_cached_key(|files| common.sort.group_key(files));
+
+    let clustered: usize = clusters.iter().map(Vec::len).sum();
+    status!(common, "Found {} clusters covering {clustered} images", clusters.len());
+
+    let listed = common.listed(&clusters, Vec::as_slice);
+    common.report_ignored(&clusters, &listed);
+    let shown = common.shown(&listed);
+    if common.machine_readable() {
+        let groups: Vec<_> = shown
+            .iter()
+            .map(|files| {
+                let hash = &files[0].perception_hash.unwrap().0[..perceptual.perceptual_algo.hash_len()];
+                Group::similar(hex(hash), files)
+            })
+            .collect();
+        print_groups(&groups, common);
+    } else {
+        for cluster in shown {
+            println!("{}", color::header(format!("Found cluster of {} similar images", cluster.len())));
+
+            if common.print_groups {
+                // Distances are relative to the first image, members may be further apart from
+                // each other when they are only connected through other members of the cluster.
+                let quality = |file: &FileData| file.perception_hash.unwrap().1;
+                println!(
+                    "{} (quality: {:.2}, {})",
+                    color::survivor(cluster[0].path.display()),
+                    quality(cluster[0]),
+                    details(cluster[0], common),
+                );
+                for file in &cluster[1..] {
+                    println!(
+                        "{} (distance: {}, quality: {:.2}, {})",
+                        file.path.display(),
+                        file.perceptual_distance(cluster[0]).unwrap(),
+                        quality(file),
+                        details(file, common),
+                    );
+                }
+                println!();
             }
+        }
+    }
+
+    clusters
+}
+
+/// Print the perceptual distance between the two images of `args`.
+fn distance(args: &DistanceArgs) -> Result<bool> {
+    let options = HashOptions {
+        perception_hash: true,
+        perceptual_algorithm: args.perceptual_algo,
+        dihedral: args.dihedral,
+        match_crops: args.match_crops,
+        all_frames: args.all_frames,
+        ..Default::default()
+    };
+
+    let hash = |path: &PathBuf| {
+        let mut file = FileData::from_file(path.clone());
+        file.hash(&options).wrap_err_with(|| format!("Failed to hash {}", path.display()))?;
+        match (&file.perception_hash, &file.decode_error) {
+            (Some(_), _) => Ok(file),
+            (None, Some(error)) => Err(eyre!("Failed to decode {}: {error}", path.display())),
+            (None, None) => Err(eyre!("{} is not an image", path.display())),
+        }
+    };
+
+    let (first, second) = (hash(&args.first)?, hash(&args.second)?);
+    println!("{}", first.perceptual_distance(&second).unwrap());
 
-            Some(Ok(FileData::from_file(path.to_owned())))
+    Ok(false)
+}
+
+/// Hash the files of `args` entirely and print how they compare to the first one. Returns
+/// whether any of them differ.
+fn verify(args: &VerifyArgs) -> Result<bool> {
+    color_eyre::install()?;
+
+    let options = HashOptions { algorithm: args.hash_algo, no_mmap: args.no_mmap, ..Default::default() };
+    let files: Vec<_> = args
+        .paths
+        .iter()
+        .map(|path| {
+            let file = FileData::from_file(path.clone());
+            let hash = file.full_hash(&options).wrap_err_with(|| format!("Failed to hash {}", path.display()))?;
+            let size = fs::metadata(path).map(|metadata| metadata.len()).unwrap_or_default();
+            Ok((path, size, hash))
         })
-        .collect()
+        .collect::<Result<_>>()?;
+
+    let (_, _, first) = files[0];
+    for &(path, size, hash) in &files {
+        let differs = if hash == first { "" } else { "  (differs)" };
+        println!("{hash}  {:>10}  {}{differs}", HumanBytes(size).to_string(), path.display());
+    }
+
+    let differing = files.iter().filter(|&&(_, _, hash)| hash != first).count();
+    match differing {
+        0 => println!("All {} files are identical", files.len()),
+        _ => println!("{differing} of {} files differ from {}", files.len(), args.paths[0].display()),
+    }
+
+    Ok(differing > 0)
+}
+
+/// Revert the entries of an undo log, newest first, and keep the ones that failed in it.
+fn undo(args: &UndoArgs) -> Result<bool> {
+    color_eyre::install()?;
+    log::set_level(if args.quiet { Level::Quiet } else { Level::Normal });
+
+    let log = UndoLog::load(&args.log)?;
+    if log.entries.is_empty() {
+        return Err(eyre!("No entries in undo log {}", args.log.display()));
+    }
+
+    let mut remaining = Vec::new();
+    let mut failed = 0;
+    for entry in log.entries.into_iter().rev() {
+        match entry.revert() {
+            Ok(()) => status!(args, "Restored {}", entry.path.display()),
+            Err(err) => {
+                if entry.reversible {
+                    failed += 1;
+                }
+                warn!("Can't restore {}: {err}", entry.path.display());
+                remaining.push(entry);
+            },
+        }
+    }
+
+    remaining.reverse();
+    UndoLog { entries: remaining }.save(&args.log)?;
+
+    if failed > 0 {
+        return Err(eyre!("Failed to restore {failed} files"));
+    }
+    Ok(false)
 }
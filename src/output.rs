@@ -0,0 +1,452 @@
+//! Machine-readable result formats.
+
+use std::{
+    borrow::Cow, cmp::Reverse, collections::HashMap, io::{self, Write}, path::{Path, PathBuf}, time::{Duration, UNIX_EPOCH}
+};
+
+use clap::ValueEnum;
+use duplicate_image_finder::{digest::Digest, duplicate_dirs::DirGroup, json::{path_to_json, Value}, perceptual::Distance, reclaimable_bytes, FileData};
+use indicatif::HumanBytes;
+
+use crate::color;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable summary and groups
+    Text,
+    /// A JSON array of groups on stdout, status messages go to stderr. Paths that aren't valid
+    /// UTF-8 are written as `{"bytes": [...]}` objects holding their raw bytes
+    Json,
+    /// Like `json`, but every group is a JSON object on its own line, printed as soon as the
+    /// group is final. With `exact --streaming` consumers can act on groups while the scan is
+    /// still running
+    Jsonl,
+    /// One path per line with groups separated by blank lines, like `fdupes`. Paths are written
+    /// as raw bytes on Unix and status messages go to stderr
+    Fdupes,
+}
+
+/// Order of the reported groups and of the files within each group.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    /// Groups by the path of their first file, files by path
+    Path,
+    /// Groups with the most reclaimable space first, larger files first
+    Size,
+    /// Groups with the most files first, files by path
+    Count,
+}
+
+impl SortOrder {
+    pub fn sort_files(self, files: &mut [&FileData]) {
+        match self {
+            SortOrder::Size => files.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path))),
+            SortOrder::Path | SortOrder::Count => files.sort_by(|a, b| a.path.cmp(&b.path)),
+        }
+    }
+
+    /// Key to sort groups by, after their files were sorted with `sort_files`.
+    pub fn group_key(self, files: &[&FileData]) -> (Reverse<u64>, PathBuf) {
+        let rank = match self {
+            SortOrder::Path => 0,
+            // Everything but the largest file, which is the first one.
+            SortOrder::Size => files[1..].iter().map(|f| f.size.unwrap_or(0) as u64).sum(),
+            SortOrder::Count => files.len() as u64,
+        };
+        (Reverse(rank), files[0].path.clone())
+    }
+}
+
+/// One reported group, as emitted in JSON mode.
+pub struct Group {
+    pub hash: String,
+    pub count: usize,
+    pub total_size: u64,
+    pub paths: Vec<PathBuf>,
+    /// Modification time of every member in seconds since the Unix epoch, if it is known.
+    pub modified: Vec<Option<u64>>,
+    /// The root every member was found below, if it is known.
+    pub roots: Vec<Option<PathBuf>>,
+    /// Perceptual distance of every member to the first one, for groups of similar images.
+    pub distances: Option<Vec<Distance>>,
+    /// Quality of the perceptual hash of every member, for groups of similar images.
+    pub qualities: Option<Vec<f32>>,
+    /// Whether the paths are directories with identical contents instead of files.
+    pub directory: bool,
+}
+
+impl Group {
+    pub fn new(hash: String, files: &[&FileData]) -> Self {
+        Self {
+            hash,
+            count: files.len(),
+            total_size: files.iter().map(|f| f.size.unwrap_or(0) as u64).sum(),
+            paths: files.iter().map(|f| f.path.clone()).collect(),
+            modified: files
+                .iter()
+                .map(|f| f.modified?.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs()))
+                .collect(),
+            roots: files.iter().map(|f| f.root.clone()).collect(),
+            distances: None,
+            qualities: None,
+            directory: false,
+        }
+    }
+
+    /// A group of directories with identical contents, `total_size` counts all of their files.
+    pub fn directories(group: &DirGroup) -> Self {
+        Self {
+            hash: group.hash.to_string(),
+            count: group.dirs.len(),
+            total_size: group.size * group.dirs.len() as u64,
+            paths: group.dirs.clone(),
+            modified: vec![None; group.dirs.len()],
+            roots: vec![None; group.dirs.len()],
+            distances: None,
+            qualities: None,
+            directory: true,
+        }
+    }
+
+    /// A group of similar images, recording how far every member is from the first one.
+    pub fn similar(hash: String, files: &[&FileData]) -> Self {
+        Self {
+            distances: Some(files.iter().map(|f| f.perceptual_distance(files[0]).unwrap_or_default()).collect()),
+            qualities: Some(files.iter().map(|f| f.perception_hash.map_or(0.0, |h| h.1)).collect()),
+            ..Self::new(hash, files)
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut value = Value::object([
+            ("hash", self.hash.clone().into()),
+            ("count", self.count.into()),
+            ("total_size", self.total_size.into()),
+            ("paths", Value::Array(self.paths.iter().map(|path| path_to_json(path)).collect())),
+        ]);
+
+        // Directories have neither a modification time nor a root of their own.
+        if let (Value::Object(fields), false) = (&mut value, self.directory) {
+            fields.push(("modified".to_owned(), self.modified.clone().into()));
+            fields.push((
+                "roots".to_owned(),
+                Value::Array(self.roots.iter().map(|root| root.as_deref().map_or(Value::Null, path_to_json)).collect()),
+            ));
+        }
+
+        if let (Value::Object(fields), Some(distances), Some(qualities)) =
+            (&mut value, &self.distances, &self.qualities)
+        {
+            let members = self
+                .paths
+                .iter()
+                .zip(&self.modified)
+                .zip(&self.roots)
+                .zip(distances)
+                .zip(qualities)
+                .map(|((((path, &modified), root), &distance), &quality)| {
+                    Value::object([
+                        ("path", path_to_json(path)),
+                        ("modified", modified.into()),
+                        ("root", root.as_deref().map_or(Value::Null, path_to_json)),
+                        ("distance", distance.0.into()),
+                        // Rounded, so the f32 doesn't show up with spurious digits.
+                        ("quality", ((quality as f64 * 1000.0).round() / 1000.0).into()),
+                    ])
+                })
+                .collect();
+            fields.push(("members".to_owned(), Value::Array(members)));
+        }
+
+        if let (Value::Object(fields), true) = (&mut value, self.directory) {
+            fields.push(("directory".to_owned(), true.into()));
+        }
+
+        value
+    }
+}
+
+/// Serialize `groups` as a JSON array.
+pub fn to_json(groups: &[Group]) -> String {
+    Value::Array(groups.iter().map(Group::to_json).collect()).to_string()
+}
+
+/// `dirs` as a JSON array, for `--by-directory`.
+pub fn dirs_to_json(dirs: &[DirStats]) -> String {
+    Value::Array(dirs.iter().map(DirStats::to_json).collect()).to_string()
+}
+
+/// Write every group in `groups` to `out` as a JSON object on its own line, flushing after each
+/// one so that readers of a pipe see it right away.
+pub fn write_json_lines(out: &mut impl Write, groups: &[Group]) -> io::Result<()> {
+    for group in groups {
+        writeln!(out, "{}", group.to_json())?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// Format `groups` like `fdupes`: every path followed by `terminator`, each group followed by an
+/// additional `terminator`. With `\n` this puts every path on its own line and separates the
+/// groups by empty lines, with `\0` it is safe for paths containing newlines. Paths are written
+/// as raw bytes, see `path_bytes`.
+pub fn to_lines(groups: &[Group], terminator: u8) -> Vec<u8> {
+    let mut out = Vec::new();
+    for group in groups {
+        for path in &group.paths {
+            out.extend_from_slice(&path_bytes(path));
+            out.push(terminator);
+        }
+        out.push(terminator);
+    }
+    out
+}
+
+/// A `<hash>  <path>` line for every file in `data` ordered by path, like `sha256sum` writes
+/// them. Archive members are left out, since it can't read them. Like `sha256sum`, lines of
+/// paths with a backslash or newline start with a backslash, and those are escaped.
+pub fn to_checksums(data: &[FileData]) -> Vec<u8> {
+    let mut files: Vec<_> = data.iter().filter(|file| file.archive_member.is_none()).collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut out = Vec::new();
+    for file in files {
+        let Some(hash) = file.file_hash else { continue };
+        let path = path_bytes(&file.path);
+        if path.iter().any(|&b| b == b'\\' || b == b'\n') {
+            out.push(b'\\');
+        }
+        out.extend_from_slice(format!("{hash}  ").as_bytes());
+        for &b in path.iter() {
+            match b {
+                b'\\' => out.extend_from_slice(b"\\\\"),
+                b'\n' => out.extend_from_slice(b"\\n"),
+                b => out.push(b),
+            }
+        }
+        out.push(b'\n');
+    }
+    out
+}
+
+/// The bytes of `path` as the OS stores them, so that printed paths can be passed back to the
+/// filesystem, e.g. with `--files-from`.
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+/// Windows paths are UTF-16 and may contain unpaired surrogates, those are replaced.
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(path) => Cow::Borrowed(path.as_bytes()),
+        Cow::Owned(path) => Cow::Owned(path.into_bytes()),
+    }
+}
+
+/// The aggregates printed by `--stats-only` instead of the groups.
+pub struct Stats {
+    pub files: usize,
+    pub bytes: u64,
+    pub exact: Option<ExactStats>,
+    pub similar: Option<SimilarStats>,
+}
+
+pub struct ExactStats {
+    pub groups: usize,
+    /// Files in all groups, including the one of each group that would be kept.
+    pub files: usize,
+    pub reclaimable: u64,
+}
+
+pub struct SimilarStats {
+    pub clusters: usize,
+    pub images: usize,
+}
+
+impl ExactStats {
+    pub fn new(groups: &[(Digest, Vec<&FileData>)]) -> Self {
+        Self {
+            groups: groups.len(),
+            files: groups.iter().map(|(_, files)| files.len()).sum(),
+            reclaimable: groups.iter().map(|(_, files)| reclaimable_bytes(files)).sum(),
+        }
+    }
+}
+
+impl SimilarStats {
+    pub fn new(clusters: &[Vec<&FileData>]) -> Self {
+        Self { clusters: clusters.len(), images: clusters.iter().map(Vec::len).sum() }
+    }
+}
+
+impl Stats {
+    /// Stats of the scanned `files`, with nothing found yet.
+    pub fn new<'a>(files: impl IntoIterator<Item = &'a FileData>) -> Self {
+        let (mut count, mut bytes) = (0, 0);
+        for file in files {
+            count += 1;
+            bytes += file.size.unwrap_or(0) as u64;
+        }
+
+        Self { files: count, bytes, exact: None, similar: None }
+    }
+
+    /// Whether any duplicates or similar images were found.
+    pub fn found(&self) -> bool {
+        self.exact.as_ref().is_some_and(|exact| exact.groups > 0)
+            || self.similar.as_ref().is_some_and(|similar| similar.clusters > 0)
+    }
+
+    /// One `Name: value` line per aggregate.
+    pub fn to_text(&self) -> String {
+        let mut out = format!("Files: {} ({})\n", self.files, HumanBytes(self.bytes));
+        if let Some(exact) = &self.exact {
+            out.push_str(&format!("Duplicate groups: {}\n", exact.groups));
+            out.push_str(&format!("Duplicate files: {}\n", exact.files));
+            out.push_str(&format!("Reclaimable: {}\n", color::reclaimable(HumanBytes(exact.reclaimable))));
+        }
+        if let Some(similar) = &self.similar {
+            out.push_str(&format!("Similar clusters: {}\n", similar.clusters));
+            out.push_str(&format!("Images in clusters: {}\n", similar.images));
+        }
+        out
+    }
+
+    /// A single JSON object, with sizes in bytes.
+    pub fn to_json(&self) -> String {
+        let mut fields = vec![("files", self.files.into()), ("bytes", self.bytes.into())];
+        if let Some(exact) = &self.exact {
+            fields.push(("duplicate_groups", exact.groups.into()));
+            fields.push(("duplicate_files", exact.files.into()));
+            fields.push(("reclaimable_bytes", exact.reclaimable.into()));
+        }
+        if let Some(similar) = &self.similar {
+            fields.push(("similar_clusters", similar.clusters.into()));
+            fields.push(("clustered_images", similar.images.into()));
+        }
+        Value::object(fields).to_string()
+    }
+}
+
+/// The duplicates in one directory, printed by `--by-directory`.
+pub struct DirStats {
+    pub path: PathBuf,
+    /// Files of the directory that have a duplicate anywhere.
+    pub files: usize,
+    /// Bytes freed by removing these files, while keeping one copy of every group.
+    pub reclaimable: u64,
+}
+
+impl DirStats {
+    /// The directories holding members of `groups`, most reclaimable bytes first. Every file
+    /// counts towards its parent directory. The files of a directory are reclaimable as long as
+    /// a copy remains elsewhere, so for a group that lies entirely within one directory one of
+    /// its files isn't. The sum over all directories may thus exceed the global total.
+    pub fn by_directory(groups: &[(Digest, Vec<&FileData>)]) -> Vec<Self> {
+        let mut dirs: HashMap<&Path, Self> = HashMap::new();
+        for (_, files) in groups {
+            let mut counts: HashMap<&Path, usize> = HashMap::new();
+            for file in files {
+                *counts.entry(file.path.parent().unwrap_or(Path::new(""))).or_default() += 1;
+            }
+
+            let size = files[0].size.unwrap_or(0) as u64;
+            for (dir, count) in counts {
+                let stats = dirs.entry(dir).or_insert_with(|| Self { path: dir.to_owned(), files: 0, reclaimable: 0 });
+                let reclaimable = if count == files.len() { count - 1 } else { count };
+                stats.files += count;
+                stats.reclaimable += size * reclaimable as u64;
+            }
+        }
+
+        let mut dirs: Vec<_> = dirs.into_values().collect();
+        dirs.sort_by(|a, b| {
+            (Reverse(a.reclaimable), Reverse(a.files), &a.path).cmp(&(Reverse(b.reclaimable), Reverse(b.files), &b.path))
+        });
+        dirs
+    }
+
+    pub fn to_json(&self) -> Value {
+        Value::object([
+            ("path", path_to_json(&self.path)),
+            ("files", self.files.into()),
+            ("reclaimable_bytes", self.reclaimable.into()),
+        ])
+    }
+
+    /// One line of the table printed by `--by-directory`, see `DirStats::HEADER`.
+    pub fn to_text(&self) -> String {
+        format!("{:>12} {:>7}  {}", HumanBytes(self.reclaimable).to_string(), self.files, self.path.display())
+    }
+
+    pub const HEADER: &'static str = " Reclaimable   Files  Directory";
+}
+
+/// Version of the `--json-summary` schema. Fields may be added without changing it, it is only
+/// incremented if existing ones are removed or change their meaning.
+const SUMMARY_VERSION: u64 = 1;
+
+/// Totals of a whole run, written by `--json-summary` whatever the output format.
+#[derive(Default)]
+pub struct Summary {
+    pub files_scanned: usize,
+    pub bytes_scanned: u64,
+    pub files_hashed: usize,
+    /// Groups of duplicates or similar images, including duplicate directories.
+    pub groups: usize,
+    pub reclaimable: u64,
+    /// Files that couldn't be read or hashed.
+    pub errors: usize,
+    pub elapsed: Duration,
+    /// `None` if the phases weren't timed separately, e.g. because they are interleaved.
+    pub timings: Option<Timings>,
+}
+
+/// Wall-clock time of the phases of a run.
+#[derive(Default, Clone, Copy)]
+pub struct Timings {
+    /// Walking the roots or reading the file list, and leaving out files with a unique size.
+    pub collect: Duration,
+    /// Hashing the files and those of the reference, including loading and saving the cache.
+    pub hash: Duration,
+    /// Everything after hashing: grouping and reporting the files, and cleaning them up.
+    pub group: Duration,
+}
+
+impl Summary {
+    /// Count `groups`, of which all but the first file of each could be removed.
+    pub fn add_groups<'a, 'b: 'a>(&mut self, groups: impl IntoIterator<Item = &'a [&'b FileData]>) {
+        for files in groups {
+            self.groups += 1;
+            self.reclaimable += reclaimable_bytes(files);
+        }
+    }
+
+    /// A single JSON object, with sizes in bytes and times in seconds.
+    pub fn to_json(&self) -> String {
+        Value::object([
+            ("version", SUMMARY_VERSION.into()),
+            ("files_scanned", self.files_scanned.into()),
+            ("bytes_scanned", self.bytes_scanned.into()),
+            ("files_hashed", self.files_hashed.into()),
+            ("duplicate_groups", self.groups.into()),
+            ("reclaimable_bytes", self.reclaimable.into()),
+            ("errors", self.errors.into()),
+            ("elapsed_seconds", self.elapsed.as_secs_f64().into()),
+            (
+                "phase_seconds",
+                self.timings.map_or(Value::Null, |timings| {
+                    Value::object([
+                        ("collect", timings.collect.as_secs_f64().into()),
+                        ("hash", timings.hash.as_secs_f64().into()),
+                        ("group", timings.group.as_secs_f64().into()),
+                    ])
+                }),
+            ),
+        ])
+        .to_string()
+    }
+}
@@ -0,0 +1,95 @@
+//! Perceptual hash algorithms used for similar image detection.
+
+use std::fmt;
+
+use clap::ValueEnum;
+use pdqhash::image::{imageops::FilterType, DynamicImage};
+
+use crate::PerceptualHash;
+
+/// Number of differing bits between two perceptual hashes, 0 for identical hashes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Distance(pub u64);
+
+impl Distance {
+    pub fn between(a: &[u8; 32], b: &[u8; 32]) -> Self {
+        Self(hamming::distance(a, b))
+    }
+}
+
+impl fmt::Display for Distance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, ValueEnum)]
+pub enum PerceptualAlgorithm {
+    /// 256 bit PDQ hash, robust but comparatively slow
+    #[default]
+    Pdq,
+    /// 64 bit difference hash, comparing neighbouring pixels of a 9x8 thumbnail
+    Dhash,
+    /// 64 bit average hash, comparing the pixels of an 8x8 thumbnail to their mean
+    Ahash,
+}
+
+impl PerceptualAlgorithm {
+    /// Hash `img`, returns `None` if the algorithm can't hash it. The bits of shorter hashes are
+    /// padded with zeros, their quality is always 1.
+    pub fn hash(self, img: &DynamicImage) -> Option<PerceptualHash> {
+        match self {
+            PerceptualAlgorithm::Pdq => pdqhash::generate_pdq(img),
+            PerceptualAlgorithm::Dhash => Some((pad(dhash(img)), 1.0)),
+            PerceptualAlgorithm::Ahash => Some((pad(ahash(img)), 1.0)),
+        }
+    }
+
+    /// Number of meaningful bytes at the start of the hash.
+    pub fn hash_len(self) -> usize {
+        match self {
+            PerceptualAlgorithm::Pdq => 32,
+            PerceptualAlgorithm::Dhash | PerceptualAlgorithm::Ahash => 8,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PerceptualAlgorithm::Pdq => "pdq",
+            PerceptualAlgorithm::Dhash => "dhash",
+            PerceptualAlgorithm::Ahash => "ahash",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::value_variants().iter().copied().find(|a| a.name() == name)
+    }
+}
+
+fn dhash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x + 1, y).0[0] > small.get_pixel(x, y).0[0];
+            hash = hash << 1 | brighter as u64;
+        }
+    }
+    hash
+}
+
+fn ahash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(8, 8, FilterType::Triangle).to_luma8();
+    let mean = small.pixels().map(|p| p.0[0] as u32).sum::<u32>() / 64;
+
+    small
+        .pixels()
+        .fold(0, |hash, p| hash << 1 | (p.0[0] as u32 > mean) as u64)
+}
+
+fn pad(hash: u64) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    bytes[..8].copy_from_slice(&hash.to_be_bytes());
+    bytes
+}
@@ -0,0 +1,46 @@
+//! Progress reporting for the long running stages of a search, independent of how it is shown.
+
+use std::path::Path;
+
+/// A stage of a search. Every stage is announced with `Progress::on_phase` when it starts and
+/// followed by `Phase::Done` when it finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Walking the roots, reported with `Progress::on_file_discovered`.
+    Collecting,
+    /// Hashing the start of `files` files, reported with `Progress::on_file_hashed`.
+    Hashing { files: usize },
+    /// Decoding `images` distinct images for their perceptual or pixel hashes, reported with
+    /// `Progress::on_file_hashed` once per distinct image.
+    Decoding { images: usize },
+    /// Hashing the full contents of `files` possible duplicates, reported with
+    /// `Progress::on_file_hashed`.
+    Verifying { files: usize },
+    /// Comparing the perceptual hashes of `images` images, reported with
+    /// `Progress::on_image_compared`.
+    Comparing { images: usize },
+    /// The current stage finished.
+    Done,
+}
+
+/// Receives progress updates from the library functions. All methods do nothing by default.
+/// Files are processed in parallel, so the methods may be called from several threads at once.
+pub trait Progress: Sync {
+    fn on_phase(&self, _phase: Phase) {}
+
+    fn on_file_discovered(&self, _path: &Path) {}
+
+    fn on_file_hashed(&self, _path: &Path) {}
+
+    /// `bytes` were read from a file while hashing or decoding it, reported right before
+    /// `on_file_hashed`. Files restored from a cache don't report any.
+    fn on_bytes_read(&self, _bytes: u64) {}
+
+    fn on_image_compared(&self, _path: &Path) {}
+}
+
+/// Ignores all progress updates.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoProgress;
+
+impl Progress for NoProgress {}
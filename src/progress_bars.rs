@@ -0,0 +1,190 @@
+//! Progress on the terminal, drawn as bars with `indicatif` or written as plain lines for logs.
+
+use std::{
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use duplicate_image_finder::progress::{Phase, Progress};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+
+/// Time between two progress lines of the same stage.
+const LINE_INTERVAL: Duration = Duration::from_secs(5);
+/// Throughput is measured over this long, so that it follows changes in the read speed.
+const RATE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Style {
+    #[value(skip)]
+    Hidden,
+    /// A spinner while collecting files and a progress bar for every later stage, each is
+    /// cleared once its stage is done
+    #[value(name = "bar")]
+    Bars,
+    /// Like `bar`, but with a spinner instead of the bar, for narrow terminals
+    #[value(name = "spinner")]
+    Spinners,
+    /// A line like `Hashed 1000/5000 files` on stderr every few seconds, for logs of unattended
+    /// runs where bars would only produce garbage
+    #[value(name = "plain")]
+    Lines,
+}
+
+pub struct ProgressBars {
+    style: Style,
+    state: Mutex<State>,
+}
+
+struct State {
+    bar: ProgressBar,
+    phase: Phase,
+    done: usize,
+    last_line: Instant,
+    /// Bytes read in the current stage.
+    bytes: u64,
+    /// Start of the current throughput measurement, and `bytes` at that time.
+    window: (Instant, u64),
+    /// Bytes per second read during the last complete measurement.
+    rate: u64,
+}
+
+impl ProgressBars {
+    pub fn new(style: Style) -> Self {
+        Self {
+            style,
+            state: Mutex::new(State {
+                bar: ProgressBar::hidden(),
+                phase: Phase::Done,
+                done: 0,
+                last_line: Instant::now(),
+                bytes: 0,
+                window: (Instant::now(), 0),
+                rate: 0,
+            }),
+        }
+    }
+
+    fn inc(&self) {
+        if self.style == Style::Hidden {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.bar.inc(1);
+        state.done += 1;
+
+        if self.style == Style::Lines && state.last_line.elapsed() >= LINE_INTERVAL {
+            state.last_line = Instant::now();
+            eprintln!("{}", state.line());
+        }
+    }
+}
+
+impl Progress for ProgressBars {
+    fn on_phase(&self, phase: Phase) {
+        if self.style == Style::Hidden {
+            return;
+        }
+
+        let next = match phase {
+            _ if self.style == Style::Lines => ProgressBar::hidden(),
+            // The number of files is only known once they were all found.
+            Phase::Collecting => spinner(),
+            Phase::Hashing { files } | Phase::Verifying { files } => progress_bar(self.style, files),
+            Phase::Decoding { images } | Phase::Comparing { images } => progress_bar(self.style, images),
+            Phase::Done => ProgressBar::hidden(),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        std::mem::replace(&mut state.bar, next).finish_and_clear();
+        state.phase = phase;
+        state.done = 0;
+        state.last_line = Instant::now();
+        state.bytes = 0;
+        state.window = (Instant::now(), 0);
+        state.rate = 0;
+    }
+
+    fn on_file_discovered(&self, _path: &Path) {
+        self.inc();
+    }
+
+    fn on_file_hashed(&self, _path: &Path) {
+        self.inc();
+    }
+
+    fn on_bytes_read(&self, bytes: u64) {
+        if self.style == Style::Hidden {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.bytes += bytes;
+
+        let (start, at_start) = state.window;
+        let elapsed = start.elapsed();
+        if elapsed >= RATE_INTERVAL {
+            state.rate = ((state.bytes - at_start) as f64 / elapsed.as_secs_f64()) as u64;
+            state.window = (Instant::now(), state.bytes);
+        }
+
+        let message = state.bytes_read();
+        state.bar.set_message(message);
+    }
+
+    fn on_image_compared(&self, _path: &Path) {
+        self.inc();
+    }
+}
+
+impl State {
+    /// The progress line for the items processed so far.
+    fn line(&self) -> String {
+        let done = self.done;
+        let of = |total: usize| format!("{done}/{total} ({}%)", done * 100 / total.max(1));
+        let line = match self.phase {
+            Phase::Collecting => format!("Found {done} files"),
+            Phase::Hashing { files } => format!("Hashed {} files", of(files)),
+            Phase::Decoding { images } => format!("Decoded {} images", of(images)),
+            Phase::Verifying { files } => format!("Verified {} files", of(files)),
+            Phase::Comparing { images } => format!("Compared {} images", of(images)),
+            Phase::Done => format!("Processed {done} files"),
+        };
+
+        match self.bytes {
+            0 => line,
+            _ => format!("{line}, {}", self.bytes_read()),
+        }
+    }
+
+    /// The bytes read so far and the current throughput, once it was measured.
+    fn bytes_read(&self) -> String {
+        match self.rate {
+            0 => format!("{} read", HumanBytes(self.bytes)),
+            rate => format!("{} read at {}/s", HumanBytes(self.bytes), HumanBytes(rate)),
+        }
+    }
+}
+
+/// Progress indicator for `len` items, with the bytes read so far as its message.
+fn progress_bar(style: Style, len: usize) -> ProgressBar {
+    let template = match style {
+        Style::Spinners => "{spinner} {elapsed_precise} {pos}/{len} ({percent}%, ETA {eta}) {msg}",
+        _ => "{elapsed_precise} [{wide_bar}] {pos}/{len} ({percent}%, {per_sec}, ETA {eta}) {msg}",
+    };
+    let bar = ProgressBar::new(len as u64).with_style(ProgressStyle::with_template(template).unwrap());
+    if style == Style::Spinners {
+        bar.enable_steady_tick(Duration::from_millis(100));
+    }
+    bar
+}
+
+/// Progress indicator for work of unknown length, counting the items processed so far.
+fn spinner() -> ProgressBar {
+    let spinner = ProgressBar::new_spinner()
+        .with_style(ProgressStyle::with_template("{spinner} Found {pos} files").unwrap());
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
+}
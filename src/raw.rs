@@ -0,0 +1,126 @@
+//! Previews of camera RAW files. Most RAW formats (CR2, NEF, ARW, DNG, ORF, RW2, ...) are TIFF
+//! containers that embed a full size or large JPEG preview next to the sensor data, so instead of
+//! demosaicing the sensor data the largest embedded JPEG is decoded.
+
+use std::collections::HashSet;
+
+use pdqhash::image::{self, DynamicImage, ImageFormat};
+
+const JPEG_OFFSET: u16 = 0x0201;
+const JPEG_LENGTH: u16 = 0x0202;
+const COMPRESSION: u16 = 0x0103;
+const STRIP_OFFSETS: u16 = 0x0111;
+const STRIP_BYTE_COUNTS: u16 = 0x0117;
+const SUB_IFDS: u16 = 0x014a;
+
+/// The largest JPEG embedded in the TIFF structure of `data` that can be decoded, `None` if
+/// `data` isn't a TIFF container or has no such preview.
+pub fn preview(data: &[u8]) -> Option<DynamicImage> {
+    let tiff = Tiff::new(data)?;
+
+    let mut previews = Vec::new();
+    let mut visited = HashSet::new();
+    let mut pending = vec![tiff.u32(4)?];
+    while let Some(offset) = pending.pop() {
+        // Offsets of 0 end the chain, revisiting an IFD means the file is corrupt.
+        if offset == 0 || !visited.insert(offset) || visited.len() > 64 {
+            continue;
+        }
+        let Some(ifd) = tiff.ifd(offset as usize) else {
+            continue;
+        };
+
+        pending.extend(ifd.next);
+        pending.extend(ifd.sub_ifds);
+        previews.extend(ifd.jpegs);
+    }
+
+    previews.sort_by_key(|&(_, len)| std::cmp::Reverse(len));
+    previews
+        .into_iter()
+        .filter_map(|(offset, len)| data.get(offset..offset.checked_add(len)?))
+        .filter(|jpeg| jpeg.starts_with(&[0xff, 0xd8]))
+        .find_map(|jpeg| image::load_from_memory_with_format(jpeg, ImageFormat::Jpeg).ok())
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+/// The parts of an image file directory that lead to embedded JPEGs.
+struct Ifd {
+    next: Option<u32>,
+    sub_ifds: Vec<u32>,
+    /// Offsets and lengths of JPEG streams.
+    jpegs: Vec<(usize, usize)>,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let big_endian = match data.get(..2)? {
+            b"II" => false,
+            b"MM" => true,
+            _ => return None,
+        };
+        let tiff = Self { data, big_endian };
+
+        // Olympus and Panasonic use their own magic numbers instead of 42.
+        matches!(tiff.u16(2)?, 42 | 0x4f52 | 0x5352 | 0x55).then_some(tiff)
+    }
+
+    fn ifd(&self, offset: usize) -> Option<Ifd> {
+        let count = self.u16(offset)? as usize;
+        let entries = offset + 2;
+
+        let (mut jpeg_offset, mut jpeg_length) = (None, None);
+        let (mut compression, mut strip_offset, mut strip_length) = (None, None, None);
+        let mut sub_ifds = Vec::new();
+        for i in 0..count {
+            let entry = entries + i * 12;
+            let (tag, kind, n) = (self.u16(entry)?, self.u16(entry + 2)?, self.u32(entry + 4)?);
+            let value = match kind {
+                3 => self.u16(entry + 8)? as u32,
+                _ => self.u32(entry + 8)?,
+            };
+
+            match tag {
+                JPEG_OFFSET => jpeg_offset = Some(value),
+                JPEG_LENGTH => jpeg_length = Some(value),
+                COMPRESSION => compression = Some(value),
+                // Previews are stored in a single strip, sensor data often in many.
+                STRIP_OFFSETS if n == 1 => strip_offset = Some(value),
+                STRIP_BYTE_COUNTS if n == 1 => strip_length = Some(value),
+                // A single offset is stored inline, more are stored at the offset.
+                SUB_IFDS if n == 1 => sub_ifds.push(value),
+                SUB_IFDS => sub_ifds.extend((0..n.min(16)).filter_map(|j| self.u32(value as usize + j as usize * 4))),
+                _ => {},
+            }
+        }
+
+        let mut jpegs = Vec::new();
+        if let (Some(offset), Some(len)) = (jpeg_offset, jpeg_length) {
+            jpegs.push((offset as usize, len as usize));
+        }
+        // Old and new style JPEG compression.
+        if let (Some(6 | 7), Some(offset), Some(len)) = (compression, strip_offset, strip_length) {
+            jpegs.push((offset as usize, len as usize));
+        }
+
+        Some(Ifd {
+            next: self.u32(entries + count * 12),
+            sub_ifds,
+            jpegs,
+        })
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..offset.checked_add(2)?)?.try_into().ok()?;
+        Some(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset.checked_add(4)?)?.try_into().ok()?;
+        Some(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+}
@@ -0,0 +1,96 @@
+//! HTML report showing groups of similar images as rows of thumbnails.
+
+use std::{fs, path::Path};
+
+use color_eyre::eyre::{Context, Result};
+use duplicate_image_finder::FileData;
+use indicatif::HumanBytes;
+use pdqhash::image::{self, DynamicImage, ImageOutputFormat};
+use rayon::prelude::*;
+
+/// Thumbnails are scaled down to fit into a square of this many pixels.
+const THUMBNAIL_SIZE: u32 = 200;
+
+const STYLE: &str = "\
+body { font-family: sans-serif; background: #eee; }
+.group { display: flex; flex-wrap: wrap; gap: 1em; margin-bottom: 2em; padding: 1em; background: #fff; }
+figure { margin: 0; width: 200px; }
+figure img, .placeholder { width: 200px; height: 200px; object-fit: contain; background: #ddd; }
+.placeholder { display: flex; align-items: center; justify-content: center; color: #666; }
+figcaption { font-size: small; word-break: break-all; }
+";
+
+/// Write an HTML page to `path` with one row of thumbnails per group. Thumbnails are inlined,
+/// images that can't be decoded again are shown as a placeholder.
+pub fn write(path: &Path, groups: &[Vec<&FileData>]) -> Result<()> {
+    let thumbnails: Vec<Vec<Option<String>>> = groups
+        .par_iter()
+        .map(|group| group.par_iter().map(|file| thumbnail(&file.path)).collect())
+        .collect();
+
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<title>Similar images</title>\n<style>\n");
+    html.push_str(STYLE);
+    html.push_str("</style>\n</head>\n<body>\n");
+    html.push_str(&format!("<h1>{} groups of similar images</h1>\n", groups.len()));
+
+    for (group, thumbnails) in groups.iter().zip(thumbnails) {
+        html.push_str("<div class=\"group\">\n");
+        for (file, thumbnail) in group.iter().zip(thumbnails) {
+            let path = escape(&file.path.display().to_string());
+
+            html.push_str("<figure>\n");
+            match thumbnail {
+                Some(data) => html.push_str(&format!("<img src=\"data:image/jpeg;base64,{data}\" alt=\"{path}\">\n")),
+                None => html.push_str("<div class=\"placeholder\">No preview</div>\n"),
+            }
+            html.push_str(&format!(
+                "<figcaption>{path}<br>{}</figcaption>\n",
+                HumanBytes(file.size.unwrap_or(0) as u64)
+            ));
+            html.push_str("</figure>\n");
+        }
+        html.push_str("</div>\n");
+    }
+
+    html.push_str("</body>\n</html>\n");
+
+    fs::write(path, html).wrap_err_with(|| format!("Failed to write report {}", path.display()))
+}
+
+/// Base64 encoded JPEG thumbnail of the image at `path`.
+fn thumbnail(path: &Path) -> Option<String> {
+    let img = image::load_from_memory(&fs::read(path).ok()?).ok()?;
+    let img = DynamicImage::ImageRgb8(img.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8());
+
+    let mut jpeg = Vec::new();
+    img.write_to(&mut jpeg, ImageOutputFormat::Jpeg(80)).ok()?;
+    Some(base64(&jpeg))
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
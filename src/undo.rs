@@ -0,0 +1,146 @@
+//! Log of the changes made while cleaning up duplicates, so that they can be reverted later.
+
+use std::{fs, path::{Path, PathBuf}};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+
+use crate::{actions, json::{self, path_from_json, path_to_json, Value}};
+
+const VERSION: u64 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// The file was deleted, this can't be undone.
+    Delete,
+    /// The file was replaced with a hardlink to the survivor.
+    Hardlink,
+    /// The file was replaced with a symlink to the survivor.
+    Symlink,
+    /// The file was moved to `destination`.
+    Move { destination: PathBuf },
+    /// The file was moved to the trash, ending up at `destination`.
+    Trash { destination: PathBuf },
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub action: Action,
+    pub path: PathBuf,
+    /// The file of the group that was kept.
+    pub survivor: PathBuf,
+    /// Whether the original contents of `path` can be restored. Hardlinks to a file that was
+    /// only similar, not identical, lost the original contents.
+    pub reversible: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct UndoLog {
+    pub entries: Vec<Entry>,
+}
+
+impl UndoLog {
+    /// Load the log from `path`. A missing file yields an empty log.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err).wrap_err_with(|| format!("Failed to read undo log {}", path.display())),
+        };
+
+        Self::from_json(&json::parse(&text)?).wrap_err_with(|| format!("Invalid undo log {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_json().to_string())
+            .wrap_err_with(|| format!("Failed to write undo log {}", path.display()))
+    }
+
+    fn to_json(&self) -> Value {
+        let entries = self.entries.iter().map(entry_to_json).collect();
+
+        Value::object([("version", VERSION.into()), ("entries", Value::Array(entries))])
+    }
+
+    fn from_json(value: &Value) -> Result<Self> {
+        if value.get("version").and_then(Value::as_u64) != Some(VERSION) {
+            bail!("Unsupported undo log version");
+        }
+
+        let entries = value
+            .get("entries")
+            .and_then(Value::as_array)
+            .ok_or_else(|| eyre!("Missing undo log entries"))?
+            .iter()
+            .map(|entry| parse_entry(entry).ok_or_else(|| eyre!("Malformed undo log entry")))
+            .collect::<Result<_>>()?;
+
+        Ok(Self { entries })
+    }
+}
+
+impl Entry {
+    /// Revert the change. Moved files are moved back and links replaced with independent
+    /// copies, as long as nothing else took their place in the meantime.
+    pub fn revert(&self) -> Result<()> {
+        match &self.action {
+            Action::Delete => bail!("deleted files can't be restored"),
+            Action::Hardlink | Action::Symlink if !self.reversible => {
+                bail!("it was replaced with a link to the similar image {}", self.survivor.display())
+            },
+            Action::Hardlink | Action::Symlink => {
+                if !actions::same_file(&self.path, &self.survivor)? {
+                    bail!("{} is no longer linked to {}", self.path.display(), self.survivor.display());
+                }
+                actions::break_link(&self.path)
+            },
+            Action::Move { destination } => {
+                if self.path.exists() {
+                    bail!("{} already exists", self.path.display());
+                }
+                actions::move_path(destination, &self.path)
+            },
+            Action::Trash { destination } => {
+                if self.path.exists() {
+                    bail!("{} already exists", self.path.display());
+                }
+                actions::trash::restore(destination, &self.path)
+            },
+        }
+    }
+}
+
+fn entry_to_json(entry: &Entry) -> Value {
+    let (action, destination) = match &entry.action {
+        Action::Delete => ("delete", None),
+        Action::Hardlink => ("hardlink", None),
+        Action::Symlink => ("symlink", None),
+        Action::Move { destination } => ("move", Some(destination)),
+        Action::Trash { destination } => ("trash", Some(destination)),
+    };
+
+    Value::object([
+        ("action", action.into()),
+        ("path", path_to_json(&entry.path)),
+        ("survivor", path_to_json(&entry.survivor)),
+        ("destination", destination.map_or(Value::Null, |destination| path_to_json(destination))),
+        ("reversible", entry.reversible.into()),
+    ])
+}
+
+fn parse_entry(value: &Value) -> Option<Entry> {
+    let action = match value.get("action")?.as_str()? {
+        "delete" => Action::Delete,
+        "hardlink" => Action::Hardlink,
+        "symlink" => Action::Symlink,
+        "move" => Action::Move { destination: path_from_json(value.get("destination")?)? },
+        "trash" => Action::Trash { destination: path_from_json(value.get("destination")?)? },
+        _ => return None,
+    };
+
+    Some(Entry {
+        action,
+        path: path_from_json(value.get("path")?)?,
+        survivor: path_from_json(value.get("survivor")?)?,
+        reversible: value.get("reversible")?.as_bool()?,
+    })
+}
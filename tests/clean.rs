@@ -0,0 +1,93 @@
+//! Cleaning up duplicates never loses the last copy of a file.
+
+mod common;
+
+use common::{bytes, run, run_with_input, TempDir};
+
+#[cfg(unix)]
+#[test]
+fn hardlinks_of_the_survivor_are_left_alone() {
+    let dir = TempDir::new();
+    let contents = bytes(1, 1000);
+    let original = dir.write("a", &contents);
+    std::fs::hard_link(&original, dir.path().join("b")).unwrap();
+    dir.write("c", &contents);
+
+    let output = run(dir.path(), &["clean", ".", "--count-hardlinks", "--force", "--no-progress"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{stdout}{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("Removing duplicates would free 1000 B"), "{stdout}");
+    assert!(stdout.contains("Skipping ./b, it is the same file as ./a"), "{stdout}");
+    assert!(stdout.contains("Deleted 1 files, reclaiming 1000 bytes"), "{stdout}");
+
+    assert_eq!(std::fs::read(dir.path().join("a")).unwrap(), contents);
+    assert_eq!(std::fs::read(dir.path().join("b")).unwrap(), contents);
+    assert!(!dir.path().join("c").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_to_similar_images_never_replace_their_target() {
+    let dir = TempDir::new();
+    let contents = common::image(1);
+    let original = dir.write("photo.ppm", &contents);
+    std::os::unix::fs::symlink(&original, dir.path().join("a.ppm")).unwrap();
+    dir.write("z.ppm", &contents);
+
+    let output = run(dir.path(), &["clean", ".", "--similar", "--follow-symlinks", "--force", "--no-progress"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{stdout}{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("Found cluster of 2 similar images"), "{stdout}");
+    assert!(stdout.contains("Deleted 1 files"), "{stdout}");
+
+    assert_eq!(std::fs::read(&original).unwrap(), contents);
+    assert_eq!(std::fs::read(dir.path().join("a.ppm")).unwrap(), contents);
+    assert!(!dir.path().join("z.ppm").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn moves_of_files_with_non_utf8_names_can_be_undone() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let dir = TempDir::new();
+    let contents = bytes(1, 1000);
+    dir.write("files/a", &contents);
+    let name = OsStr::from_bytes(b"caf\xe9");
+    std::fs::write(dir.path().join("files").join(name), &contents).unwrap();
+
+    let output = run(dir.path(), &["clean", "files", "--move-to", "quarantine", "--force", "--undo-log", "undo.json", "--no-progress"]);
+    assert_eq!(output.status.code(), Some(1), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!dir.path().join("files").join(name).exists());
+    let log = std::fs::read_to_string(dir.path().join("undo.json")).unwrap();
+    assert!(log.contains(r#""path":{"bytes":["#), "{log}");
+
+    let output = run(dir.path(), &["undo", "undo.json"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read(dir.path().join("files").join(name)).unwrap(), contents);
+}
+
+#[test]
+fn undo_log_is_written_when_reading_a_choice_fails() {
+    let dir = TempDir::new();
+    dir.write("files/a1", bytes(1, 100));
+    dir.write("files/a2", bytes(1, 100));
+    dir.write("files/b1", bytes(2, 100));
+    dir.write("files/b2", bytes(2, 100));
+
+    // Keep the first file of the first group, then answer with invalid UTF-8.
+    let args = ["clean", "files", "--interactive", "--move-to", "quarantine", "--force", "--undo-log", "undo.json", "--no-progress"];
+    let output = run_with_input(dir.path(), &args, b"1\n\xff\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(2), "{stderr}");
+    assert!(stderr.contains("Failed to read choice from stdin"), "{stderr}");
+    assert!(dir.path().join("quarantine").exists());
+    assert!(dir.path().join("files/b2").exists());
+
+    let log = std::fs::read_to_string(dir.path().join("undo.json")).unwrap();
+    assert!(log.contains("a2"), "{log}");
+
+    let output = run(dir.path(), &["undo", "undo.json"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(dir.path().join("files/a2").exists());
+}
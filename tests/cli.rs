@@ -0,0 +1,138 @@
+//! Exit status and output of the binary.
+
+mod common;
+
+use common::{bytes, run, TempDir};
+use duplicate_image_finder::json;
+
+#[test]
+fn unique_files_exit_cleanly() {
+    let dir = TempDir::new();
+    // Two files of the same size, so that they are hashed and compared.
+    dir.write("a", bytes(1, 100));
+    dir.write("b", bytes(2, 200));
+    dir.write("c", bytes(3, 200));
+    let root = dir.path().to_str().unwrap();
+
+    let output = run(dir.path(), &[root, "--no-progress"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("No duplicates found"));
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = run(dir.path(), &[root, "--quiet"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty(), "{}", String::from_utf8_lossy(&output.stdout));
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn empty_files_are_only_grouped_with_include_empty() {
+    let dir = TempDir::new();
+    for name in ["e1", "e2", "e3"] {
+        dir.write(name, b"");
+    }
+    dir.write("other", bytes(1, 100));
+
+    let output = run(dir.path(), &[".", "--output", "json", "--no-progress"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "[]");
+
+    let output = run(dir.path(), &[".", "--output", "json", "--no-progress", "--include-empty"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.contains(r#""count":3,"total_size":0,"paths":["./e1","./e2","./e3"]"#), "{stdout}");
+}
+
+#[test]
+fn hidden_directories_are_only_searched_with_hidden() {
+    let dir = TempDir::new();
+    let contents = bytes(1, 100);
+    dir.write("photo.jpg", &contents);
+    dir.write(".thumbnails/photo.jpg", &contents);
+
+    let output = run(dir.path(), &[".", "--output", "json", "--no-progress"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "[]");
+
+    let output = run(dir.path(), &[".", "--output", "json", "--no-progress", "--hidden"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.contains(r#""paths":["./.thumbnails/photo.jpg","./photo.jpg"]"#), "{stdout}");
+}
+
+#[test]
+fn checksum_file_holds_sha256sum_lines() {
+    let dir = TempDir::new();
+    dir.write("files/abc", "abc");
+    // Two blocks once padded, since the length no longer fits after the data.
+    dir.write("files/two", "abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq");
+
+    let output = run(dir.path(), &["files", "--checksum-file", "sums", "--no-progress"]);
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    let sums = std::fs::read_to_string(dir.path().join("sums")).unwrap();
+    assert_eq!(
+        sums,
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  files/abc\n\
+         248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1  files/two\n",
+    );
+
+    // Names with a backslash or a newline are escaped, and their lines marked with a backslash.
+    #[cfg(unix)]
+    {
+        dir.write("escaped/back\\slash", "abc");
+        dir.write("escaped/new\nline", "abc");
+        let output = run(dir.path(), &["escaped", "--checksum-file", "escaped.sums", "--no-progress"]);
+        assert_eq!(output.status.code(), Some(1), "{}", String::from_utf8_lossy(&output.stderr));
+        let sums = std::fs::read_to_string(dir.path().join("escaped.sums")).unwrap();
+        assert_eq!(
+            sums,
+            "\\ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  escaped/back\\\\slash\n\
+             \\ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  escaped/new\\nline\n",
+        );
+    }
+}
+
+#[test]
+fn reference_below_a_root_does_not_match_itself() {
+    let dir = TempDir::new();
+    let contents = bytes(1, 100);
+    dir.write("ref/a", &contents);
+    dir.write("ref/b", bytes(2, 100));
+
+    let output = run(dir.path(), &[".", "--reference", "ref", "--print-groups", "--no-progress"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{stdout}");
+    assert!(stdout.contains("Found 0 files that already exist in the reference"), "{stdout}");
+
+    dir.write("copy", &contents);
+    let output = run(dir.path(), &[".", "--reference", "ref", "--print-groups", "--no-progress"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{stdout}");
+    assert!(stdout.contains("Found 1 files that already exist in the reference"), "{stdout}");
+    assert!(stdout.contains("  duplicate of ref/a"), "{stdout}");
+    assert!(!stdout.contains("./ref/a"), "{stdout}");
+}
+
+#[test]
+fn duplicate_dirs_and_files_are_one_json_array() {
+    let dir = TempDir::new();
+    for copy in ["one", "two"] {
+        dir.write(&format!("{copy}/a"), bytes(1, 100));
+        dir.write(&format!("{copy}/b"), bytes(2, 100));
+    }
+    dir.write("loose", bytes(3, 100));
+    dir.write("other/loose", bytes(3, 100));
+
+    let output = run(dir.path(), &[".", "--detect-duplicate-dirs", "--output", "json", "--no-progress"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1));
+    let groups = json::parse(&stdout).unwrap_or_else(|err| panic!("{err}: {stdout}"));
+    let groups = groups.as_array().unwrap();
+    assert_eq!(groups.len(), 2, "{stdout}");
+
+    assert_eq!(groups[0].get("directory").and_then(json::Value::as_bool), Some(true), "{stdout}");
+    assert!(groups[0].get("modified").is_none() && groups[0].get("roots").is_none(), "{stdout}");
+    assert!(stdout.contains(r#""paths":["./one","./two"]"#), "{stdout}");
+    assert!(groups[1].get("directory").is_none(), "{stdout}");
+    assert!(stdout.contains(r#""paths":["./loose","./other/loose"]"#), "{stdout}");
+}
@@ -0,0 +1,52 @@
+//! Which files `collect` finds below the roots.
+
+mod common;
+
+use std::path::PathBuf;
+
+use common::{bytes, TempDir};
+use duplicate_image_finder::{collect, glob::Glob, progress::NoProgress, CollectOptions};
+
+/// The collected paths relative to `dir`, sorted.
+fn collected(dir: &TempDir, options: &CollectOptions) -> Vec<PathBuf> {
+    let mut paths: Vec<_> = collect(&[dir.path()], options, &NoProgress)
+        .into_iter()
+        .map(|file| file.unwrap().path.strip_prefix(dir.path()).unwrap().to_owned())
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_files_are_only_collected_when_following_symlinks() {
+    let dir = TempDir::new();
+    let target = dir.write("photo.jpg", bytes(1, 100));
+    std::os::unix::fs::symlink(&target, dir.path().join("link.jpg")).unwrap();
+
+    assert_eq!(collected(&dir, &CollectOptions::default()), [PathBuf::from("photo.jpg")]);
+
+    let options = CollectOptions { follow_symlinks: true, ..Default::default() };
+    assert_eq!(collected(&dir, &options), [PathBuf::from("link.jpg"), PathBuf::from("photo.jpg")]);
+}
+
+#[cfg(unix)]
+#[test]
+fn excluded_directories_are_never_visited() {
+    let dir = TempDir::new();
+    dir.write("photo.jpg", bytes(1, 100));
+    dir.write("cache/deep/photo.jpg", bytes(1, 100));
+    // Following the link fails, so any visit of its directory shows up as an error.
+    std::os::unix::fs::symlink("missing", dir.path().join("cache/deep/broken")).unwrap();
+
+    let errors = |options: &CollectOptions| {
+        collect(&[dir.path()], options, &NoProgress).into_iter().filter(Result::is_err).count()
+    };
+
+    let options = CollectOptions { follow_symlinks: true, ..Default::default() };
+    assert_eq!(errors(&options), 1);
+
+    let options = CollectOptions { exclude: vec![Glob::new("cache").unwrap()], ..options };
+    assert_eq!(errors(&options), 0);
+    assert_eq!(collected(&dir, &options), [PathBuf::from("photo.jpg")]);
+}
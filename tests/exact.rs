@@ -0,0 +1,111 @@
+//! Grouping byte-identical files.
+
+mod common;
+
+use common::{bytes, TempDir};
+use duplicate_image_finder::{
+    collect, exact_groups, hash_files, progress::NoProgress, reclaimable_bytes, CollectOptions, FileData, HashOptions,
+};
+
+/// Collect and hash everything below `dir`.
+fn hashed(dir: &TempDir, collect_options: &CollectOptions, options: &HashOptions) -> Vec<FileData> {
+    let files = collect(&[dir.path()], collect_options, &NoProgress);
+    let (data, errors) = hash_files(files, options, &NoProgress);
+    assert!(errors.is_empty(), "{errors:?}");
+    data
+}
+
+/// The groups as sorted file names, sorted.
+fn names<'a>(groups: impl IntoIterator<Item = Vec<&'a FileData>>) -> Vec<Vec<String>> {
+    let mut groups: Vec<Vec<_>> = groups
+        .into_iter()
+        .map(|group| {
+            let mut names: Vec<_> = group
+                .iter()
+                .map(|file| file.path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        })
+        .collect();
+    groups.sort();
+    groups
+}
+
+#[test]
+fn reclaimable_space_counts_all_but_one_file_per_group() {
+    let dir = TempDir::new();
+    let small = bytes(1, 1000);
+    for name in ["a1", "a2", "a3"] {
+        dir.write(name, &small);
+    }
+    let large = bytes(2, 4106);
+    for name in ["b1", "b2"] {
+        dir.write(name, &large);
+    }
+    for name in ["e1", "e2"] {
+        dir.write(name, b"");
+    }
+    dir.write("unique", bytes(3, 1000));
+
+    let collect_options = CollectOptions { include_empty: true, ..Default::default() };
+    let options = HashOptions::default();
+    let data = hashed(&dir, &collect_options, &options);
+    let groups = exact_groups(&data, &options, &NoProgress);
+
+    assert_eq!(names(groups.values().cloned()), [vec!["a1", "a2", "a3"], vec!["b1", "b2"], vec!["e1", "e2"]]);
+    let per_group: Vec<_> = groups.values().map(|group| (group.len(), reclaimable_bytes(group))).collect();
+    assert!(per_group.contains(&(3, 2 * 1000)));
+    assert!(per_group.contains(&(2, 4106)));
+    assert!(per_group.contains(&(2, 0)));
+    assert_eq!(groups.values().map(|group| reclaimable_bytes(group)).sum::<u64>(), 2 * 1000 + 4106);
+}
+
+#[test]
+fn prefix_bucket_with_two_sets_of_duplicates_yields_two_groups() {
+    let dir = TempDir::new();
+    let options = HashOptions::default();
+    // Same size and prefix, so all four files land in one bucket before their full contents
+    // are compared.
+    let prefix = bytes(1, options.prefix_len);
+    let a = [prefix.as_slice(), &bytes(2, 1000)].concat();
+    let b = [prefix.as_slice(), &bytes(3, 1000)].concat();
+    dir.write("a1", &a);
+    dir.write("a2", &a);
+    dir.write("b1", &b);
+    dir.write("b2", &b);
+
+    let data = hashed(&dir, &CollectOptions::default(), &options);
+    assert!(data.iter().all(|file| file.file_hash == data[0].file_hash));
+
+    let groups = exact_groups(&data, &options, &NoProgress);
+    assert_eq!(names(groups.into_values()), [vec!["a1", "a2"], vec!["b1", "b2"]]);
+}
+
+#[test]
+fn parallel_verification_matches_a_serial_run() {
+    let dir = TempDir::new();
+    let options = HashOptions { max_open_files: 2, ..Default::default() };
+    // Every file has the same size and prefix, so only the full comparison tells them apart.
+    let prefix = bytes(1, options.prefix_len);
+    for i in 0..40u64 {
+        let contents = [prefix.as_slice(), &bytes(2 + i % 7, 3000)].concat();
+        dir.write(&format!("f{i:02}"), contents);
+    }
+    dir.write("unique", [prefix.as_slice(), &bytes(100, 3000)].concat());
+
+    let data = hashed(&dir, &CollectOptions::default(), &options);
+    let parallel = names(exact_groups(&data, &options, &NoProgress).into_values());
+
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+    let serial = pool.install(|| names(exact_groups(&data, &options, &NoProgress).into_values()));
+    assert_eq!(parallel, serial);
+
+    let mut by_contents = std::collections::BTreeMap::<_, Vec<_>>::new();
+    for file in &data {
+        by_contents.entry(std::fs::read(&file.path).unwrap()).or_default().push(file);
+    }
+    let reference = names(by_contents.into_values().filter(|group| group.len() > 1));
+    assert_eq!(parallel, reference);
+    assert_eq!(parallel.len(), 7);
+}
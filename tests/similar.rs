@@ -0,0 +1,122 @@
+//! Clustering of visually similar images.
+
+mod common;
+
+use std::{
+    path::Path,
+    sync::{mpsc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use common::{run, TempDir};
+use duplicate_image_finder::{
+    collect,
+    hash_files,
+    progress::{NoProgress, Phase, Progress},
+    CollectOptions,
+    FileData,
+    HashOptions,
+};
+
+#[test]
+fn webp_groups_with_its_png_twin() {
+    let dir = TempDir::new();
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    for name in ["python.webp", "python.png"] {
+        dir.write(name, std::fs::read(fixtures.join(name)).unwrap());
+    }
+
+    let output = run(dir.path(), &["similar", ".", "--output", "json", "--no-progress"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{stdout}{stderr}");
+    assert!(stderr.contains("Found 2 images in dataset"), "{stderr}");
+    assert!(stdout.contains(r#""count":2,"#), "{stdout}");
+    assert!(stdout.contains(r#""paths":["./python.png","./python.webp"]"#), "{stdout}");
+}
+
+#[test]
+fn similar_to_writes_its_matches_as_one_json_group() {
+    let dir = TempDir::new();
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    for name in ["python.webp", "python.png"] {
+        dir.write(name, std::fs::read(fixtures.join(name)).unwrap());
+    }
+
+    let output = run(dir.path(), &["similar-to", "python.png", ".", "--output", "json", "--no-progress"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{stdout}{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.starts_with(r#"[{"hash":"#), "{stdout}");
+    assert!(stdout.contains(r#""count":2,"#), "{stdout}");
+    assert!(stdout.contains(r#""paths":["python.png","./python.webp"]"#), "{stdout}");
+    assert_eq!(stdout.lines().count(), 1, "{stdout}");
+}
+
+#[test]
+fn images_are_decoded_with_a_single_open_file() {
+    let dir = TempDir::new();
+    let path = dir.write("photo.ppm", common::image(1));
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut file = FileData::from_file(path);
+        let options = HashOptions { perception_hash: true, max_open_files: 1, ..Default::default() };
+        sender.send(file.hash(&options).map(|()| file)).unwrap();
+    });
+
+    let file = receiver.recv_timeout(Duration::from_secs(30)).expect("hashing deadlocked").unwrap();
+    assert!(file.file_hash.is_some());
+    assert!(file.perception_hash.is_some());
+}
+
+/// Records the phases and how many files were hashed in each of them.
+#[derive(Default)]
+struct Phases(Mutex<Vec<(Phase, usize)>>);
+
+impl Progress for Phases {
+    fn on_phase(&self, phase: Phase) {
+        self.0.lock().unwrap().push((phase, 0));
+    }
+
+    fn on_file_hashed(&self, _path: &Path) {
+        self.0.lock().unwrap().last_mut().unwrap().1 += 1;
+    }
+}
+
+#[test]
+fn copies_are_verified_once_before_decoding() {
+    let dir = TempDir::new();
+    let image = common::image(1);
+    dir.write("a.ppm", &image);
+    dir.write("b.ppm", &image);
+    // Only the last pixel differs, so all three share their size and prefix hash.
+    let mut changed = image.clone();
+    *changed.last_mut().unwrap() ^= 0xff;
+    dir.write("c.ppm", changed);
+
+    let files = collect(&[dir.path()], &CollectOptions::default(), &NoProgress);
+    let phases = Phases::default();
+    let options = HashOptions { perception_hash: true, ..Default::default() };
+    let (data, errors) = hash_files(files, &options, &phases);
+    assert!(errors.is_empty(), "{errors:?}");
+
+    let phases = phases.0.into_inner().unwrap();
+    let announced: Vec<_> = phases.iter().filter(|(phase, _)| *phase != Phase::Done).collect();
+    assert_eq!(
+        announced,
+        [&(Phase::Hashing { files: 3 }, 3), &(Phase::Verifying { files: 3 }, 3), &(Phase::Decoding { images: 2 }, 2)]
+    );
+
+    for file in &data {
+        assert!(file.verified_hash.is_some(), "{}", file.path.display());
+    }
+    let hash = |name: &str| data.iter().find(|file| file.path.ends_with(name)).unwrap().verified_hash;
+    assert_eq!(hash("a.ppm"), hash("b.ppm"));
+    assert_ne!(hash("a.ppm"), hash("c.ppm"));
+
+    let files = collect(&[dir.path()], &CollectOptions::default(), &NoProgress);
+    let options = HashOptions { ignore_metadata: true, ..options };
+    let (data, _) = hash_files(files, &options, &NoProgress);
+    assert!(data.iter().all(|file| file.verified_hash.is_none()));
+}